@@ -0,0 +1,125 @@
+//! Throughput of the engine with one shard and with sharded workers on a generated file,
+//! against a baseline that applies every row under a single lock with no event log, as the
+//! engine first did.
+//!
+//! ```text
+//! cargo bench --bench sharding
+//! TX_ENGINE_BENCH_ROWS=1000000 TX_ENGINE_BENCH_SHARDS=8 cargo bench --bench sharding
+//! ```
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+use tx_engine::{
+    config::EngineConfig,
+    processor::process_csv,
+    transaction::{Transaction, TransactionRecord},
+    AppState, EngineError,
+};
+
+const CLIENTS: u32 = 10_000;
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Deposits and withdrawals with the occasional dispute and resolve, none of them rejected so
+/// the timing is not dominated by error output.
+fn generate(path: &Path, rows: usize) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "type,client,tx,amount")?;
+
+    // Every client appears once per round; the round decides the kind of transaction.
+    for index in 0..rows as u32 {
+        let (client, round, tx) = (index % CLIENTS, index / CLIENTS, index + 1);
+
+        match round % 20 {
+            // The client's deposit from the previous round, resolved in the next one.
+            7 => writeln!(writer, "dispute,{client},{},", tx - CLIENTS)?,
+            8 => writeln!(writer, "resolve,{client},{},", tx - 2 * CLIENTS)?,
+            1 | 5 | 9 | 13 => writeln!(writer, "withdrawal,{client},{tx},0.5")?,
+            _ => writeln!(writer, "deposit,{client},{tx},10.25")?,
+        }
+    }
+
+    writer.flush()
+}
+
+/// Reads and applies every row while holding the client map's lock for the whole file.
+async fn process_baseline(path: &Path, state: &AppState) -> Result<(), EngineError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .map_err(|e| EngineError::CsvFileError(e.to_string()))?;
+
+    let mut client_map = state.client_map.write().await;
+
+    for record in reader.deserialize::<TransactionRecord>() {
+        let record = record.map_err(|e| EngineError::InvalidTransaction(e.to_string()))?;
+        let transaction = Transaction::from_record(record, &state.config)?;
+
+        if let Err(e) = client_map.apply(&transaction, &state.config.disputes) {
+            eprintln!("{}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Seconds to process the file with `shards`, or on the baseline path without any.
+fn run(path: &Path, shards: Option<usize>) -> f64 {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("failed to build runtime");
+
+    let state = Arc::new(AppState::new(EngineConfig {
+        shards: shards.unwrap_or(1),
+        ..EngineConfig::default()
+    }));
+
+    let start = Instant::now();
+    runtime
+        .block_on(async {
+            match shards {
+                Some(_) => process_csv(path.to_string_lossy().into_owned(), state).await,
+                None => process_baseline(path, &state).await,
+            }
+        })
+        .expect("failed to process the benchmark file");
+
+    start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let rows = env_or("TX_ENGINE_BENCH_ROWS", 10_000_000);
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    let shards = env_or("TX_ENGINE_BENCH_SHARDS", cores);
+
+    let path = std::env::temp_dir().join(format!("tx_engine_bench_{}.csv", std::process::id()));
+    eprintln!("Generating {rows} rows in {}", path.display());
+    generate(&path, rows).expect("failed to generate the benchmark file");
+
+    let baseline = run(&path, None);
+    println!(
+        "baseline     {baseline:>8.2} s  {:>12.0} rows/s",
+        rows as f64 / baseline
+    );
+
+    for shards in [1, shards] {
+        let seconds = run(&path, Some(shards));
+        println!(
+            "shards: {shards:>3}  {seconds:>8.2} s  {:>12.0} rows/s  {:>5.2}x baseline",
+            rows as f64 / seconds,
+            baseline / seconds
+        );
+    }
+
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,135 @@
+//! Peak resident memory over a long run with a memory ceiling on the transaction history.
+//! The rows are processed as a single file, and disputes reach back to transactions that were
+//! spilled long before. Fails if the peak goes over `TX_ENGINE_BENCH_RSS_MB`.
+//!
+//! ```text
+//! cargo bench --bench spill
+//! TX_ENGINE_BENCH_ROWS=2000000 TX_ENGINE_BENCH_TX_MEMORY=8 TX_ENGINE_BENCH_RSS_MB=64 cargo bench --bench spill
+//! ```
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+use tx_engine::{
+    config::{EngineConfig, ErrorAction, ErrorPolicy},
+    processor::process_csv,
+    tx_store::{TxSpill, HOT_TX_BYTES},
+    AppState,
+};
+
+const CLIENTS: u32 = 10_000;
+
+/// How many rounds back a dispute reaches, far enough for its deposit to have been spilled.
+const DISPUTE_ROUNDS: u32 = 51;
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Rows `first..first + rows` of the run: deposits and withdrawals, with disputes and resolves
+/// of deposits made `DISPUTE_ROUNDS` rounds earlier.
+fn generate(path: &Path, first: u32, rows: u32) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "type,client,tx,amount")?;
+
+    // Every client appears once per round; the round decides the kind of transaction.
+    for index in first..first + rows {
+        let (client, round, tx) = (index % CLIENTS, index / CLIENTS, index + 1);
+
+        match round % 20 {
+            7 if round > DISPUTE_ROUNDS => writeln!(
+                writer,
+                "dispute,{client},{},",
+                tx - DISPUTE_ROUNDS * CLIENTS
+            )?,
+            8 if round > DISPUTE_ROUNDS => writeln!(
+                writer,
+                "resolve,{client},{},",
+                tx - (DISPUTE_ROUNDS + 1) * CLIENTS
+            )?,
+            1 | 5 | 9 | 13 => writeln!(writer, "withdrawal,{client},{tx},0.5")?,
+            _ => writeln!(writer, "deposit,{client},{tx},10.25")?,
+        }
+    }
+
+    writer.flush()
+}
+
+/// Peak resident set size of this process in MB, from `/proc/self/status`.
+fn peak_rss_mb() -> f64 {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<f64>()
+                .ok()
+        })
+        .map_or(0.0, |kb| kb / 1024.0)
+}
+
+fn main() {
+    let rows = env_or("TX_ENGINE_BENCH_ROWS", 20_000_000);
+    let tx_memory = env_or("TX_ENGINE_BENCH_TX_MEMORY", 64);
+    let rss_ceiling = env_or("TX_ENGINE_BENCH_RSS_MB", 256);
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    let shards = env_or("TX_ENGINE_BENCH_SHARDS", cores);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("failed to build runtime");
+
+    // Rows that fail are skipped, so the file isn't staged and its rows are let go of a batch
+    // at a time.
+    let mut state = AppState::new(EngineConfig {
+        shards,
+        error_policy: ErrorPolicy {
+            malformed: ErrorAction::Skip,
+            rejected: ErrorAction::Skip,
+        },
+        ..EngineConfig::default()
+    });
+    let hot_limit = tx_memory * 1024 * 1024 / HOT_TX_BYTES;
+    state.tx_spill =
+        Some(TxSpill::create(&std::env::temp_dir(), hot_limit).expect("failed to create spill"));
+    let state = Arc::new(state);
+
+    let path = std::env::temp_dir().join(format!("tx_engine_bench_{}.csv", std::process::id()));
+    generate(&path, 0, rows as u32).expect("failed to generate the file");
+    eprintln!("{rows} rows in one file, {tx_memory} MB of transaction history");
+
+    let start = Instant::now();
+    let result = runtime.block_on(process_csv(
+        path.to_string_lossy().into_owned(),
+        state.clone(),
+    ));
+    let elapsed = start.elapsed().as_secs_f64();
+    let _ = std::fs::remove_file(path);
+    result.expect("failed to process the benchmark file");
+
+    let peak = peak_rss_mb();
+    println!(
+        "rows: {:>11}  peak rss: {:>8.1} MB  {:>8.2} s  {:>12.0} rows/s",
+        rows,
+        peak,
+        elapsed,
+        rows as f64 / elapsed
+    );
+
+    assert!(
+        peak <= rss_ceiling as f64,
+        "peak RSS of {peak:.1} MB is over the {rss_ceiling} MB ceiling"
+    );
+}
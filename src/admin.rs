@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    event_log::{Event, Outcome},
+    transaction::TransactionType,
+    EngineError,
+};
+
+/// Whether an account accepts transactions.
+///
+/// Every change goes through [`AccountStatus::transition`]:
+///
+/// | from     | chargeback | unlock   | freeze   | unfreeze | close    |
+/// |----------|------------|----------|----------|----------|----------|
+/// | `Active` | `Locked`   | -        | `Frozen` | -        | `Closed` |
+/// | `Locked` | -          | `Active` | -        | -        | `Closed` |
+/// | `Frozen` | -          | -        | -        | `Active` | `Closed` |
+/// | `Closed` | -          | -        | -        | -        | -        |
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Locked by a chargeback until an admin unlocks it.
+    Locked,
+    /// Frozen by an admin.
+    Frozen,
+    /// Closed by an admin. Final.
+    Closed,
+}
+
+impl AccountStatus {
+    /// Fails with the matching error unless the account accepts transactions.
+    pub fn ensure_active(self) -> Result<(), EngineError> {
+        match self {
+            AccountStatus::Active => Ok(()),
+            AccountStatus::Locked => Err(EngineError::AccountLocked),
+            AccountStatus::Frozen => Err(EngineError::AccountFrozen),
+            AccountStatus::Closed => Err(EngineError::AccountClosed),
+        }
+    }
+
+    /// The status of account `client_id` after `action`.
+    pub fn transition(
+        self,
+        client_id: u16,
+        action: TransactionType,
+    ) -> Result<AccountStatus, EngineError> {
+        match (self, action) {
+            (AccountStatus::Active, TransactionType::ChargeBack) => Ok(AccountStatus::Locked),
+            (AccountStatus::Locked, TransactionType::Unlock) => Ok(AccountStatus::Active),
+            (AccountStatus::Active, TransactionType::Freeze) => Ok(AccountStatus::Frozen),
+            (AccountStatus::Frozen, TransactionType::Unfreeze) => Ok(AccountStatus::Active),
+            (
+                AccountStatus::Active | AccountStatus::Locked | AccountStatus::Frozen,
+                TransactionType::Close,
+            ) => Ok(AccountStatus::Closed),
+            (status, action) => Err(EngineError::AdminError(format!(
+                "Cannot {action} client {client_id}, the account is {status}"
+            ))),
+        }
+    }
+}
+
+impl Display for AccountStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountStatus::Active => write!(f, "active"),
+            AccountStatus::Locked => write!(f, "locked"),
+            AccountStatus::Frozen => write!(f, "frozen"),
+            AccountStatus::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+/// Why an admin operation was made and by whom.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminNote {
+    pub reason: String,
+    pub operator: String,
+}
+
+/// A row of an admin input file: `type,client,tx,reason,operator`, where `tx` is the
+/// operation's own reference.
+#[derive(Debug, Deserialize)]
+pub struct AdminRecord {
+    #[serde(rename = "type")]
+    pub tx_type: String,
+
+    #[serde(rename = "client")]
+    pub client_id: u16,
+
+    #[serde(rename = "tx")]
+    pub tx_id: u32,
+
+    #[serde(default)]
+    pub reason: String,
+
+    #[serde(default)]
+    pub operator: String,
+}
+
+impl AdminRecord {
+    /// Column names, in the order the fields of a row are read.
+    pub const COLUMNS: &'static [&'static str] = &["type", "client", "tx", "reason", "operator"];
+}
+
+/// An admin operation the engine attempted, as written to the audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub source: String,
+    pub line: u64,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub reason: String,
+    pub operator: String,
+    /// Stable identifier of the error if the operation was rejected, see
+    /// [`EngineError::code`].
+    pub code: Option<String>,
+    pub message: Option<String>,
+}
+
+impl AuditEntry {
+    /// The audit entry for `event`, if it is an admin operation.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        let note = event.transaction.note.as_deref()?;
+        let error = match &event.outcome {
+            Outcome::Accepted => None,
+            Outcome::Rejected(e) => Some(e),
+        };
+
+        Some(AuditEntry {
+            seq: event.seq,
+            source: event.source.to_string(),
+            line: event.line,
+            client: event.transaction.client_id,
+            tx: event.transaction.tx_id,
+            tx_type: event.transaction.tx_type,
+            reason: note.reason.clone(),
+            operator: note.operator.clone(),
+            code: error.map(|e| e.code().to_string()),
+            message: error.map(|e| e.to_string()),
+        })
+    }
+}
+
+/// Appends every admin operation to an audit trail file, one JSON object per line, kept
+/// across runs.
+pub struct AuditWriter {
+    writer: BufWriter<std::fs::File>,
+}
+
+fn audit_error(e: impl Display) -> EngineError {
+    EngineError::OutputError(format!("Failed to write audit trail: {}", e))
+}
+
+impl AuditWriter {
+    /// Opens the audit trail at `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, EngineError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(audit_error)?;
+
+        Ok(AuditWriter {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, entry: &AuditEntry) -> Result<(), EngineError> {
+        serde_json::to_writer(&mut self.writer, entry).map_err(audit_error)?;
+        self.writer.write_all(b"\n").map_err(audit_error)
+    }
+
+    pub fn flush(&mut self) -> Result<(), EngineError> {
+        self.writer.flush().map_err(audit_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_transitions() {
+        let statuses = [
+            AccountStatus::Active,
+            AccountStatus::Locked,
+            AccountStatus::Frozen,
+            AccountStatus::Closed,
+        ];
+        let actions = [
+            TransactionType::ChargeBack,
+            TransactionType::Unlock,
+            TransactionType::Freeze,
+            TransactionType::Unfreeze,
+            TransactionType::Close,
+        ];
+
+        for from in statuses {
+            for action in actions {
+                let expected = match (from, action) {
+                    (AccountStatus::Active, TransactionType::ChargeBack) => {
+                        Some(AccountStatus::Locked)
+                    }
+                    (AccountStatus::Locked, TransactionType::Unlock)
+                    | (AccountStatus::Frozen, TransactionType::Unfreeze) => {
+                        Some(AccountStatus::Active)
+                    }
+                    (AccountStatus::Active, TransactionType::Freeze) => Some(AccountStatus::Frozen),
+                    (AccountStatus::Closed, _) => None,
+                    (_, TransactionType::Close) => Some(AccountStatus::Closed),
+                    _ => None,
+                };
+
+                assert_eq!(
+                    from.transition(3, action).ok(),
+                    expected,
+                    "{from} + {action}"
+                );
+            }
+        }
+
+        assert_eq!(
+            AccountStatus::Active.transition(3, TransactionType::Unlock),
+            Err(EngineError::AdminError(
+                "Cannot unlock client 3, the account is active".to_string()
+            ))
+        );
+        assert_eq!(
+            AccountStatus::Closed.ensure_active(),
+            Err(EngineError::AccountClosed)
+        );
+    }
+}
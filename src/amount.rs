@@ -0,0 +1,267 @@
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::{fmt::Display, str::FromStr};
+
+use crate::EngineError;
+
+/// Number of decimal places carried by an [`Amount`].
+pub const DECIMALS: u32 = 4;
+
+const SCALE: i64 = 10_i64.pow(DECIMALS);
+
+/// How to treat amounts that carry more than [`DECIMALS`] decimal places.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Refuse amounts with excess precision.
+    #[default]
+    Reject,
+    /// Round half to even (banker's rounding).
+    HalfEven,
+    /// Round half away from zero.
+    HalfUp,
+    /// Drop the excess digits (round toward zero).
+    Truncate,
+}
+
+impl FromStr for RoundingMode {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(RoundingMode::Reject),
+            "half-even" => Ok(RoundingMode::HalfEven),
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "truncate" => Ok(RoundingMode::Truncate),
+            _ => Err(EngineError::OtherError(format!(
+                "Unknown rounding mode: {s}"
+            ))),
+        }
+    }
+}
+
+/// An exact monetary amount with four decimal places, stored as an integer number of
+/// ten-thousandths.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Builds an amount from its raw number of ten-thousandths.
+    pub const fn from_raw(raw: i64) -> Self {
+        Amount(raw)
+    }
+
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Result<Amount, EngineError> {
+        self.0
+            .checked_add(rhs.0)
+            .map(Amount)
+            .ok_or(EngineError::AmountOverflow)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Result<Amount, EngineError> {
+        self.0
+            .checked_sub(rhs.0)
+            .map(Amount)
+            .ok_or(EngineError::AmountOverflow)
+    }
+
+    /// Parses a decimal string, applying `rounding` when it has more than [`DECIMALS`]
+    /// decimal places.
+    pub fn parse(s: &str, rounding: RoundingMode) -> Result<Amount, EngineError> {
+        let invalid = || EngineError::InvalidAmount(s.to_string());
+
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+
+        if (int_part.is_empty() && frac_part.is_empty())
+            || !int_part.bytes().all(|b| b.is_ascii_digit())
+            || !frac_part.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let mut raw: i64 = 0;
+        for b in int_part.bytes() {
+            raw = raw
+                .checked_mul(10)
+                .and_then(|r| r.checked_add(i64::from(b - b'0')))
+                .ok_or(EngineError::AmountOverflow)?;
+        }
+
+        let (kept, excess) = frac_part.split_at(frac_part.len().min(DECIMALS as usize));
+        for i in 0..DECIMALS as usize {
+            let digit = kept.as_bytes().get(i).map_or(0, |b| i64::from(b - b'0'));
+            raw = raw
+                .checked_mul(10)
+                .and_then(|r| r.checked_add(digit))
+                .ok_or(EngineError::AmountOverflow)?;
+        }
+
+        if excess.bytes().any(|b| b != b'0') {
+            let first = excess.as_bytes()[0] - b'0';
+            let beyond_half = excess.bytes().skip(1).any(|b| b != b'0');
+
+            let round_up = match rounding {
+                RoundingMode::Reject => return Err(invalid()),
+                RoundingMode::Truncate => false,
+                RoundingMode::HalfUp => first >= 5,
+                RoundingMode::HalfEven => {
+                    first > 5 || (first == 5 && (beyond_half || raw % 2 == 1))
+                }
+            };
+
+            if round_up {
+                raw = raw.checked_add(1).ok_or(EngineError::AmountOverflow)?;
+            }
+        }
+
+        Ok(Amount(if negative { -raw } else { raw }))
+    }
+}
+
+impl FromStr for Amount {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s, RoundingMode::default())
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+
+        write!(f, "{sign}{}.{:04}", abs / scale, abs % scale)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AmountVisitor;
+
+        impl Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a decimal amount with at most {DECIMALS} decimal places")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() -> Result<(), EngineError> {
+        assert_eq!("1".parse::<Amount>()?, Amount::from_raw(10_000));
+        assert_eq!("0.95".parse::<Amount>()?, Amount::from_raw(9_500));
+        assert_eq!(".5".parse::<Amount>()?, Amount::from_raw(5_000));
+        assert_eq!("-2.0001".parse::<Amount>()?, Amount::from_raw(-20_001));
+        assert_eq!("1.50000".parse::<Amount>()?, Amount::from_raw(15_000));
+
+        assert_eq!(Amount::from_raw(19_500).to_string(), "1.9500");
+        assert_eq!(Amount::from_raw(-1).to_string(), "-0.0001");
+
+        for bad in ["", ".", "-", "1.2.3", "abc", "1e5", " 1"] {
+            assert_eq!(
+                bad.parse::<Amount>(),
+                Err(EngineError::InvalidAmount(bad.to_string()))
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rounding_modes() -> Result<(), EngineError> {
+        assert_eq!(
+            Amount::parse("1.00005", RoundingMode::Reject),
+            Err(EngineError::InvalidAmount("1.00005".to_string()))
+        );
+
+        assert_eq!(
+            Amount::parse("1.00005", RoundingMode::Truncate)?.raw(),
+            10_000
+        );
+        assert_eq!(
+            Amount::parse("1.00005", RoundingMode::HalfUp)?.raw(),
+            10_001
+        );
+        assert_eq!(
+            Amount::parse("1.00005", RoundingMode::HalfEven)?.raw(),
+            10_000
+        );
+        assert_eq!(
+            Amount::parse("1.00015", RoundingMode::HalfEven)?.raw(),
+            10_002
+        );
+        assert_eq!(
+            Amount::parse("1.000051", RoundingMode::HalfEven)?.raw(),
+            10_001
+        );
+        assert_eq!(
+            Amount::parse("-1.00005", RoundingMode::HalfUp)?.raw(),
+            -10_001
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow() {
+        let max = Amount::from_raw(i64::MAX);
+
+        assert_eq!(
+            max.checked_add(Amount::from_raw(1)),
+            Err(EngineError::AmountOverflow)
+        );
+        assert_eq!(
+            Amount::from_raw(i64::MIN).checked_sub(Amount::from_raw(1)),
+            Err(EngineError::AmountOverflow)
+        );
+        assert_eq!(
+            "99999999999999999999".parse::<Amount>(),
+            Err(EngineError::AmountOverflow)
+        );
+    }
+}
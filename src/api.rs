@@ -0,0 +1,361 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::net::TcpListener;
+
+use crate::{
+    admin::AccountStatus,
+    amount::Amount,
+    client::{Client, ClientSummary},
+    config::{ErrorAction, ErrorPolicy},
+    currency::Currency,
+    dispute::DisputeState,
+    input::LinesDecoder,
+    processor::{client_summary_csv, process_rows, RowOptions},
+    transaction::{TransactionRecord, TransactionType},
+    EngineError, EngineState,
+};
+
+/// Source of the transactions submitted over HTTP in the event log and reports. Requests are
+/// numbered as its lines.
+const SOURCE: &str = "http";
+
+/// Serves the engine over HTTP, with JSON bodies:
+///
+/// | Route                            | Reply                                                  |
+/// |----------------------------------|--------------------------------------------------------|
+/// | `POST /transactions`             | Applies a transaction, replies with its client         |
+/// | `GET /clients`                   | Every client, as CSV or JSON, see [`ClientsQuery`]     |
+/// | `GET /clients/{id}`              | The client's balances and status                       |
+/// | `GET /clients/{id}/transactions` | The client's deposits and withdrawals, by transaction  |
+///
+/// Errors reply with an [`ApiError`]. Must be called within the runtime.
+pub fn router(state: EngineState) -> Router {
+    let api = Arc::new(Api {
+        state,
+        requests: AtomicU64::new(0),
+    });
+
+    Router::new()
+        .route("/transactions", post(submit))
+        .route("/clients", get(clients))
+        .route("/clients/{id}", get(client))
+        .route("/clients/{id}/transactions", get(transactions))
+        .with_state(api)
+}
+
+/// Serves [`router`] on the TCP `address` until the runtime shuts down. Returns the address
+/// listened on, which has the port picked by the system if `address` has port 0.
+pub async fn listen(state: EngineState, address: &str) -> Result<String, EngineError> {
+    let listen_error = |e: std::io::Error| {
+        EngineError::OtherError(format!("Failed to listen on {}: {}", address, e))
+    };
+
+    let listener = TcpListener::bind(address).await.map_err(listen_error)?;
+    let local = listener.local_addr().map_err(listen_error)?;
+    let app = router(state);
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            eprintln!("Failed to serve HTTP: {}", e);
+        }
+    });
+
+    Ok(local.to_string())
+}
+
+struct Api {
+    state: EngineState,
+    /// Requests that submitted a transaction, to number them.
+    requests: AtomicU64,
+}
+
+/// The body of a failed request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    /// Stable identifier of the error, see [`EngineError::code`], or `not_found` and
+    /// `invalid_format`.
+    pub code: String,
+    pub message: String,
+    /// Event sequence number of a rejected transaction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(skip)]
+    status: StatusCode,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &str, message: String) -> Self {
+        ApiError {
+            code: code.to_string(),
+            message,
+            seq: None,
+            status,
+        }
+    }
+
+    fn not_found(client_id: u16) -> Self {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            format!("Client {client_id} not found"),
+        )
+    }
+
+    fn rejected(e: EngineError, seq: Option<u64>) -> Self {
+        ApiError {
+            seq,
+            ..ApiError::from(e)
+        }
+    }
+}
+
+impl From<EngineError> for ApiError {
+    fn from(e: EngineError) -> Self {
+        let status = match e {
+            EngineError::InvalidAmount(_)
+            | EngineError::InvalidCurrency(_)
+            | EngineError::InvalidTransaction(_)
+            | EngineError::CsvFileError(_) => StatusCode::BAD_REQUEST,
+            EngineError::DuplicateTransaction(_) | EngineError::TxOwnerMismatch(_) => {
+                StatusCode::CONFLICT
+            }
+            EngineError::InsufficientFunds
+            | EngineError::AmountOverflow
+            | EngineError::AccountLocked
+            | EngineError::AccountInDeficit
+            | EngineError::AccountFrozen
+            | EngineError::AccountClosed
+            | EngineError::AdminError(_)
+            | EngineError::DisputeError(_)
+            | EngineError::ResolveError(_)
+            | EngineError::ChargeBackError(_)
+            | EngineError::DisputeWindowExpired(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            EngineError::JournalError(_)
+            | EngineError::SnapshotError(_)
+            | EngineError::OutputError(_)
+            | EngineError::WalError(_)
+            | EngineError::BatchDiscarded(_)
+            | EngineError::OtherError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::new(status, e.code(), e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(&self)).into_response()
+    }
+}
+
+/// A client's balance in a currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceView {
+    pub currency: Currency,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientView {
+    pub client: u16,
+    pub status: AccountStatus,
+    pub locked: bool,
+    pub in_deficit: bool,
+    /// By currency. A client without any balance has an empty one in the default currency.
+    pub balances: Vec<BalanceView>,
+}
+
+impl ClientView {
+    pub(crate) fn new(client: &Client, default_currency: Currency) -> Self {
+        let balance = |summary: &ClientSummary| BalanceView {
+            currency: summary.get_currency(),
+            available: summary.get_available(),
+            held: summary.get_held(),
+            total: summary.get_total(),
+        };
+
+        let mut balances: Vec<BalanceView> = client.summaries().map(balance).collect();
+        if balances.is_empty() {
+            balances.push(balance(&client.summary_or_empty(default_currency)));
+        }
+
+        ClientView {
+            client: client.get_client_id(),
+            status: client.status(),
+            locked: client.is_locked(),
+            in_deficit: client.in_deficit(),
+            balances,
+        }
+    }
+}
+
+/// A deposit or withdrawal kept for later disputes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionView {
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub amount: Option<Amount>,
+    pub currency: Currency,
+    pub dispute: DisputeState,
+    pub disputed_amount: Amount,
+    pub disputed_total: Amount,
+    pub time: Option<u64>,
+    pub seq: u64,
+}
+
+/// Picks how `GET /clients` replies: `?format=csv` or `?format=json`. Without it, JSON if the
+/// request accepts `application/json`, the engine's CSV summary otherwise.
+#[derive(Debug, Default, Deserialize)]
+pub struct ClientsQuery {
+    format: Option<String>,
+}
+
+/// Applies the transaction in the body, a JSON object keyed by column name like a line of
+/// JSON Lines input, as a file of its own of a single row.
+async fn submit(State(api): State<Arc<Api>>, body: Bytes) -> Result<Json<ClientView>, ApiError> {
+    let invalid = |message: &str| {
+        ApiError::from(EngineError::InvalidTransaction(format!(
+            "The body must be a JSON object: {message}"
+        )))
+    };
+
+    let row: Value = serde_json::from_slice(&body).map_err(|e| invalid(&e.to_string()))?;
+    if !row.is_object() {
+        return Err(invalid("found another JSON value"));
+    }
+
+    let state = &api.state;
+    let line = api.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    let decoder = Box::new(LinesDecoder::new(
+        vec![(line, row.to_string())],
+        TransactionRecord::COLUMNS,
+    ));
+
+    // Aborting a file of one row is skipping it, without the error.
+    let skip_abort = |action| match action {
+        ErrorAction::Abort => ErrorAction::Skip,
+        action => action,
+    };
+    let options = RowOptions {
+        admin: false,
+        resume: false,
+        policy: ErrorPolicy {
+            malformed: skip_abort(state.config.error_policy.malformed),
+            rejected: skip_abort(state.config.error_policy.rejected),
+        },
+        atomic: false,
+        outcomes: true,
+    };
+
+    let outcome = process_rows(state, Arc::from(SOURCE), decoder, options)
+        .await?
+        .pop()
+        .ok_or_else(|| invalid("found an empty row"))?;
+    outcome
+        .result
+        .map_err(|e| ApiError::rejected(e, outcome.seq))?;
+
+    // Captured as the row was applied, before another request could change the client.
+    match outcome.client {
+        Some(client) => Ok(Json(client)),
+        None => Err(ApiError::from(EngineError::OtherError(String::from(
+            "The transaction was applied to no client",
+        )))),
+    }
+}
+
+async fn clients(
+    State(api): State<Arc<Api>>,
+    Query(query): Query<ClientsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let json = match query.format.as_deref() {
+        Some("json") => true,
+        Some("csv") => false,
+        Some(format) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_format",
+                format!("Unknown format: {format}"),
+            ))
+        }
+        None => headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json")),
+    };
+
+    if !json {
+        let csv = client_summary_csv(&api.state).await?;
+        return Ok(([(header::CONTENT_TYPE, "text/csv")], csv).into_response());
+    }
+
+    let client_map = api.state.client_map.read().await;
+    let mut clients: Vec<ClientView> = client_map
+        .values()
+        .map(|client| ClientView::new(client, api.state.config.default_currency))
+        .collect();
+    clients.sort_by_key(|client| client.client);
+
+    Ok(Json(clients).into_response())
+}
+
+async fn client(
+    State(api): State<Arc<Api>>,
+    Path(client_id): Path<u16>,
+) -> Result<Json<ClientView>, ApiError> {
+    let client_map = api.state.client_map.read().await;
+    let client = client_map
+        .get(client_id)
+        .ok_or_else(|| ApiError::not_found(client_id))?;
+
+    Ok(Json(ClientView::new(
+        client,
+        api.state.config.default_currency,
+    )))
+}
+
+async fn transactions(
+    State(api): State<Arc<Api>>,
+    Path(client_id): Path<u16>,
+) -> Result<Json<Vec<TransactionView>>, ApiError> {
+    let client_map = api.state.client_map.read().await;
+    let client = client_map
+        .get(client_id)
+        .ok_or_else(|| ApiError::not_found(client_id))?;
+
+    let mut transactions = client
+        .transactions()
+        .map(|tx| {
+            tx.map(|tx| TransactionView {
+                tx: tx.tx_id,
+                tx_type: tx.tx_type,
+                amount: tx.amount,
+                currency: tx.currency,
+                dispute: tx.dispute,
+                disputed_amount: tx.disputed_amount,
+                disputed_total: tx.disputed_total,
+                time: tx.time,
+                seq: tx.seq,
+            })
+        })
+        .collect::<Result<Vec<_>, EngineError>>()?;
+    transactions.sort_by_key(|tx| tx.tx);
+
+    Ok(Json(transactions))
+}
@@ -1,22 +1,215 @@
 use crate::{
+    admin::AccountStatus,
+    amount::Amount,
+    config::{Cutoff, DeficitMode, DisputePolicy},
+    currency::Currency,
+    dispute::{self, ensure_in_window, DisputeState},
+    journal::{Account, Journal, JournalMark},
     transaction::{Transaction, TransactionType},
+    tx_store::{TxSpill, TxStore},
     EngineError,
 };
 
 use serde::ser::{Serialize, SerializeStruct};
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    sync::Arc,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Client {
-    tx_map: HashMap<u32, Transaction>, // map: tx_id -> transaction
-    pub summary: ClientSummary,
+    client_id: u16,
+    tx_map: TxStore,                              // map: tx_id -> transaction
+    summaries: BTreeMap<Currency, ClientSummary>, // map: currency -> balances
+    journal: Journal,
 }
 
 impl Client {
     pub fn new(client_id: u16) -> Self {
         Client {
-            tx_map: HashMap::new(),
-            summary: ClientSummary::new(client_id),
+            client_id,
+            tx_map: TxStore::new(client_id),
+            summaries: BTreeMap::new(),
+            journal: Journal::default(),
+        }
+    }
+
+    pub fn get_client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    /// A chargeback in any currency locks the whole account, and admin operations apply to
+    /// the whole account as well.
+    pub fn status(&self) -> AccountStatus {
+        self.summaries
+            .values()
+            .map(|summary| summary.status)
+            .find(|status| *status != AccountStatus::Active)
+            .unwrap_or_default()
+    }
+
+    /// Whether the account refuses transactions, see [`AccountStatus`].
+    pub fn is_locked(&self) -> bool {
+        self.status() != AccountStatus::Active
+    }
+
+    /// A dispute took available below zero in some currency, see [`DeficitMode`].
+    pub fn in_deficit(&self) -> bool {
+        self.summaries.values().any(ClientSummary::in_deficit)
+    }
+
+    /// Rebuilds a client from the parts saved in a [snapshot](crate::snapshot). Its
+    /// transactions are [restored](Client::restore) one at a time.
+    pub(crate) fn from_parts(
+        client_id: u16,
+        summaries: Vec<ClientSummary>,
+        journal: Journal,
+    ) -> Self {
+        Client {
+            client_id,
+            tx_map: TxStore::new(client_id),
+            summaries: summaries
+                .into_iter()
+                .map(|summary| (summary.currency, summary))
+                .collect(),
+            journal,
+        }
+    }
+
+    /// Adds a deposit or withdrawal saved in a [snapshot](crate::snapshot), straight to
+    /// `spill` if there is one.
+    pub(crate) fn restore(
+        &mut self,
+        tx: Transaction,
+        spill: Option<&Arc<TxSpill>>,
+    ) -> Result<(), EngineError> {
+        match spill {
+            Some(spill) => self.tx_map.insert_spilled(tx, spill),
+            None => {
+                self.tx_map.insert(tx);
+                Ok(())
+            }
+        }
+    }
+
+    /// Deposits and withdrawals kept for later disputes, including spilled ones.
+    pub fn transactions(
+        &self,
+    ) -> impl Iterator<Item = Result<Cow<'_, Transaction>, EngineError>> + '_ {
+        self.tx_map.iter()
+    }
+
+    pub fn transaction(&self, tx_id: u32) -> Result<Option<Cow<'_, Transaction>>, EngineError> {
+        self.tx_map.get(tx_id)
+    }
+
+    /// Number of transactions kept in memory, see [`TxStore`].
+    pub fn hot_transactions(&self) -> usize {
+        self.tx_map.hot_len()
+    }
+
+    /// Spills up to `count` of the oldest transactions kept in memory and drops the journal
+    /// entries, keeping the balances.
+    pub fn spill(&mut self, count: usize, spill: &Arc<TxSpill>) -> Result<(), EngineError> {
+        self.journal.compact();
+        self.tx_map.spill(count, spill)
+    }
+
+    /// Drops the transactions made before `cutoff` that are not under an open dispute: they
+    /// are past the dispute window and can't be disputed any more. Returns their IDs.
+    pub fn expire(&mut self, cutoff: Cutoff) -> Result<Vec<u32>, EngineError> {
+        self.tx_map.expire(cutoff)
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Asserts that the client's journal balances and agrees with its summaries, and that
+    /// `total == available + held` in every currency.
+    pub fn verify(&self) -> Result<(), EngineError> {
+        self.journal.verify()?;
+
+        for summary in self.summaries.values() {
+            let mismatch = |what: &str| {
+                EngineError::JournalError(format!(
+                    "Client {} {} {} does not match the journal",
+                    self.client_id, summary.currency, what
+                ))
+            };
+
+            if summary.available
+                != self
+                    .journal
+                    .balance(summary.currency, Account::ClientAvailable)
+            {
+                return Err(mismatch("available"));
+            }
+
+            if summary.held != self.journal.balance(summary.currency, Account::ClientHeld) {
+                return Err(mismatch("held"));
+            }
+
+            if summary.total != summary.available.checked_add(summary.held)? {
+                return Err(EngineError::JournalError(format!(
+                    "Client {} {} total is not available + held",
+                    self.client_id, summary.currency
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn summary(&self, currency: Currency) -> Option<&ClientSummary> {
+        self.summaries.get(&currency)
+    }
+
+    /// Per-currency summaries, ordered by currency code.
+    pub fn summaries(&self) -> impl Iterator<Item = &ClientSummary> {
+        self.summaries.values()
+    }
+
+    /// The summary for `currency`, or an empty one if the client never used it.
+    pub fn summary_or_empty(&self, currency: Currency) -> ClientSummary {
+        self.summary(currency).cloned().unwrap_or_else(|| {
+            let mut summary = ClientSummary::new(self.client_id, currency);
+            summary.status = self.status();
+            summary
+        })
+    }
+
+    fn summary_mut(&mut self, currency: Currency) -> &mut ClientSummary {
+        let status = self.status();
+
+        self.summaries.entry(currency).or_insert_with(|| {
+            let mut summary = ClientSummary::new(self.client_id, currency);
+            summary.status = status;
+            summary
+        })
+    }
+
+    /// Applies `change` to a copy of the `currency` summary and keeps it only once `post` has
+    /// recorded the amount moved in the journal, so a failed post leaves the client as it was.
+    fn apply(
+        &mut self,
+        currency: Currency,
+        change: impl FnOnce(&mut ClientSummary) -> Result<Amount, EngineError>,
+        post: impl FnOnce(&mut Journal, Amount) -> Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        let mut summary = self.summary_mut(currency).clone();
+        let amount = change(&mut summary)?;
+        post(&mut self.journal, amount)?;
+        self.summaries.insert(currency, summary);
+
+        Ok(())
+    }
+
+    fn set_status(&mut self, status: AccountStatus) {
+        for summary in self.summaries.values_mut() {
+            summary.status = status;
         }
     }
 
@@ -25,7 +218,7 @@ impl Client {
         tx: &Transaction,
         expected_tx_type: TransactionType,
     ) -> Result<(), EngineError> {
-        if tx.client_id != self.summary.client_id {
+        if tx.client_id != self.client_id {
             return Err(EngineError::InvalidTransaction(format!(
                 "tx client ID mismatch {}",
                 tx.tx_id
@@ -46,104 +239,306 @@ impl Client {
         self.validate_tx(tx, TransactionType::Deposit)?;
 
         // Ensure idempotence
-        if self.tx_map.contains_key(&tx.tx_id) {
+        if self.tx_map.contains(tx.tx_id)? {
             return Err(EngineError::DuplicateTransaction(format!("{}", tx.tx_id)));
         }
 
-        self.summary.deposit(tx)?;
-        self.tx_map.insert(tx.tx_id, tx.clone());
+        self.apply(
+            tx.currency,
+            |summary| summary.deposit(tx),
+            |journal, amount| journal.record(tx.tx_id, tx.tx_type, tx.currency, amount),
+        )?;
+        self.tx_map.insert(tx.clone());
 
         Ok(())
     }
 
-    pub fn withdraw(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+    pub fn withdraw(
+        &mut self,
+        tx: &Transaction,
+        policy: &DisputePolicy,
+    ) -> Result<(), EngineError> {
         self.validate_tx(tx, TransactionType::Withdrawal)?;
 
         // Ensure idempotence
-        if self.tx_map.contains_key(&tx.tx_id) {
+        if self.tx_map.contains(tx.tx_id)? {
             return Err(EngineError::DuplicateTransaction(format!("{}", tx.tx_id)));
         }
 
-        self.summary.withdraw(tx)?;
-        self.tx_map.insert(tx.tx_id, tx.clone());
+        let frozen = policy.deficit == DeficitMode::Freeze && self.in_deficit();
+        self.apply(
+            tx.currency,
+            |summary| summary.withdraw(tx, frozen),
+            |journal, amount| journal.record(tx.tx_id, tx.tx_type, tx.currency, amount),
+        )?;
+        self.tx_map.insert(tx.clone());
 
         Ok(())
     }
 
-    pub fn dispute(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+    pub fn dispute(&mut self, tx: &Transaction, policy: &DisputePolicy) -> Result<(), EngineError> {
         self.validate_tx(tx, TransactionType::Dispute)?;
 
         // Fetch referenced transaction from client's tx map
-        if let Some(disputed_tx) = self.tx_map.get_mut(&tx.tx_id) {
-            self.summary.dispute(&disputed_tx)?;
-            disputed_tx.disputed = true;
+        if let Some(disputed_tx) = self.tx_map.get(tx.tx_id)? {
+            // Disputes apply to the currency of the referenced transaction.
+            let mut disputed_tx = disputed_tx.into_owned();
+            let currency = disputed_tx.currency;
+            ensure_in_window(&disputed_tx, tx, policy)?;
+
+            let disputed_type = disputed_tx.tx_type;
+            self.apply(
+                currency,
+                |summary| summary.dispute(&mut disputed_tx, tx.amount, policy),
+                |journal, amount| {
+                    journal.record_dispute(tx.tx_id, tx.tx_type, disputed_type, currency, amount)
+                },
+            )?;
+            self.tx_map.insert(disputed_tx);
 
             Ok(())
         } else {
             Err(EngineError::DisputeError(format!(
                 "Invalid TX ID: {} for client: {}",
-                tx.tx_id, self.summary.client_id
+                tx.tx_id, self.client_id
             )))
         }
     }
 
-    pub fn resolve(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+    pub fn resolve(&mut self, tx: &Transaction, policy: &DisputePolicy) -> Result<(), EngineError> {
         self.validate_tx(tx, TransactionType::Resolve)?;
 
         // Fetch referenced transaction from client's tx map
-        if let Some(transaction) = self.tx_map.get_mut(&tx.tx_id) {
-            self.summary.resolve(&transaction)?;
-            transaction.resolved = true;
+        if let Some(transaction) = self.tx_map.get(tx.tx_id)? {
+            let mut transaction = transaction.into_owned();
+            let currency = transaction.currency;
+            ensure_in_window(&transaction, tx, policy)?;
+
+            let disputed_type = transaction.tx_type;
+            self.apply(
+                currency,
+                |summary| summary.resolve(&mut transaction, tx.amount, policy),
+                |journal, amount| {
+                    journal.record_dispute(tx.tx_id, tx.tx_type, disputed_type, currency, amount)
+                },
+            )?;
+            self.tx_map.insert(transaction);
 
             Ok(())
         } else {
             Err(EngineError::ResolveError(format!(
                 "Invalid TX ID: {} for client: {}",
-                tx.tx_id, self.summary.client_id
+                tx.tx_id, self.client_id
             )))
         }
     }
 
-    pub fn charge_back(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+    pub fn charge_back(
+        &mut self,
+        tx: &Transaction,
+        policy: &DisputePolicy,
+    ) -> Result<(), EngineError> {
         self.validate_tx(tx, TransactionType::ChargeBack)?;
 
         // Fetch referenced transaction from client's tx map
-        if let Some(transaction) = self.tx_map.get(&tx.tx_id) {
-            self.summary.charge_back(&transaction)?;
+        if let Some(transaction) = self.tx_map.get(tx.tx_id)? {
+            let mut transaction = transaction.into_owned();
+            let currency = transaction.currency;
+            ensure_in_window(&transaction, tx, policy)?;
+
+            let disputed_type = transaction.tx_type;
+            self.apply(
+                currency,
+                |summary| summary.charge_back(&mut transaction, tx.amount, policy),
+                |journal, amount| {
+                    journal.record_dispute(tx.tx_id, tx.tx_type, disputed_type, currency, amount)
+                },
+            )?;
+            self.tx_map.insert(transaction);
+
+            // Lock the remaining currencies of the account as well.
+            self.set_status(AccountStatus::Locked);
 
             Ok(())
         } else {
             Err(EngineError::ChargeBackError(format!(
                 "Invalid TX ID: {} for client: {}",
-                tx.tx_id, self.summary.client_id
+                tx.tx_id, self.client_id
             )))
         }
     }
 }
 
+/// The changes a staged file made to a client: the summaries, and with them the status, it
+/// leaves the client with, which the client only takes once the file commits, and how to undo
+/// its changes to the transactions and the journal if the file is discarded instead.
+#[derive(Debug)]
+pub(crate) struct ClientUndo {
+    summaries: BTreeMap<Currency, ClientSummary>,
+    journal: JournalMark,
+    /// Deposits and withdrawals the file added.
+    created: Vec<u32>,
+    /// Transactions the file disputed, as they were before. map: tx_id -> transaction
+    changed: HashMap<u32, Transaction>,
+}
+
+impl Client {
+    /// Applies a transaction of any type, see [`apply_transaction`](crate::apply_transaction).
+    pub fn execute(
+        &mut self,
+        transaction: &Transaction,
+        disputes: &DisputePolicy,
+    ) -> Result<(), EngineError> {
+        match transaction.tx_type {
+            TransactionType::Deposit => self.deposit(transaction),
+            TransactionType::Withdrawal => self.withdraw(transaction, disputes),
+            TransactionType::Dispute => self.dispute(transaction, disputes),
+            TransactionType::Resolve => self.resolve(transaction, disputes),
+            TransactionType::ChargeBack => self.charge_back(transaction, disputes),
+            TransactionType::Unlock
+            | TransactionType::Freeze
+            | TransactionType::Unfreeze
+            | TransactionType::Close => self.administer(transaction),
+        }
+    }
+
+    /// Starts staging a file's changes to the client.
+    pub(crate) fn stage(&self) -> ClientUndo {
+        ClientUndo {
+            summaries: self.summaries.clone(),
+            journal: self.journal.mark(),
+            created: Vec::new(),
+            changed: HashMap::new(),
+        }
+    }
+
+    /// Applies a transaction to the staged summaries in `undo`, logging how to undo its changes
+    /// to the transactions. The client's own summaries stay as they were.
+    pub(crate) fn execute_staged(
+        &mut self,
+        undo: &mut ClientUndo,
+        transaction: &Transaction,
+        disputes: &DisputePolicy,
+    ) -> Result<(), EngineError> {
+        let tx_id = transaction.tx_id;
+        let disputed = match transaction.tx_type {
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::ChargeBack
+                if !undo.changed.contains_key(&tx_id) =>
+            {
+                self.tx_map.get(tx_id)?.map(Cow::into_owned)
+            }
+            _ => None,
+        };
+
+        std::mem::swap(&mut self.summaries, &mut undo.summaries);
+        let result = self.execute(transaction, disputes);
+        std::mem::swap(&mut self.summaries, &mut undo.summaries);
+
+        if result.is_ok() {
+            match transaction.tx_type {
+                TransactionType::Deposit | TransactionType::Withdrawal => undo.created.push(tx_id),
+                _ => {
+                    if let Some(disputed) = disputed {
+                        undo.changed.insert(tx_id, disputed);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Keeps the changes staged in `undo`.
+    pub(crate) fn commit(&mut self, undo: ClientUndo) {
+        self.summaries = undo.summaries;
+    }
+
+    /// Undoes the changes staged in `undo`, leaving the client as it was before them, whether
+    /// or not they were spilled since.
+    pub(crate) fn roll_back(&mut self, undo: ClientUndo) -> Result<(), EngineError> {
+        for (_, transaction) in undo.changed {
+            self.tx_map.insert(transaction);
+        }
+
+        self.tx_map.remove(&undo.created)?;
+        self.journal.roll_back(undo.journal);
+
+        Ok(())
+    }
+
+    /// Drops a client a staged file created, with its spilled transactions.
+    pub(crate) fn discard(mut self) -> Result<(), EngineError> {
+        self.tx_map.clear()
+    }
+}
+
+impl Client {
+    /// Applies an admin operation to the account status, see [`AccountStatus::transition`].
+    pub fn administer(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+        if tx.client_id != self.client_id {
+            return Err(EngineError::InvalidTransaction(format!(
+                "tx client ID mismatch {}",
+                tx.tx_id
+            )));
+        }
+
+        let status = self.status().transition(self.client_id, tx.tx_type)?;
+
+        // An account that never transacted keeps its status on an empty default summary.
+        self.summary_mut(tx.currency);
+        self.set_status(status);
+
+        Ok(())
+    }
+}
+
 impl Display for Client {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.summary)
+        for summary in self.summaries.values() {
+            writeln!(f, "{}", summary)?;
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientSummary {
     client_id: u16,
-    available: f64,
-    held: f64,
-    total: f64,
-    locked: bool,
+    currency: Currency,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    status: AccountStatus,
 }
 
 impl ClientSummary {
-    fn new(client_id: u16) -> Self {
+    fn new(client_id: u16, currency: Currency) -> Self {
+        ClientSummary {
+            client_id,
+            currency,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
+            status: AccountStatus::Active,
+        }
+    }
+
+    pub(crate) fn from_parts(
+        client_id: u16,
+        currency: Currency,
+        available: Amount,
+        held: Amount,
+        total: Amount,
+        status: AccountStatus,
+    ) -> Self {
         ClientSummary {
             client_id,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
-            locked: false,
+            currency,
+            available,
+            held,
+            total,
+            status,
         }
     }
 
@@ -151,10 +546,37 @@ impl ClientSummary {
         self.client_id
     }
 
-    pub fn validate_and_get_amount(&self, tx: &Transaction) -> Result<f64, EngineError> {
-        if self.locked {
-            return Err(EngineError::AccountLocked);
-        }
+    pub fn get_currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn get_available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn get_held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn get_total(&self) -> Amount {
+        self.total
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    /// Locked by a chargeback, frozen or closed.
+    pub fn is_locked(&self) -> bool {
+        self.status != AccountStatus::Active
+    }
+
+    pub fn in_deficit(&self) -> bool {
+        self.available < Amount::ZERO
+    }
+
+    pub fn validate_and_get_amount(&self, tx: &Transaction) -> Result<Amount, EngineError> {
+        self.status.ensure_active()?;
 
         if tx.amount.is_none() {
             return Err(EngineError::InvalidTransaction(format!(
@@ -165,7 +587,7 @@ impl ClientSummary {
 
         let amount = tx.amount.unwrap();
 
-        if amount <= 0.0 {
+        if !amount.is_positive() {
             return Err(EngineError::InvalidTransaction(format!(
                 "Tx ID: {} invalid amount",
                 tx.tx_id
@@ -175,106 +597,203 @@ impl ClientSummary {
         Ok(amount)
     }
 
-    fn deposit(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+    fn deposit(&mut self, tx: &Transaction) -> Result<Amount, EngineError> {
         let amount = self.validate_and_get_amount(tx)?;
 
-        self.available += amount;
-        self.total += amount;
+        let available = self.available.checked_add(amount)?;
+        let total = self.total.checked_add(amount)?;
 
-        Ok(())
+        self.available = available;
+        self.total = total;
+
+        Ok(amount)
     }
 
-    fn withdraw(&mut self, tx: &Transaction) -> Result<(), EngineError> {
+    fn withdraw(&mut self, tx: &Transaction, frozen: bool) -> Result<Amount, EngineError> {
         let amount = self.validate_and_get_amount(tx)?;
 
+        if frozen {
+            return Err(EngineError::AccountInDeficit);
+        }
+
         if self.available < amount {
             return Err(EngineError::InsufficientFunds);
         }
 
-        self.available -= amount;
-        self.total -= amount;
+        let available = self.available.checked_sub(amount)?;
+        let total = self.total.checked_sub(amount)?;
 
-        Ok(())
+        self.available = available;
+        self.total = total;
+
+        Ok(amount)
     }
 
-    fn dispute(&mut self, disputed_tx: &Transaction) -> Result<(), EngineError> {
-        let amount = self.validate_and_get_amount(disputed_tx)?;
+    /// Disputes `requested`, or all of the undisputed amount of `disputed_tx`, and returns the
+    /// amount moved to held.
+    fn dispute(
+        &mut self,
+        disputed_tx: &mut Transaction,
+        requested: Option<Amount>,
+        policy: &DisputePolicy,
+    ) -> Result<Amount, EngineError> {
+        let original = self.validate_and_get_amount(disputed_tx)?;
+
+        let withdrawal = match disputed_tx.tx_type {
+            TransactionType::Deposit => false,
+            TransactionType::Withdrawal if policy.allow_withdrawal_disputes => true,
+            _ => {
+                return Err(EngineError::DisputeError(
+                    "Attempt to dispute non deposit tx".to_string(),
+                ))
+            }
+        };
 
-        // Assuming here that only deposit transactions can be disputed
-        if disputed_tx.tx_type != TransactionType::Deposit {
-            return Err(EngineError::DisputeError(format!(
-                "Attempt to dispute non deposit tx"
-            )));
-        }
+        // A re-dispute starts over, with the resolved part disputable again.
+        let disputed_total = match disputed_tx.dispute {
+            DisputeState::Resolved if policy.allow_redispute => Amount::ZERO,
+            _ => disputed_tx.disputed_total,
+        };
+        let undisputed = original.checked_sub(disputed_total)?;
 
         // Ensure idempotence
-        if disputed_tx.disputed {
-            return Err(EngineError::DisputeError(format!(
-                "TX {} is already disputed",
-                disputed_tx.tx_id
-            )));
-        }
+        let tx_id = disputed_tx.tx_id;
+        let state = disputed_tx.dispute.transition(
+            tx_id,
+            TransactionType::Dispute,
+            undisputed > Amount::ZERO,
+            policy,
+        )?;
+
+        let amount = dispute::portion(tx_id, TransactionType::Dispute, requested, undisputed)?;
+
+        if withdrawal {
+            // The withdrawn funds are credited back to held until the dispute is settled.
+            let held = self.held.checked_add(amount)?;
+            let total = self.total.checked_add(amount)?;
+
+            self.held = held;
+            self.total = total;
+        } else {
+            // In strict mode the client must still have the disputed funds available.
+            if self.available < amount && policy.deficit == DeficitMode::Strict {
+                return Err(EngineError::InsufficientFunds);
+            }
 
-        if self.available < amount {
-            return Err(EngineError::InsufficientFunds);
+            let available = self.available.checked_sub(amount)?;
+            let held = self.held.checked_add(amount)?;
+
+            self.available = available;
+            self.held = held;
         }
 
-        self.available -= amount;
-        self.held += amount;
+        disputed_tx.dispute = state;
+        disputed_tx.disputed_amount = disputed_tx.disputed_amount.checked_add(amount)?;
+        disputed_tx.disputed_total = disputed_total.checked_add(amount)?;
 
-        Ok(())
+        Ok(amount)
     }
 
-    fn resolve(&mut self, disputed_tx: &Transaction) -> Result<(), EngineError> {
-        let amount = self.validate_and_get_amount(disputed_tx)?;
+    /// Resolves `requested`, or all of the disputed amount of `disputed_tx`, and returns the
+    /// amount released from held.
+    fn resolve(
+        &mut self,
+        disputed_tx: &mut Transaction,
+        requested: Option<Amount>,
+        policy: &DisputePolicy,
+    ) -> Result<Amount, EngineError> {
+        self.validate_and_get_amount(disputed_tx)?;
+
+        let tx_id = disputed_tx.tx_id;
+        let state =
+            disputed_tx
+                .dispute
+                .transition(tx_id, TransactionType::Resolve, false, policy)?;
+
+        let amount = dispute::portion(
+            tx_id,
+            TransactionType::Resolve,
+            requested,
+            disputed_tx.disputed_amount,
+        )?;
+
+        let held = self.held.checked_sub(amount)?;
+
+        if disputed_tx.tx_type == TransactionType::Withdrawal {
+            // The withdrawal stands, so the held credit is taken back.
+            let total = self.total.checked_sub(amount)?;
+
+            self.held = held;
+            self.total = total;
+        } else {
+            let available = self.available.checked_add(amount)?;
 
-        // Only resolve transactions that were previously disputed.
-        if !disputed_tx.disputed {
-            return Err(EngineError::ResolveError(format!(
-                "TX {} is undisputed",
-                disputed_tx.tx_id
-            )));
+            self.available = available;
+            self.held = held;
         }
 
-        // Ensure idempotence
-        if disputed_tx.resolved {
-            return Err(EngineError::ResolveError(format!(
-                "TX {} is already resolved",
-                disputed_tx.tx_id
-            )));
+        disputed_tx.disputed_amount = disputed_tx.disputed_amount.checked_sub(amount)?;
+        // The dispute stays open until all of it is resolved.
+        if disputed_tx.disputed_amount == Amount::ZERO {
+            disputed_tx.dispute = state;
         }
 
-        self.available += amount;
-        self.held -= amount;
-
-        Ok(())
+        Ok(amount)
     }
 
-    fn charge_back(&mut self, disputed_tx: &Transaction) -> Result<(), EngineError> {
-        let amount = self.validate_and_get_amount(disputed_tx)?;
-
-        // Only chargeback transactions that were previously disputed.
-        if !disputed_tx.disputed {
+    /// Charges back all of the disputed amount of `disputed_tx` and returns the amount reversed.
+    /// A `requested` amount must be all of it: the chargeback locks the account, so nothing
+    /// left held could be settled later.
+    fn charge_back(
+        &mut self,
+        disputed_tx: &mut Transaction,
+        requested: Option<Amount>,
+        policy: &DisputePolicy,
+    ) -> Result<Amount, EngineError> {
+        self.validate_and_get_amount(disputed_tx)?;
+
+        let tx_id = disputed_tx.tx_id;
+        let state =
+            disputed_tx
+                .dispute
+                .transition(tx_id, TransactionType::ChargeBack, false, policy)?;
+
+        let amount = dispute::portion(
+            tx_id,
+            TransactionType::ChargeBack,
+            requested,
+            disputed_tx.disputed_amount,
+        )?;
+        if amount != disputed_tx.disputed_amount {
             return Err(EngineError::ChargeBackError(format!(
-                "TX {} is undisputed",
-                disputed_tx.tx_id
+                "Amount {amount} must cover the disputed {} of TX {tx_id}",
+                disputed_tx.disputed_amount
             )));
         }
 
-        // Do not chargeback transactions that are already resolved.
-        if disputed_tx.resolved {
-            return Err(EngineError::ChargeBackError(format!(
-                "TX {} is already resolved",
-                disputed_tx.tx_id
-            )));
+        let held = self.held.checked_sub(amount)?;
+
+        if disputed_tx.tx_type == TransactionType::Withdrawal {
+            // The withdrawal is reversed and the funds returned to the client.
+            let available = self.available.checked_add(amount)?;
+
+            self.available = available;
+            self.held = held;
+        } else {
+            let total = self.total.checked_sub(amount)?;
+
+            self.total = total;
+            self.held = held;
         }
 
-        self.total -= amount;
-        self.held -= amount;
+        self.status = self
+            .status
+            .transition(self.client_id, TransactionType::ChargeBack)?;
 
-        self.locked = true;
+        disputed_tx.dispute = state;
+        disputed_tx.disputed_amount = Amount::ZERO;
 
-        Ok(())
+        Ok(amount)
     }
 }
 
@@ -282,8 +801,13 @@ impl Display for ClientSummary {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Client ID: {}, Available: {}, Held: {}, Total: {}, locked: {}",
-            self.client_id, self.available, self.held, self.total, self.locked
+            "Client ID: {}, Currency: {}, Available: {}, Held: {}, Total: {}, locked: {}",
+            self.client_id,
+            self.currency,
+            self.available,
+            self.held,
+            self.total,
+            self.is_locked()
         )
     }
 }
@@ -296,10 +820,31 @@ impl Serialize for ClientSummary {
         // 5 is the number of fields in the struct.
         let mut state = serializer.serialize_struct("ClientSummary", 5)?;
         state.serialize_field("client", &self.client_id)?;
-        state.serialize_field(" available", &format!(" {:.4}", &self.available))?;
-        state.serialize_field(" held", &format!(" {:.4}", &self.held))?;
-        state.serialize_field(" total", &format!(" {:.4}", &self.total))?;
-        state.serialize_field(" locked", &format!(" {}", &self.locked))?;
+        state.serialize_field(" available", &format!(" {}", &self.available))?;
+        state.serialize_field(" held", &format!(" {}", &self.held))?;
+        state.serialize_field(" total", &format!(" {}", &self.total))?;
+        state.serialize_field(" locked", &format!(" {}", self.is_locked()))?;
+        state.end()
+    }
+}
+
+/// Serializes a [`ClientSummary`] with an extra `currency` column, for per-currency output.
+pub struct CurrencySummary<'a>(pub &'a ClientSummary);
+
+impl Serialize for CurrencySummary<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let summary = self.0;
+
+        let mut state = serializer.serialize_struct("CurrencySummary", 6)?;
+        state.serialize_field("client", &summary.client_id)?;
+        state.serialize_field(" currency", &format!(" {}", &summary.currency))?;
+        state.serialize_field(" available", &format!(" {}", &summary.available))?;
+        state.serialize_field(" held", &format!(" {}", &summary.held))?;
+        state.serialize_field(" total", &format!(" {}", &summary.total))?;
+        state.serialize_field(" locked", &format!(" {}", summary.is_locked()))?;
         state.end()
     }
 }
@@ -307,21 +852,15 @@ impl Serialize for ClientSummary {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{admin::AccountStatus, dispute::DisputeState, fixtures::tx};
 
     #[test]
     fn test_mismatch_tx_id() -> Result<(), EngineError> {
         let mut client = Client::new(1);
 
-        let transaction = Transaction {
-            tx_id: 1,
-            client_id: 2,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let transaction = tx(TransactionType::Deposit, 2, 1, Some("1.0"));
 
-        if let Err(_) = client.deposit(&transaction) {
+        if client.deposit(&transaction).is_err() {
             Ok(())
         } else {
             Err(EngineError::OtherError(
@@ -334,14 +873,7 @@ mod tests {
     fn test_duplicate() -> Result<(), EngineError> {
         let mut client = Client::new(1);
 
-        let tx = Transaction {
-            tx_id: 1,
-            client_id: 1,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let tx = tx(TransactionType::Deposit, 1, 1, Some("1.0"));
 
         client.deposit(&tx)?;
 
@@ -359,31 +891,17 @@ mod tests {
     fn test_insuffiecient_funds() -> Result<(), EngineError> {
         let mut client = Client::new(1);
 
-        let deposit_tx = Transaction {
-            tx_id: 1,
-            client_id: 1,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let deposit_tx = tx(TransactionType::Deposit, 1, 1, Some("1.0"));
 
-        let mut withdraw_tx = Transaction {
-            tx_id: 2,
-            client_id: 1,
-            tx_type: TransactionType::Withdrawal,
-            amount: Some(2.0),
-            disputed: false,
-            resolved: false,
-        };
+        let mut withdraw_tx = tx(TransactionType::Withdrawal, 1, 2, Some("2.0"));
 
         client.deposit(&deposit_tx)?;
 
-        let result = client.withdraw(&withdraw_tx);
+        let result = client.withdraw(&withdraw_tx, &DisputePolicy::default());
         assert_eq!(result, Err(EngineError::InsufficientFunds));
 
-        withdraw_tx.amount = Some(1.0);
-        client.withdraw(&withdraw_tx)?;
+        withdraw_tx.amount = Some("1.0".parse()?);
+        client.withdraw(&withdraw_tx, &DisputePolicy::default())?;
 
         Ok(())
     }
@@ -392,34 +910,32 @@ mod tests {
     fn test_dispute() -> Result<(), EngineError> {
         let mut client = Client::new(1);
 
-        let mut deposit_tx = Transaction {
-            tx_id: 1,
-            client_id: 1,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let mut deposit_tx = tx(TransactionType::Deposit, 1, 1, Some("1.0"));
 
-        let mut dispute_tx = Transaction {
-            tx_id: 1,
-            client_id: 1,
-            tx_type: TransactionType::Dispute,
-            amount: None,
-            disputed: false,
-            resolved: false,
-        };
+        let mut dispute_tx = tx(TransactionType::Dispute, 1, 1, None);
 
         client.deposit(&deposit_tx)?;
-        client.dispute(&dispute_tx)?;
+        client.dispute(&dispute_tx, &DisputePolicy::default())?;
 
-        assert_eq!(client.summary.available, 0.0);
-        assert_eq!(client.summary.held, 1.0);
-        assert_eq!(client.summary.total, 1.0);
-        assert_eq!(client.summary.locked, false);
-        assert_eq!(client.tx_map.get(&1).unwrap().disputed, true);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "0.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "1.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "1.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Active
+        );
+        assert_eq!(client.transaction(1)?.unwrap().dispute, DisputeState::Open);
 
-        let result = client.dispute(&dispute_tx);
+        let result = client.dispute(&dispute_tx, &DisputePolicy::default());
 
         assert_eq!(
             result,
@@ -432,29 +948,34 @@ mod tests {
         deposit_tx.tx_id = 2;
         dispute_tx.tx_id = 2;
 
-        let withdraw_tx = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::Withdrawal,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let withdraw_tx = tx(TransactionType::Withdrawal, 1, 3, Some("1.0"));
 
         client.deposit(&deposit_tx)?;
-        client.withdraw(&withdraw_tx)?;
+        client.withdraw(&withdraw_tx, &DisputePolicy::default())?;
 
         // What happens in this scenario?
         // When a dispute happens but the funds have already been withdrawn, should the account
         // be locked?
 
-        let result = client.dispute(&dispute_tx);
+        let result = client.dispute(&dispute_tx, &DisputePolicy::default());
 
-        assert_eq!(client.summary.available, 0.0);
-        assert_eq!(client.summary.held, 1.0);
-        assert_eq!(client.summary.total, 1.0);
-        assert_eq!(client.summary.locked, false);
-        assert_eq!(client.tx_map.get(&2).unwrap().disputed, false);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "0.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "1.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "1.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Active
+        );
+        assert_eq!(client.transaction(2)?.unwrap().dispute, DisputeState::None);
 
         assert_eq!(result, Err(EngineError::InsufficientFunds));
 
@@ -465,79 +986,81 @@ mod tests {
     fn test_resolve() -> Result<(), EngineError> {
         let mut client = Client::new(1);
 
-        let deposit_tx = Transaction {
-            tx_id: 1,
-            client_id: 1,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let deposit_tx = tx(TransactionType::Deposit, 1, 1, Some("1.0"));
 
-        let withdraw_tx = Transaction {
-            tx_id: 2,
-            client_id: 1,
-            tx_type: TransactionType::Withdrawal,
-            amount: Some(0.05),
-            disputed: false,
-            resolved: false,
-        };
+        let withdraw_tx = tx(TransactionType::Withdrawal, 1, 2, Some("0.05"));
 
-        let deposit_tx2 = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let deposit_tx2 = tx(TransactionType::Deposit, 1, 3, Some("1.0"));
 
-        let dispute_tx = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::Dispute,
-            amount: None,
-            disputed: false,
-            resolved: false,
-        };
+        let dispute_tx = tx(TransactionType::Dispute, 1, 3, None);
 
-        let mut resolve_tx = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::Resolve,
-            amount: None,
-            disputed: false,
-            resolved: false,
-        };
+        let mut resolve_tx = tx(TransactionType::Resolve, 1, 3, None);
 
         client.deposit(&deposit_tx)?;
-        client.withdraw(&withdraw_tx)?;
+        client.withdraw(&withdraw_tx, &DisputePolicy::default())?;
 
-        assert_eq!(client.summary.available, 0.95);
-        assert_eq!(client.summary.held, 0.0);
-        assert_eq!(client.summary.total, 0.950);
-        assert_eq!(client.summary.locked, false);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "0.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "0.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "0.950".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Active
+        );
 
         client.deposit(&deposit_tx2)?;
-        client.dispute(&dispute_tx)?;
+        client.dispute(&dispute_tx, &DisputePolicy::default())?;
 
-        assert_eq!(client.summary.available, 0.95);
-        assert_eq!(client.summary.held, 1.0);
-        assert_eq!(client.summary.total, 1.95);
-        assert_eq!(client.summary.locked, false);
-        assert_eq!(client.tx_map.get(&3).unwrap().disputed, true);
-        assert_eq!(client.tx_map.get(&3).unwrap().resolved, false);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "0.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "1.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "1.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Active
+        );
+        assert_eq!(client.transaction(3)?.unwrap().dispute, DisputeState::Open);
 
-        client.resolve(&resolve_tx)?;
+        client.resolve(&resolve_tx, &DisputePolicy::default())?;
 
-        assert_eq!(client.summary.available, 1.95);
-        assert_eq!(client.summary.held, 0.0);
-        assert_eq!(client.summary.total, 1.95);
-        assert_eq!(client.summary.locked, false);
-        assert_eq!(client.tx_map.get(&3).unwrap().disputed, true);
-        assert_eq!(client.tx_map.get(&3).unwrap().resolved, true);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "1.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "0.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "1.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Active
+        );
+        assert_eq!(
+            client.transaction(3)?.unwrap().dispute,
+            DisputeState::Resolved
+        );
 
-        let result = client.resolve(&resolve_tx);
+        let result = client.resolve(&resolve_tx, &DisputePolicy::default());
 
         assert_eq!(
             result,
@@ -548,7 +1071,7 @@ mod tests {
         );
 
         resolve_tx.tx_id = 1;
-        let result = client.resolve(&resolve_tx);
+        let result = client.resolve(&resolve_tx, &DisputePolicy::default());
 
         assert_eq!(
             result,
@@ -558,8 +1081,7 @@ mod tests {
             )))
         );
 
-        assert_eq!(client.tx_map.get(&1).unwrap().disputed, false);
-        assert_eq!(client.tx_map.get(&1).unwrap().resolved, false);
+        assert_eq!(client.transaction(1)?.unwrap().dispute, DisputeState::None);
 
         Ok(())
     }
@@ -568,83 +1090,66 @@ mod tests {
     fn test_chargeback() -> Result<(), EngineError> {
         let mut client = Client::new(1);
 
-        let deposit_tx = Transaction {
-            tx_id: 1,
-            client_id: 1,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let deposit_tx = tx(TransactionType::Deposit, 1, 1, Some("1.0"));
 
-        let withdraw_tx = Transaction {
-            tx_id: 2,
-            client_id: 1,
-            tx_type: TransactionType::Withdrawal,
-            amount: Some(0.05),
-            disputed: false,
-            resolved: false,
-        };
+        let withdraw_tx = tx(TransactionType::Withdrawal, 1, 2, Some("0.05"));
 
-        let deposit_tx2 = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::Deposit,
-            amount: Some(1.0),
-            disputed: false,
-            resolved: false,
-        };
+        let deposit_tx2 = tx(TransactionType::Deposit, 1, 3, Some("1.0"));
 
-        let mut dispute_tx = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::Dispute,
-            amount: None,
-            disputed: false,
-            resolved: false,
-        };
+        let mut dispute_tx = tx(TransactionType::Dispute, 1, 3, None);
 
-        let resolve_tx = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::Resolve,
-            amount: None,
-            disputed: false,
-            resolved: false,
-        };
+        let resolve_tx = tx(TransactionType::Resolve, 1, 3, None);
 
-        let mut chargeback_tx = Transaction {
-            tx_id: 3,
-            client_id: 1,
-            tx_type: TransactionType::ChargeBack,
-            amount: None,
-            disputed: false,
-            resolved: false,
-        };
+        let mut chargeback_tx = tx(TransactionType::ChargeBack, 1, 3, None);
 
         client.deposit(&deposit_tx)?;
-        client.withdraw(&withdraw_tx)?;
+        client.withdraw(&withdraw_tx, &DisputePolicy::default())?;
 
         client.deposit(&deposit_tx2)?;
-        client.dispute(&dispute_tx)?;
+        client.dispute(&dispute_tx, &DisputePolicy::default())?;
 
-        assert_eq!(client.summary.available, 0.95);
-        assert_eq!(client.summary.held, 1.0);
-        assert_eq!(client.summary.total, 1.95);
-        assert_eq!(client.summary.locked, false);
-        assert_eq!(client.tx_map.get(&3).unwrap().disputed, true);
-        assert_eq!(client.tx_map.get(&3).unwrap().resolved, false);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "0.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "1.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "1.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Active
+        );
+        assert_eq!(client.transaction(3)?.unwrap().dispute, DisputeState::Open);
 
-        client.resolve(&resolve_tx)?;
+        client.resolve(&resolve_tx, &DisputePolicy::default())?;
 
-        assert_eq!(client.summary.available, 1.95);
-        assert_eq!(client.summary.held, 0.0);
-        assert_eq!(client.summary.total, 1.95);
-        assert_eq!(client.summary.locked, false);
-        assert_eq!(client.tx_map.get(&3).unwrap().disputed, true);
-        assert_eq!(client.tx_map.get(&3).unwrap().resolved, true);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "1.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "0.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "1.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Active
+        );
+        assert_eq!(
+            client.transaction(3)?.unwrap().dispute,
+            DisputeState::Resolved
+        );
 
-        let result = client.charge_back(&chargeback_tx);
+        let result = client.charge_back(&chargeback_tx, &DisputePolicy::default());
 
         assert_eq!(
             result,
@@ -655,7 +1160,7 @@ mod tests {
         );
 
         chargeback_tx.tx_id = 1;
-        let result = client.charge_back(&chargeback_tx);
+        let result = client.charge_back(&chargeback_tx, &DisputePolicy::default());
 
         assert_eq!(
             result,
@@ -666,17 +1171,525 @@ mod tests {
         );
 
         dispute_tx.tx_id = 1;
-        client.dispute(&dispute_tx)?;
-        client.charge_back(&chargeback_tx)?;
-
-        assert_eq!(client.summary.available, 0.95);
-        assert_eq!(client.summary.held, 0.0);
-        assert_eq!(client.summary.total, 0.95);
-        assert_eq!(client.tx_map.get(&3).unwrap().disputed, true);
-        assert_eq!(client.tx_map.get(&3).unwrap().resolved, true);
-        assert_eq!(client.tx_map.get(&1).unwrap().disputed, true);
-        assert_eq!(client.tx_map.get(&1).unwrap().resolved, false);
-        assert_eq!(client.summary.locked, true);
+        client.dispute(&dispute_tx, &DisputePolicy::default())?;
+        client.charge_back(&chargeback_tx, &DisputePolicy::default())?;
+
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).available,
+            "0.95".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "0.0".parse()?
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).total,
+            "0.95".parse()?
+        );
+        assert_eq!(
+            client.transaction(3)?.unwrap().dispute,
+            DisputeState::Resolved
+        );
+        assert_eq!(
+            client.transaction(1)?.unwrap().dispute,
+            DisputeState::ChargedBack
+        );
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).status(),
+            AccountStatus::Locked
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redispute() -> Result<(), EngineError> {
+        let mut client = Client::new(1);
+        let dispute_tx = tx(TransactionType::Dispute, 1, 1, None);
+
+        client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("1.0")))?;
+        client.dispute(&dispute_tx, &DisputePolicy::default())?;
+        client.resolve(
+            &tx(TransactionType::Resolve, 1, 1, None),
+            &DisputePolicy::default(),
+        )?;
+
+        // A resolved transaction can only be disputed again if the policy allows it.
+        assert_eq!(
+            client.dispute(&dispute_tx, &DisputePolicy::default()),
+            Err(EngineError::DisputeError(
+                "TX 1 is already resolved".to_string()
+            ))
+        );
+
+        let redispute = DisputePolicy {
+            allow_redispute: true,
+            ..DisputePolicy::default()
+        };
+        client.dispute(&dispute_tx, &redispute)?;
+
+        assert_eq!(client.transaction(1)?.unwrap().dispute, DisputeState::Open);
+        assert_eq!(
+            client.summary_or_empty(Currency::default()).held,
+            "1.0".parse()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal() -> Result<(), EngineError> {
+        let policy = DisputePolicy::default();
+        let mut client = Client::new(1);
+
+        client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("1.0")))?;
+        client.withdraw(
+            &tx(TransactionType::Withdrawal, 1, 2, Some("0.05")),
+            &policy,
+        )?;
+        client.deposit(&tx(TransactionType::Deposit, 1, 3, Some("1.0")))?;
+        client.dispute(&tx(TransactionType::Dispute, 1, 3, None), &policy)?;
+        client.resolve(&tx(TransactionType::Resolve, 1, 3, None), &policy)?;
+        client.dispute(&tx(TransactionType::Dispute, 1, 1, None), &policy)?;
+        client.charge_back(&tx(TransactionType::ChargeBack, 1, 1, None), &policy)?;
+
+        // An entry per applied transaction, balancing the client's summary.
+        assert_eq!(client.journal().entries().len(), 7);
+        assert_eq!(
+            client
+                .journal()
+                .balance(Currency::default(), Account::ClientAvailable),
+            "0.95".parse()?
+        );
+
+        client.verify()
+    }
+
+    #[test]
+    fn test_multi_currency() -> Result<(), EngineError> {
+        let mut client = Client::new(1);
+        let usd = Currency::default();
+        let eur: Currency = "EUR".parse()?;
+
+        client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("2.0")))?;
+        client.deposit(&Transaction {
+            currency: eur,
+            ..tx(TransactionType::Deposit, 1, 2, Some("5.0"))
+        })?;
+
+        // Only 2.0 USD is available even though the client holds 5.0 EUR.
+        assert_eq!(
+            client.withdraw(
+                &tx(TransactionType::Withdrawal, 1, 3, Some("3.0")),
+                &DisputePolicy::default()
+            ),
+            Err(EngineError::InsufficientFunds)
+        );
+
+        // The dispute row carries the default currency but applies to the EUR deposit.
+        let dispute_tx = tx(TransactionType::Dispute, 1, 2, None);
+
+        client.dispute(&dispute_tx, &DisputePolicy::default())?;
+
+        assert_eq!(client.summary_or_empty(eur).available, Amount::ZERO);
+        assert_eq!(client.summary_or_empty(eur).held, "5.0".parse()?);
+        assert_eq!(client.summary_or_empty(usd).available, "2.0".parse()?);
+        assert_eq!(client.summary_or_empty(usd).held, Amount::ZERO);
+
+        let chargeback_tx = Transaction {
+            tx_type: TransactionType::ChargeBack,
+            ..dispute_tx
+        };
+
+        client.charge_back(&chargeback_tx, &DisputePolicy::default())?;
+
+        assert_eq!(client.summary_or_empty(eur).total, Amount::ZERO);
+        assert_eq!(client.summary_or_empty(usd).total, "2.0".parse()?);
+        assert!(client.is_locked());
+        assert!(client.summaries().all(|summary| summary.is_locked()));
+        assert!(client.summary_or_empty("GBP".parse()?).is_locked());
+
+        client.verify()
+    }
+
+    #[test]
+    fn test_deficit() -> Result<(), EngineError> {
+        let usd = Currency::default();
+        let eur: Currency = "EUR".parse()?;
+
+        for deficit in [DeficitMode::Allow, DeficitMode::Freeze] {
+            let policy = DisputePolicy {
+                deficit,
+                ..DisputePolicy::default()
+            };
+            let mut client = Client::new(1);
+
+            // The disputed deposit has already been withdrawn.
+            client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("1.0")))?;
+            client.deposit(&Transaction {
+                currency: eur,
+                ..tx(TransactionType::Deposit, 1, 2, Some("5.0"))
+            })?;
+            client.withdraw(&tx(TransactionType::Withdrawal, 1, 3, Some("1.0")), &policy)?;
+            client.dispute(&tx(TransactionType::Dispute, 1, 1, None), &policy)?;
+
+            assert_eq!(client.summary_or_empty(usd).available, "-1.0".parse()?);
+            assert_eq!(client.summary_or_empty(usd).held, "1.0".parse()?);
+            assert_eq!(client.summary_or_empty(usd).total, Amount::ZERO);
+            assert!(client.in_deficit());
+            assert!(!client.summary_or_empty(eur).in_deficit());
+
+            let withdrawal = client.withdraw(
+                &Transaction {
+                    currency: eur,
+                    ..tx(TransactionType::Withdrawal, 1, 4, Some("1.0"))
+                },
+                &policy,
+            );
+            match deficit {
+                DeficitMode::Freeze => {
+                    assert_eq!(withdrawal, Err(EngineError::AccountInDeficit))
+                }
+                _ => assert_eq!(withdrawal, Ok(())),
+            }
+
+            // A deposit covering the deficit ends it.
+            client.deposit(&tx(TransactionType::Deposit, 1, 5, Some("2.0")))?;
+            assert!(!client.in_deficit());
+            client.withdraw(
+                &Transaction {
+                    currency: eur,
+                    ..tx(TransactionType::Withdrawal, 1, 6, Some("1.0"))
+                },
+                &policy,
+            )?;
+
+            client.verify()?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_admin_operations() -> Result<(), EngineError> {
+        let usd = Currency::default();
+        let policy = DisputePolicy::default();
+
+        let mut client = Client::new(1);
+        client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("3.0")))?;
+        client.deposit(&tx(TransactionType::Deposit, 1, 2, Some("1.0")))?;
+        client.dispute(&tx(TransactionType::Dispute, 1, 2, None), &policy)?;
+        client.charge_back(&tx(TransactionType::ChargeBack, 1, 2, None), &policy)?;
+
+        assert_eq!(client.status(), AccountStatus::Locked);
+        assert_eq!(
+            client.deposit(&tx(TransactionType::Deposit, 1, 3, Some("1.0"))),
+            Err(EngineError::AccountLocked)
+        );
+
+        // Unlocking lets transactions through again.
+        client.administer(&tx(TransactionType::Unlock, 1, 100, None))?;
+        assert!(!client.is_locked());
+        client.deposit(&tx(TransactionType::Deposit, 1, 3, Some("1.0")))?;
+
+        client.administer(&tx(TransactionType::Freeze, 1, 101, None))?;
+        assert_eq!(
+            client.withdraw(&tx(TransactionType::Withdrawal, 1, 4, Some("1.0")), &policy),
+            Err(EngineError::AccountFrozen)
+        );
+        assert_eq!(
+            client.administer(&tx(TransactionType::Unlock, 1, 102, None)),
+            Err(EngineError::AdminError(
+                "Cannot unlock client 1, the account is frozen".to_string()
+            ))
+        );
+
+        client.administer(&tx(TransactionType::Close, 1, 103, None))?;
+        assert_eq!(
+            client.deposit(&tx(TransactionType::Deposit, 1, 5, Some("1.0"))),
+            Err(EngineError::AccountClosed)
+        );
+        assert!(client.summary_or_empty(usd).is_locked());
+
+        // A client that never transacted still keeps its status.
+        let mut idle = Client::new(2);
+        idle.administer(&Transaction {
+            client_id: 2,
+            ..tx(TransactionType::Freeze, 1, 104, None)
+        })?;
+        assert_eq!(idle.status(), AccountStatus::Frozen);
+
+        client.verify()
+    }
+
+    #[test]
+    fn test_partial_disputes() -> Result<(), EngineError> {
+        let usd = Currency::default();
+        let policy = DisputePolicy::default();
+        let mut client = Client::new(1);
+        let dispute = |amount| tx(TransactionType::Dispute, 1, 1, amount);
+
+        client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("10.0")))?;
+
+        // Concurrent partial disputes add up, but never beyond the deposit.
+        client.dispute(&dispute(Some("3.0")), &policy)?;
+        client.dispute(&dispute(Some("4.0")), &policy)?;
+        assert_eq!(
+            client.transaction(1)?.unwrap().disputed_amount,
+            "7.0".parse()?
+        );
+
+        assert_eq!(
+            client.dispute(&dispute(Some("4.0")), &policy),
+            Err(EngineError::DisputeError(
+                "Amount 4.0000 exceeds the undisputed 3.0000 of TX 1".to_string()
+            ))
+        );
+        assert_eq!(
+            client.dispute(&dispute(Some("0.0")), &policy),
+            Err(EngineError::InvalidTransaction(
+                "Tx ID: 1 invalid amount".to_string()
+            ))
+        );
+
+        // Without an amount, the rest is disputed.
+        client.dispute(&dispute(None), &policy)?;
+        assert_eq!(client.summary_or_empty(usd).held, "10.0".parse()?);
+        assert_eq!(
+            client.dispute(&dispute(None), &policy),
+            Err(EngineError::DisputeError(
+                "TX 1 is already disputed".to_string()
+            ))
+        );
+
+        // A partial resolve leaves the dispute open.
+        client.resolve(&tx(TransactionType::Resolve, 1, 1, Some("2.0")), &policy)?;
+        assert_eq!(client.transaction(1)?.unwrap().dispute, DisputeState::Open);
+        assert_eq!(
+            client.transaction(1)?.unwrap().disputed_amount,
+            "8.0".parse()?
+        );
+        assert_eq!(client.summary_or_empty(usd).available, "2.0".parse()?);
+
+        assert_eq!(
+            client.charge_back(&tx(TransactionType::ChargeBack, 1, 1, Some("9.0")), &policy),
+            Err(EngineError::ChargeBackError(
+                "Amount 9.0000 exceeds the disputed 8.0000 of TX 1".to_string()
+            ))
+        );
+
+        // A chargeback locks the account, so it must take all of the disputed amount.
+        assert_eq!(
+            client.charge_back(&tx(TransactionType::ChargeBack, 1, 1, Some("5.0")), &policy),
+            Err(EngineError::ChargeBackError(
+                "Amount 5.0000 must cover the disputed 8.0000 of TX 1".to_string()
+            ))
+        );
+
+        // The funds still held can be resolved...
+        let mut resolved = client.clone();
+        resolved.resolve(&tx(TransactionType::Resolve, 1, 1, None), &policy)?;
+        assert_eq!(
+            resolved.transaction(1)?.unwrap().dispute,
+            DisputeState::Resolved
+        );
+        assert_eq!(resolved.summary_or_empty(usd).available, "10.0".parse()?);
+        assert_eq!(resolved.summary_or_empty(usd).held, Amount::ZERO);
+        resolved.verify()?;
+
+        // ...or charged back, with nothing left held.
+        client.charge_back(&tx(TransactionType::ChargeBack, 1, 1, Some("8.0")), &policy)?;
+        assert_eq!(
+            client.transaction(1)?.unwrap().dispute,
+            DisputeState::ChargedBack
+        );
+        assert_eq!(
+            client.transaction(1)?.unwrap().disputed_amount,
+            Amount::ZERO
+        );
+
+        let summary = client.summary_or_empty(usd);
+        assert_eq!(summary.available, "2.0".parse()?);
+        assert_eq!(summary.held, Amount::ZERO);
+        assert_eq!(summary.total, "2.0".parse()?);
+        assert!(summary.is_locked());
+        client.verify()?;
+
+        // Once a partial dispute is resolved, the never disputed part can still be disputed.
+        let mut client = Client::new(1);
+        client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("5.0")))?;
+        client.dispute(&dispute(Some("2.0")), &policy)?;
+        client.resolve(&tx(TransactionType::Resolve, 1, 1, None), &policy)?;
+        assert_eq!(
+            client.transaction(1)?.unwrap().dispute,
+            DisputeState::Resolved
+        );
+
+        client.dispute(&dispute(None), &policy)?;
+        assert_eq!(client.transaction(1)?.unwrap().dispute, DisputeState::Open);
+        assert_eq!(client.summary_or_empty(usd).held, "3.0".parse()?);
+        assert_eq!(
+            client.dispute(&dispute(None), &policy),
+            Err(EngineError::DisputeError(
+                "TX 1 is already disputed".to_string()
+            ))
+        );
+
+        client.verify()
+    }
+
+    #[test]
+    fn test_failed_journal_post() -> Result<(), EngineError> {
+        let usd = Currency::default();
+        let policy = DisputePolicy::default();
+        let mut client = Client::new(1);
+
+        client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("1.0")))?;
+        client.dispute(&tx(TransactionType::Dispute, 1, 1, None), &policy)?;
+
+        // Losses so large that the chargeback can't be posted.
+        let balances: Vec<_> = client
+            .journal
+            .balances()
+            .chain([((usd, Account::ChargebackLoss), Amount::from_raw(i64::MAX))])
+            .collect();
+        client.journal = Journal::from_balances(balances);
+
+        assert_eq!(
+            client.charge_back(&tx(TransactionType::ChargeBack, 1, 1, None), &policy),
+            Err(EngineError::AmountOverflow)
+        );
+
+        // The balances, the status and the dispute are as they were before it.
+        let summary = client.summary_or_empty(usd);
+        assert_eq!(summary.available, Amount::ZERO);
+        assert_eq!(summary.held, "1.0".parse()?);
+        assert_eq!(summary.total, "1.0".parse()?);
+        assert!(!client.is_locked());
+        assert_eq!(client.transaction(1)?.unwrap().dispute, DisputeState::Open);
+        assert_eq!(
+            client.journal().balance(usd, Account::ClientHeld),
+            "1.0".parse()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dispute_transitions() -> Result<(), EngineError> {
+        let usd = Currency::default();
+        let policy = DisputePolicy {
+            allow_withdrawal_disputes: true,
+            ..DisputePolicy::default()
+        };
+
+        // Deposits of 10.0 (TX 1) and 5.0 (TX 3), then a 4.0 withdrawal (TX 2).
+        let setup = || -> Result<Client, EngineError> {
+            let mut client = Client::new(1);
+            client.deposit(&tx(TransactionType::Deposit, 1, 1, Some("10.0")))?;
+            client.deposit(&tx(TransactionType::Deposit, 1, 3, Some("5.0")))?;
+            client.withdraw(
+                &tx(TransactionType::Withdrawal, 1, 2, Some("4.0")),
+                &DisputePolicy::default(),
+            )?;
+            Ok(client)
+        };
+
+        let step = |client: &mut Client, action, tx_id| {
+            let row = tx(action, 1, tx_id, None);
+            match action {
+                TransactionType::Dispute => client.dispute(&row, &policy),
+                TransactionType::Resolve => client.resolve(&row, &policy),
+                _ => client.charge_back(&row, &policy),
+            }
+        };
+
+        // Available, held and total once the disputed transaction reaches each state.
+        let balances = |tx_id, state| match (tx_id, state) {
+            (1, DisputeState::Open) => ("1.0", "10.0", "11.0"),
+            (1, DisputeState::ChargedBack) => ("1.0", "0.0", "1.0"),
+            (2, DisputeState::Open) => ("11.0", "4.0", "15.0"),
+            (2, DisputeState::ChargedBack) => ("15.0", "0.0", "15.0"),
+            _ => ("11.0", "0.0", "11.0"),
+        };
+
+        let paths = [
+            (DisputeState::None, vec![]),
+            (DisputeState::Open, vec![TransactionType::Dispute]),
+            (
+                DisputeState::Resolved,
+                vec![TransactionType::Dispute, TransactionType::Resolve],
+            ),
+            (
+                DisputeState::ChargedBack,
+                vec![TransactionType::Dispute, TransactionType::ChargeBack],
+            ),
+        ];
+
+        for tx_id in [1, 2] {
+            for (from, path) in &paths {
+                for action in [
+                    TransactionType::Dispute,
+                    TransactionType::Resolve,
+                    TransactionType::ChargeBack,
+                ] {
+                    let case = format!("TX {tx_id} {from:?} + {action}");
+                    let mut client = setup()?;
+
+                    for earlier in path {
+                        step(&mut client, *earlier, tx_id)?;
+                    }
+
+                    let expected = match (from, action) {
+                        (DisputeState::None, TransactionType::Dispute) => Ok(DisputeState::Open),
+                        (DisputeState::Open, TransactionType::Resolve) => {
+                            Ok(DisputeState::Resolved)
+                        }
+                        (DisputeState::Open, TransactionType::ChargeBack) => {
+                            Ok(DisputeState::ChargedBack)
+                        }
+                        (DisputeState::ChargedBack, _) => Err("account_locked"),
+                        (_, TransactionType::Dispute) => Err("dispute_error"),
+                        (_, TransactionType::Resolve) => Err("resolve_error"),
+                        _ => Err("chargeback_error"),
+                    };
+
+                    let result = step(&mut client, action, tx_id)
+                        .and_then(|()| client.transaction(tx_id))
+                        .map(|tx| tx.unwrap().dispute)
+                        .map_err(|e| e.code());
+                    assert_eq!(result, expected, "{case}");
+
+                    let state = expected.unwrap_or(*from);
+                    let (available, held, total) = balances(tx_id, state);
+                    let summary = client.summary_or_empty(usd);
+
+                    assert_eq!(client.transaction(tx_id)?.unwrap().dispute, state, "{case}");
+                    assert_eq!(summary.available, available.parse()?, "{case}");
+                    assert_eq!(summary.held, held.parse()?, "{case}");
+                    assert_eq!(summary.total, total.parse()?, "{case}");
+                    assert_eq!(
+                        summary.is_locked(),
+                        state == DisputeState::ChargedBack,
+                        "{case}"
+                    );
+                    client.verify()?;
+                }
+            }
+        }
+
+        // Withdrawals can only be disputed when the policy allows it.
+        let mut client = setup()?;
+        assert_eq!(
+            client.dispute(
+                &tx(TransactionType::Dispute, 1, 2, None),
+                &DisputePolicy::default()
+            ),
+            Err(EngineError::DisputeError(
+                "Attempt to dispute non deposit tx".to_string()
+            ))
+        );
+        assert_eq!(client.transaction(2)?.unwrap().dispute, DisputeState::None);
 
         Ok(())
     }
@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::{
+    amount::RoundingMode,
+    currency::Currency,
+    input::{InputFormat, Schema},
+    transaction::Transaction,
+    EngineError,
+};
+
+/// Shape of the final client summary output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// One row per client showing its balances in the default currency.
+    #[default]
+    PerClient,
+    /// One row per (client, currency) with an extra `currency` column.
+    PerCurrency,
+}
+
+impl FromStr for OutputMode {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-client" => Ok(OutputMode::PerClient),
+            "per-currency" => Ok(OutputMode::PerCurrency),
+            _ => Err(EngineError::OtherError(format!("Unknown output mode: {s}"))),
+        }
+    }
+}
+
+/// What to do with a row that fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorAction {
+    /// Stop reading the file and roll the engine back to its state before the file.
+    Abort,
+    /// Report the row and carry on with the next one.
+    Skip,
+    /// Report the row, copy it to the quarantine file for reprocessing and carry on.
+    Quarantine,
+}
+
+impl FromStr for ErrorAction {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(ErrorAction::Abort),
+            "skip" => Ok(ErrorAction::Skip),
+            "quarantine" => Ok(ErrorAction::Quarantine),
+            _ => Err(EngineError::OtherError(format!(
+                "Unknown error action: {s}"
+            ))),
+        }
+    }
+}
+
+/// How each category of failed row is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorPolicy {
+    /// Rows that cannot be read as a transaction: wrong columns, bad numbers, unknown types.
+    pub malformed: ErrorAction,
+    /// Well-formed transactions a client refuses, e.g. for insufficient funds.
+    pub rejected: ErrorAction,
+}
+
+impl ErrorPolicy {
+    pub fn aborts(&self) -> bool {
+        self.malformed == ErrorAction::Abort || self.rejected == ErrorAction::Abort
+    }
+
+    pub fn quarantines(&self) -> bool {
+        self.malformed == ErrorAction::Quarantine || self.rejected == ErrorAction::Quarantine
+    }
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy {
+            malformed: ErrorAction::Abort,
+            rejected: ErrorAction::Skip,
+        }
+    }
+}
+
+/// What a dispute does when the client no longer has the disputed funds available.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeficitMode {
+    /// Reject the dispute for insufficient funds, so the client keeps the money.
+    #[default]
+    Strict,
+    /// Hold the funds anyway, taking available negative and putting the client in deficit.
+    Allow,
+    /// Like `Allow`, and refuse withdrawals in every currency while the client is in deficit.
+    Freeze,
+}
+
+impl FromStr for DeficitMode {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(DeficitMode::Strict),
+            "allow" => Ok(DeficitMode::Allow),
+            "freeze" => Ok(DeficitMode::Freeze),
+            _ => Err(EngineError::OtherError(format!(
+                "Unknown deficit mode: {s}"
+            ))),
+        }
+    }
+}
+
+/// Knobs for the dispute lifecycle, see [`DisputeState`](crate::dispute::DisputeState).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisputePolicy {
+    /// Let a resolved transaction be disputed again.
+    pub allow_redispute: bool,
+
+    /// Let withdrawals be disputed as well as deposits. The disputed amount is credited to
+    /// held while the dispute is open; a resolve takes it back and a chargeback releases it
+    /// to the client.
+    pub allow_withdrawal_disputes: bool,
+
+    pub deficit: DeficitMode,
+
+    /// How long after a transaction it can still be disputed. None for no limit.
+    pub window: Option<DisputeWindow>,
+}
+
+/// How long a transaction stays disputable: a time, measured on the rows' timestamps, or a
+/// number of rows, for files without timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeWindow {
+    /// Seconds between timestamps. Every transaction row must then have a timestamp.
+    Seconds(u64),
+    /// Events between sequence numbers.
+    Rows(u64),
+}
+
+impl DisputeWindow {
+    /// Whether the window is measured on timestamps.
+    pub fn is_timed(self) -> bool {
+        matches!(self, DisputeWindow::Seconds(_))
+    }
+
+    /// Where `transaction` stands on the window's clock: its timestamp or its sequence number.
+    /// None for a transaction without a timestamp, which no time window applies to.
+    pub fn position(self, transaction: &Transaction) -> Option<u64> {
+        match self {
+            DisputeWindow::Seconds(_) => transaction.time,
+            DisputeWindow::Rows(_) => Some(transaction.seq),
+        }
+    }
+
+    fn length(self) -> u64 {
+        match self {
+            DisputeWindow::Seconds(length) | DisputeWindow::Rows(length) => length,
+        }
+    }
+
+    /// Whether `made` is past the window when `now` refers to it.
+    pub fn expired(self, made: &Transaction, now: &Transaction) -> bool {
+        match (self.position(made), self.position(now)) {
+            (Some(made), Some(now)) => now.saturating_sub(made) > self.length(),
+            _ => false,
+        }
+    }
+
+    /// What is past the window once the engine is at sequence number `seq` with the latest
+    /// timestamp at `clock`.
+    pub fn cutoff(self, seq: u64, clock: u64) -> Option<Cutoff> {
+        let now = match self {
+            DisputeWindow::Seconds(_) => clock,
+            DisputeWindow::Rows(_) => seq,
+        };
+
+        now.checked_sub(self.length()).map(|position| Cutoff {
+            window: self,
+            position,
+        })
+    }
+}
+
+impl FromStr for DisputeWindow {
+    type Err = EngineError;
+
+    /// A number with an `s`, `m`, `h` or `d` unit for a time, e.g. `120d`, or a bare number
+    /// of rows.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, unit) = match s.char_indices().last() {
+            Some((index, unit)) if unit.is_ascii_alphabetic() => (&s[..index], Some(unit)),
+            _ => (s, None),
+        };
+
+        let multiplier = match unit {
+            None | Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            Some(_) => 0,
+        };
+
+        number
+            .parse::<u64>()
+            .ok()
+            .filter(|_| multiplier > 0)
+            .and_then(|number| number.checked_mul(multiplier))
+            .map(|length| match unit {
+                None => DisputeWindow::Rows(length),
+                Some(_) => DisputeWindow::Seconds(length),
+            })
+            .ok_or_else(|| EngineError::OtherError(format!("Invalid dispute window: {s}")))
+    }
+}
+
+/// Where a dispute window ends, see [`DisputeWindow::cutoff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cutoff {
+    window: DisputeWindow,
+    position: u64,
+}
+
+impl Cutoff {
+    /// Whether `transaction` was made before the cutoff.
+    pub fn passed(self, transaction: &Transaction) -> bool {
+        self.window
+            .position(transaction)
+            .is_some_and(|position| position < self.position)
+    }
+}
+
+/// Engine-wide settings shared by every processing path.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Applied to input amounts with more than four decimal places.
+    pub rounding: RoundingMode,
+
+    /// Currency assumed for rows without a `currency` column or value.
+    pub default_currency: Currency,
+
+    pub output_mode: OutputMode,
+
+    /// Number of client shards. With more than one, each shard gets its own worker thread
+    /// while a file is processed; with one, transactions are applied on the reading thread.
+    pub shards: usize,
+
+    pub error_policy: ErrorPolicy,
+
+    /// Commit each file only if every row in it is valid and accepted, otherwise leave the
+    /// engine as it was before the file. Overrides the error policy.
+    pub atomic: bool,
+
+    pub disputes: DisputePolicy,
+
+    /// Format of input files. None picks it from the file extension, CSV if unknown.
+    pub input_format: Option<InputFormat>,
+
+    /// Layout of the columns of transactions files.
+    pub schema: Schema,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            rounding: RoundingMode::default(),
+            default_currency: Currency::default(),
+            output_mode: OutputMode::default(),
+            shards: 1,
+            error_policy: ErrorPolicy::default(),
+            atomic: false,
+            disputes: DisputePolicy::default(),
+            input_format: None,
+            schema: Schema::default(),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::{fmt::Display, str::FromStr};
+
+use crate::EngineError;
+
+const MAX_LEN: usize = 8;
+
+/// A short alphanumeric currency code such as `USD`, stored inline and normalised to
+/// upper case so it can be copied into every transaction cheaply.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; MAX_LEN]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(MAX_LEN);
+
+        // Only ASCII alphanumerics are ever stored.
+        std::str::from_utf8(&self.0[..len]).unwrap_or_default()
+    }
+}
+
+impl Currency {
+    /// The code as stored, NUL padded, for fixed-size binary records.
+    pub(crate) fn to_bytes(self) -> [u8; MAX_LEN] {
+        self.0
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; MAX_LEN]) -> Self {
+        Currency(bytes)
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency(*b"USD\0\0\0\0\0")
+    }
+}
+
+impl FromStr for Currency {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.len() > MAX_LEN || !s.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(EngineError::InvalidCurrency(s.to_string()));
+        }
+
+        let mut code = [0; MAX_LEN];
+        for (slot, b) in code.iter_mut().zip(s.bytes()) {
+            *slot = b.to_ascii_uppercase();
+        }
+
+        Ok(Currency(code))
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::fmt::Debug for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Currency({})", self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CurrencyVisitor;
+
+        impl Visitor<'_> for CurrencyVisitor {
+            type Value = Currency;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(
+                    f,
+                    "an alphanumeric currency code of at most {MAX_LEN} characters"
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Currency, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_currency() -> Result<(), EngineError> {
+        let eur: Currency = "eur".parse()?;
+
+        assert_eq!(eur.as_str(), "EUR");
+        assert_eq!(eur, "EUR".parse()?);
+        assert_eq!(Currency::default().to_string(), "USD");
+        assert_eq!("USDT2024".parse::<Currency>()?.as_str(), "USDT2024");
+
+        for bad in ["", "US D", "TOOLONGCODE", "€"] {
+            assert_eq!(
+                bad.parse::<Currency>(),
+                Err(EngineError::InvalidCurrency(bad.to_string()))
+            );
+        }
+
+        Ok(())
+    }
+}
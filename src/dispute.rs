@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+use crate::{
+    amount::Amount,
+    config::DisputePolicy,
+    transaction::{Transaction, TransactionType},
+    EngineError,
+};
+
+/// Where a deposit or withdrawal stands in the dispute lifecycle.
+///
+/// Every change goes through [`DisputeState::transition`], the single table of allowed steps:
+///
+/// | from          | dispute                                         | resolve    | chargeback    |
+/// |---------------|-------------------------------------------------|------------|---------------|
+/// | `None`        | `Open`                                          | -          | -             |
+/// | `Open`        | `Open` if partly undisputed                     | `Resolved` | `ChargedBack` |
+/// | `Resolved`    | `Open` if partly undisputed or re-disputable    | -          | -             |
+/// | `ChargedBack` | -                                               | -          | -             |
+///
+/// Disputes, resolves and chargebacks may move part of the transaction's amount. The part
+/// never disputed can be disputed whenever the transaction isn't charged back, a partial
+/// resolve leaves the dispute open until the whole disputed amount is resolved, and a
+/// chargeback settles the whole disputed amount at once.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    /// Never disputed.
+    #[default]
+    None,
+    /// Disputed, with the funds held until it is resolved or charged back.
+    Open,
+    /// The dispute was settled in the client's favour and the funds released.
+    Resolved,
+    /// The dispute ended with the funds reversed. Final.
+    ChargedBack,
+}
+
+/// A dispute step that the transaction's current state does not allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub tx_id: u32,
+    pub from: DisputeState,
+    pub action: TransactionType,
+}
+
+impl DisputeState {
+    /// The state after `action` is applied to transaction `tx_id` in this state, where
+    /// `undisputed` tells whether part of its amount was never disputed.
+    pub fn transition(
+        self,
+        tx_id: u32,
+        action: TransactionType,
+        undisputed: bool,
+        policy: &DisputePolicy,
+    ) -> Result<DisputeState, InvalidTransition> {
+        match (self, action) {
+            (DisputeState::None, TransactionType::Dispute) => Ok(DisputeState::Open),
+            (DisputeState::Open, TransactionType::Dispute) if undisputed => Ok(DisputeState::Open),
+            (DisputeState::Resolved, TransactionType::Dispute)
+                if undisputed || policy.allow_redispute =>
+            {
+                Ok(DisputeState::Open)
+            }
+            (DisputeState::Open, TransactionType::Resolve) => Ok(DisputeState::Resolved),
+            (DisputeState::Open, TransactionType::ChargeBack) => Ok(DisputeState::ChargedBack),
+            (from, action) => Err(InvalidTransition {
+                tx_id,
+                from,
+                action,
+            }),
+        }
+    }
+}
+
+/// The error for a dispute, resolve or chargeback of a transaction past the dispute window.
+pub(crate) fn window_expired(tx_id: u32) -> EngineError {
+    EngineError::DisputeWindowExpired(format!("TX {tx_id} is past its dispute window"))
+}
+
+/// Fails if `transaction` is past the dispute window when `action` refers to it: it can't be
+/// disputed any more, and only an open dispute can still be resolved or charged back.
+pub(crate) fn ensure_in_window(
+    transaction: &Transaction,
+    action: &Transaction,
+    policy: &DisputePolicy,
+) -> Result<(), EngineError> {
+    let expired = policy
+        .window
+        .is_some_and(|window| window.expired(transaction, action));
+
+    if expired
+        && (action.tx_type == TransactionType::Dispute || transaction.dispute != DisputeState::Open)
+    {
+        return Err(window_expired(transaction.tx_id));
+    }
+
+    Ok(())
+}
+
+/// The part of `outstanding` that a dispute, resolve or chargeback row moves: the row's own
+/// amount if it has one, otherwise all of it.
+pub(crate) fn portion(
+    tx_id: u32,
+    action: TransactionType,
+    requested: Option<Amount>,
+    outstanding: Amount,
+) -> Result<Amount, EngineError> {
+    let Some(amount) = requested else {
+        return Ok(outstanding);
+    };
+
+    if !amount.is_positive() {
+        return Err(EngineError::InvalidTransaction(format!(
+            "Tx ID: {} invalid amount",
+            tx_id
+        )));
+    }
+
+    if amount > outstanding {
+        let message = match action {
+            TransactionType::Dispute => {
+                format!("Amount {amount} exceeds the undisputed {outstanding} of TX {tx_id}")
+            }
+            _ => format!("Amount {amount} exceeds the disputed {outstanding} of TX {tx_id}"),
+        };
+
+        return Err(match action {
+            TransactionType::Resolve => EngineError::ResolveError(message),
+            TransactionType::ChargeBack => EngineError::ChargeBackError(message),
+            _ => EngineError::DisputeError(message),
+        });
+    }
+
+    Ok(amount)
+}
+
+impl Display for InvalidTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.from {
+            DisputeState::None => write!(f, "TX {} is undisputed", self.tx_id),
+            DisputeState::Open => write!(f, "TX {} is already disputed", self.tx_id),
+            DisputeState::Resolved => write!(f, "TX {} is already resolved", self.tx_id),
+            DisputeState::ChargedBack => write!(f, "TX {} is already charged back", self.tx_id),
+        }
+    }
+}
+
+impl From<InvalidTransition> for EngineError {
+    fn from(e: InvalidTransition) -> Self {
+        match e.action {
+            TransactionType::Resolve => EngineError::ResolveError(e.to_string()),
+            TransactionType::ChargeBack => EngineError::ChargeBackError(e.to_string()),
+            _ => EngineError::DisputeError(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transition_table() {
+        let strict = DisputePolicy::default();
+        let redispute = DisputePolicy {
+            allow_redispute: true,
+            ..DisputePolicy::default()
+        };
+
+        let states = [
+            DisputeState::None,
+            DisputeState::Open,
+            DisputeState::Resolved,
+            DisputeState::ChargedBack,
+        ];
+        let actions = [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::ChargeBack,
+        ];
+
+        for from in states {
+            for action in actions {
+                for undisputed in [true, false] {
+                    let expected = match (from, action) {
+                        (DisputeState::None, TransactionType::Dispute) => Some(DisputeState::Open),
+                        (DisputeState::Open | DisputeState::Resolved, TransactionType::Dispute)
+                            if undisputed =>
+                        {
+                            Some(DisputeState::Open)
+                        }
+                        (DisputeState::Open, TransactionType::Resolve) => {
+                            Some(DisputeState::Resolved)
+                        }
+                        (DisputeState::Open, TransactionType::ChargeBack) => {
+                            Some(DisputeState::ChargedBack)
+                        }
+                        _ => None,
+                    };
+
+                    assert_eq!(
+                        from.transition(1, action, undisputed, &strict).ok(),
+                        expected,
+                        "{from:?} + {action}, undisputed: {undisputed}"
+                    );
+                }
+            }
+        }
+
+        assert_eq!(
+            DisputeState::Resolved.transition(1, TransactionType::Dispute, false, &redispute),
+            Ok(DisputeState::Open)
+        );
+        assert_eq!(
+            DisputeState::ChargedBack
+                .transition(7, TransactionType::Dispute, true, &redispute)
+                .map_err(EngineError::from),
+            Err(EngineError::DisputeError(
+                "TX 7 is already charged back".to_string()
+            ))
+        );
+        assert_eq!(
+            DisputeState::None
+                .transition(7, TransactionType::ChargeBack, false, &strict)
+                .map_err(EngineError::from),
+            Err(EngineError::ChargeBackError(
+                "TX 7 is undisputed".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use crate::{transaction::Transaction, AppState, EngineError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Accepted,
+    Rejected(EngineError),
+}
+
+/// A transaction the engine attempted to apply, in processing order.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub seq: u64,
+    pub source: Arc<str>,
+    pub line: u64,
+    pub transaction: Transaction,
+    pub outcome: Outcome,
+}
+
+/// Ordered, append-only record of every accepted and rejected transaction.
+///
+/// A log restored alongside a [snapshot](crate::snapshot) starts after the snapshot's last
+/// sequence number, so numbering stays continuous across restarts.
+#[derive(Debug, Default)]
+pub struct EventLog {
+    base_seq: u64,
+    base_clock: u64,
+    clock: u64,
+    events: Vec<Event>,
+}
+
+impl EventLog {
+    /// An empty log whose first event will be `base_seq + 1`, with the clock at `base_clock`.
+    pub fn with_base(base_seq: u64, base_clock: u64) -> Self {
+        EventLog {
+            base_seq,
+            base_clock,
+            clock: base_clock,
+            events: Vec::new(),
+        }
+    }
+
+    /// Sequence number of the latest event, including those before the base.
+    pub fn last_seq(&self) -> u64 {
+        self.base_seq + self.events.len() as u64
+    }
+
+    /// The engine clock: the latest timestamp of any event, see [`Transaction::time`], or zero
+    /// before the first.
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Appends an event and returns its sequence number.
+    pub fn append(
+        &mut self,
+        source: Arc<str>,
+        line: u64,
+        transaction: Transaction,
+        outcome: Outcome,
+    ) -> u64 {
+        let seq = self.last_seq() + 1;
+        self.clock = self.clock.max(transaction.time.unwrap_or_default());
+
+        self.events.push(Event {
+            seq,
+            source,
+            line,
+            transaction,
+            outcome,
+        });
+
+        seq
+    }
+
+    pub fn get(&self, seq: u64) -> Option<&Event> {
+        seq.checked_sub(self.base_seq + 1)
+            .and_then(|index| self.events.get(index as usize))
+    }
+
+    /// Records the outcome of an event appended before its transaction was applied.
+    pub(crate) fn set_outcome(&mut self, seq: u64, outcome: Outcome) {
+        if let Some(event) = seq
+            .checked_sub(self.base_seq + 1)
+            .and_then(|index| self.events.get_mut(index as usize))
+        {
+            event.outcome = outcome;
+        }
+    }
+
+    /// Drops every event so far to save memory. Numbering continues as after a snapshot.
+    pub(crate) fn compact(&mut self) {
+        self.base_clock = self.clock();
+        self.base_seq = self.last_seq();
+        self.events = Vec::new();
+    }
+
+    /// Drops every event after `seq`.
+    pub(crate) fn truncate(&mut self, seq: u64) {
+        self.events
+            .truncate(seq.saturating_sub(self.base_seq) as usize);
+        self.clock = self
+            .events
+            .iter()
+            .filter_map(|event| event.transaction.time)
+            .fold(self.base_clock, u64::max);
+    }
+
+    /// Sequence number of the event read from `line` of `source`, if any.
+    pub fn seq_at(&self, source: &str, line: u64) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|event| &*event.source == source && event.line == line)
+            .map(|event| event.seq)
+    }
+
+    /// Rebuilds the engine state as it was right after event `until` was applied, by applying
+    /// the events of this log after the last event of `base`: a fresh state, or one loaded
+    /// from a snapshot taken no later than `until`.
+    ///
+    /// Fails if replaying an event yields a different outcome than the one recorded, which
+    /// means the log was produced under a different configuration, or if the log lacks events
+    /// after `base` because it starts after a later snapshot or was compacted. The
+    /// [write-ahead log](crate::wal::replay) keeps them all.
+    pub fn replay(&self, mut base: AppState, until: u64) -> Result<AppState, EngineError> {
+        let from = base.event_log.get_mut().last_seq();
+
+        if self.base_seq > from {
+            return Err(EngineError::OtherError(format!(
+                "Events up to {} were loaded from a snapshot or dropped and cannot be replayed",
+                self.base_seq
+            )));
+        }
+
+        if from > until {
+            return Err(EngineError::OtherError(format!(
+                "Cannot replay to event {} from a state after event {}",
+                until, from
+            )));
+        }
+
+        let mut client_map = base.client_map.get_mut();
+        let event_log = base.event_log.get_mut();
+
+        for event in self
+            .events
+            .iter()
+            .skip_while(|event| event.seq <= from)
+            .take_while(|event| event.seq <= until)
+        {
+            let outcome = match client_map.apply(&event.transaction, &base.config.disputes) {
+                Ok(()) => Outcome::Accepted,
+                Err(e) => Outcome::Rejected(e),
+            };
+
+            if outcome != event.outcome {
+                return Err(EngineError::OtherError(format!(
+                    "Replay diverged at event {}",
+                    event.seq
+                )));
+            }
+
+            event_log.append(
+                event.source.clone(),
+                event.line,
+                event.transaction.clone(),
+                outcome,
+            );
+        }
+
+        drop(client_map);
+
+        Ok(base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        apply_transaction,
+        config::{DisputePolicy, EngineConfig},
+        currency::Currency,
+        fixtures::tx,
+        transaction::TransactionType,
+    };
+
+    fn build_log(transactions: Vec<Transaction>) -> EventLog {
+        let mut client_map = std::collections::HashMap::new();
+        let mut event_log = EventLog::default();
+        let source: Arc<str> = Arc::from("test.csv");
+
+        for (i, transaction) in transactions.into_iter().enumerate() {
+            let outcome =
+                match apply_transaction(&mut client_map, &transaction, &DisputePolicy::default()) {
+                    Ok(()) => Outcome::Accepted,
+                    Err(e) => Outcome::Rejected(e),
+                };
+
+            event_log.append(source.clone(), i as u64 + 2, transaction, outcome);
+        }
+
+        event_log
+    }
+
+    #[test]
+    fn test_replay_point_in_time() -> Result<(), EngineError> {
+        let event_log = build_log(vec![
+            tx(TransactionType::Deposit, 7, 1, Some("10.0")),
+            tx(TransactionType::Withdrawal, 7, 2, Some("20.0")),
+            tx(TransactionType::Withdrawal, 7, 3, Some("4.0")),
+            tx(TransactionType::Dispute, 7, 1, None),
+        ]);
+
+        assert_eq!(event_log.len(), 4);
+        assert_eq!(
+            event_log.events()[1].outcome,
+            Outcome::Rejected(EngineError::InsufficientFunds)
+        );
+        assert_eq!(event_log.seq_at("test.csv", 4), Some(3));
+
+        let usd = Currency::default();
+
+        let mut state = event_log.replay(AppState::new(EngineConfig::default()), 1)?;
+        let summary = state.client_map.get_mut()[&7].summary_or_empty(usd);
+        assert_eq!(summary.get_available(), "10.0".parse()?);
+
+        let mut state = event_log.replay(AppState::new(EngineConfig::default()), 3)?;
+        assert_eq!(state.event_log.get_mut().len(), 3);
+        let summary = state.client_map.get_mut()[&7].summary_or_empty(usd);
+        assert_eq!(summary.get_available(), "6.0".parse()?);
+        assert_eq!(summary.get_total(), "6.0".parse()?);
+
+        // The final dispute fails for lack of funds in both the original run and the replay.
+        let mut state = event_log.replay(AppState::new(EngineConfig::default()), u64::MAX)?;
+        assert_eq!(state.event_log.get_mut().len(), 4);
+
+        // Replaying on top of a state part of the way through only applies the later events.
+        let base = event_log.replay(AppState::new(EngineConfig::default()), 2)?;
+        let mut state = event_log.replay(base, 3)?;
+        assert_eq!(state.event_log.get_mut().last_seq(), 3);
+        let summary = state.client_map.get_mut()[&7].summary_or_empty(usd);
+        assert_eq!(summary.get_available(), "6.0".parse()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_after_snapshot() -> Result<(), EngineError> {
+        let full_log = build_log(vec![
+            tx(TransactionType::Deposit, 7, 1, Some("10.0")),
+            tx(TransactionType::Withdrawal, 7, 2, Some("4.0")),
+            tx(TransactionType::Deposit, 7, 3, Some("1.0")),
+        ]);
+
+        // A log restored with a snapshot after event 1 only has the events after it.
+        let mut event_log = EventLog::with_base(1, 1);
+        for event in &full_log.events()[1..] {
+            event_log.append(
+                event.source.clone(),
+                event.line,
+                event.transaction.clone(),
+                event.outcome.clone(),
+            );
+        }
+
+        let base = full_log.replay(AppState::new(EngineConfig::default()), 1)?;
+        let mut state = event_log.replay(base, 3)?;
+        let summary = state.client_map.get_mut()[&7].summary_or_empty(Currency::default());
+        assert_eq!(summary.get_available(), "7.0".parse()?);
+
+        assert!(event_log
+            .replay(AppState::new(EngineConfig::default()), 3)
+            .is_err());
+
+        let base = full_log.replay(AppState::new(EngineConfig::default()), 3)?;
+        assert!(event_log.replay(base, 2).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_divergence() {
+        let mut event_log = build_log(vec![tx(TransactionType::Deposit, 1, 1, Some("1.0"))]);
+
+        event_log.events[0].outcome = Outcome::Rejected(EngineError::InsufficientFunds);
+
+        assert_eq!(
+            event_log
+                .replay(AppState::new(EngineConfig::default()), 1)
+                .err(),
+            Some(EngineError::OtherError(
+                "Replay diverged at event 1".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Fixtures shared by the unit tests.
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    amount::Amount,
+    currency::Currency,
+    dispute::DisputeState,
+    transaction::{Transaction, TransactionType},
+};
+
+/// A scratch directory named after its test, emptied when created and removed when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("tx_engine_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A transaction as read from a row, in the default currency and without a timestamp.
+pub(crate) fn tx(
+    tx_type: TransactionType,
+    client_id: u16,
+    tx_id: u32,
+    amount: Option<&str>,
+) -> Transaction {
+    Transaction {
+        tx_id,
+        client_id,
+        tx_type,
+        amount: amount.map(|amount| amount.parse().unwrap()),
+        currency: Currency::default(),
+        dispute: DisputeState::None,
+        disputed_amount: Amount::ZERO,
+        disputed_total: Amount::ZERO,
+        time: None,
+        seq: 0,
+        note: None,
+    }
+}
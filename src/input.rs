@@ -0,0 +1,903 @@
+use csv::{ReaderBuilder, StringRecord};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{wal::InputOffset, EngineError};
+
+/// The path that stands for stdin.
+pub const STDIN: &str = "-";
+
+/// Columns every input must have, whatever else it is made of.
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Format of an input file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line, keyed by column name.
+    JsonLines,
+}
+
+impl FromStr for InputFormat {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" => Ok(InputFormat::JsonLines),
+            _ => Err(EngineError::OtherError(format!(
+                "Unknown input format: {s}"
+            ))),
+        }
+    }
+}
+
+impl InputFormat {
+    /// The format of `path` going by its extension: `.csv`, or `.jsonl` and `.ndjson`, ahead
+    /// of a `.gz` or `.zst` one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = match path.extension()?.to_str()? {
+            "gz" | "zst" => Path::new(path.file_stem()?),
+            _ => path,
+        };
+
+        match path.extension()?.to_str()? {
+            "csv" => Some(InputFormat::Csv),
+            "jsonl" | "ndjson" => Some(InputFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Where a column of the engine is read from: a position in the row, counted from 1, or a
+/// name in the header row of a CSV file or a key of a JSON Lines object.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnSource {
+    Position(usize),
+    Name(String),
+}
+
+impl FromStr for ColumnSource {
+    type Err = EngineError;
+
+    /// A number is a position, anything else a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(position) => ColumnSource::Position(position),
+            Err(_) => ColumnSource::Name(s.to_string()),
+        })
+    }
+}
+
+/// An engine column and where it is read from, written `column=source`, e.g. `tx=Reference`
+/// or `amount=4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub column: String,
+    pub source: ColumnSource,
+}
+
+impl FromStr for ColumnMapping {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, source) = s.split_once('=').ok_or_else(|| {
+            EngineError::OtherError(format!(
+                "Invalid column mapping, expected column=source: {s}"
+            ))
+        })?;
+
+        Ok(ColumnMapping {
+            column: column.trim().to_string(),
+            source: source.trim().parse()?,
+        })
+    }
+}
+
+/// How the rows of a transactions file lay out the engine's columns.
+///
+/// Columns the schema doesn't map are found by their own name, ignoring case and surrounding
+/// spaces, or in a file without headers at their place in the engine's order.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schema {
+    /// Whether the first row of a CSV file names its columns.
+    pub headers: bool,
+    /// Field delimiter of a CSV file, an ASCII character.
+    pub delimiter: char,
+    /// Where each engine column is read from.
+    pub columns: BTreeMap<String, ColumnSource>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Schema {
+            headers: true,
+            delimiter: ',',
+            columns: BTreeMap::new(),
+        }
+    }
+}
+
+impl Schema {
+    /// Reads a schema from a JSON file, e.g.
+    /// `{"headers": false, "delimiter": ";", "columns": {"tx": 1, "type": 2}}`.
+    pub fn load(path: &Path) -> Result<Self, EngineError> {
+        let schema_error = |e: &dyn std::fmt::Display| {
+            EngineError::OtherError(format!("Invalid schema {}: {}", path.display(), e))
+        };
+
+        let file = File::open(path).map_err(|e| schema_error(&e))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| schema_error(&e))
+    }
+
+    /// Fails unless every mapped column is one of `columns`, every position is at least 1
+    /// and the delimiter is ASCII.
+    pub fn validate(&self, columns: &[&str]) -> Result<(), EngineError> {
+        let schema_error = |message: String| EngineError::OtherError(message);
+
+        if !self.delimiter.is_ascii() {
+            return Err(schema_error(format!(
+                "The delimiter must be an ASCII character: {}",
+                self.delimiter
+            )));
+        }
+
+        for (column, source) in &self.columns {
+            if !columns.contains(&column.as_str()) {
+                return Err(schema_error(format!(
+                    "Unknown column {column}, expected one of {}",
+                    columns.join(", ")
+                )));
+            }
+
+            if *source == ColumnSource::Position(0) {
+                return Err(schema_error(format!(
+                    "Column positions start at 1: {column}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A row read by a [`Decoder`]: its line in the file and its fields in the engine's column
+/// order. A row that could not be decoded keeps its text as a single field, so it can still be
+/// quarantined, along with why it could not be.
+#[derive(Debug)]
+pub struct DecodedRow {
+    pub line: u64,
+    pub fields: StringRecord,
+    pub error: Option<EngineError>,
+}
+
+/// Reads the rows of an input file as the fields of the engine's columns, whatever the file
+/// format, so every format is deserialized, reported and quarantined the same way.
+pub trait Decoder: Send {
+    /// The next row, or None at the end of the file. A row that can't be decoded is returned
+    /// with its error; only a file that can't be read any further fails.
+    fn next_row(&mut self) -> Result<Option<DecodedRow>, EngineError>;
+
+    /// Where the row after the last one returned starts.
+    fn position(&self) -> InputOffset;
+
+    /// Resumes reading at `offset`, a [position](Decoder::position) returned before.
+    fn seek(&mut self, offset: InputOffset) -> Result<(), EngineError>;
+}
+
+/// Opens `path` with the decoder for `format`, reading its rows as laid out by `schema`.
+/// `columns` are the engine's columns, in the order the rows' fields are returned.
+///
+/// [`STDIN`] reads stdin, and gzip or zstd input is decompressed as it is read.
+pub fn open(
+    path: &str,
+    format: InputFormat,
+    schema: &Schema,
+    columns: &[&str],
+) -> Result<Box<dyn Decoder>, EngineError> {
+    let source = Source::open(path).map_err(|_| {
+        EngineError::CsvFileError(String::from(match format {
+            InputFormat::Csv => "Invalid CSV file",
+            InputFormat::JsonLines => "Invalid JSON Lines file",
+        }))
+    })?;
+
+    Ok(match format {
+        InputFormat::Csv => Box::new(CsvDecoder::new(source, schema, columns)?),
+        InputFormat::JsonLines => Box::new(JsonLinesDecoder::new(source, schema, columns)?),
+    })
+}
+
+fn missing_column(column: &str) -> EngineError {
+    EngineError::CsvFileError(format!("Missing column: {column}"))
+}
+
+/// The bytes of an input: a plain file, or a stream read once from start to end, such as
+/// stdin or a decompressed file. Offsets count the bytes read, after decompression.
+enum Source {
+    File(File),
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Source {
+    fn open(path: &str) -> io::Result<Self> {
+        if path == STDIN {
+            return Self::stream(io::stdin());
+        }
+
+        let mut file = File::open(path)?;
+
+        // A named pipe, e.g. from process substitution, can only be read once.
+        if !file.metadata()?.is_file() {
+            return Self::stream(file);
+        }
+
+        let compressed = Self::compressed(&Self::magic(&mut file)?);
+        file.rewind()?;
+
+        match compressed {
+            true => Self::stream(file),
+            false => Ok(Source::File(file)),
+        }
+    }
+
+    /// The first bytes of `reader`, enough to tell whether it is compressed.
+    fn magic(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+        let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+        reader
+            .take(ZSTD_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+
+        Ok(magic)
+    }
+
+    fn compressed(magic: &[u8]) -> bool {
+        magic.starts_with(&GZIP_MAGIC) || magic.starts_with(&ZSTD_MAGIC)
+    }
+
+    /// `reader` from the start, decompressed if it is compressed.
+    fn stream(mut reader: impl Read + Send + 'static) -> io::Result<Self> {
+        let magic = Self::magic(&mut reader)?;
+        let reader = io::Cursor::new(magic.clone()).chain(reader);
+
+        Ok(Source::Stream(if magic.starts_with(&GZIP_MAGIC) {
+            // Multi-member, as concatenated gzip files are one stream.
+            Box::new(flate2::read::MultiGzDecoder::new(reader))
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            Box::new(zstd::stream::read::Decoder::new(reader)?)
+        } else {
+            Box::new(reader)
+        }))
+    }
+
+    fn is_stream(&self) -> bool {
+        matches!(self, Source::Stream(_))
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::File(file) => file.read(buf),
+            Source::Stream(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::File(file) => file.seek(pos),
+            Source::Stream(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "a stream can't seek",
+            )),
+        }
+    }
+}
+
+fn resume_error(offset: InputOffset, e: impl std::fmt::Display) -> EngineError {
+    EngineError::CsvFileError(format!("Failed to resume at byte {}: {}", offset.byte, e))
+}
+
+/// Resumes `decoder` at `offset` by reading the rows before it, for a stream that can't seek.
+fn skip_to(decoder: &mut impl Decoder, offset: InputOffset) -> Result<(), EngineError> {
+    while decoder.position().byte < offset.byte && decoder.next_row()?.is_some() {}
+
+    match decoder.position().byte == offset.byte {
+        true => Ok(()),
+        false => Err(resume_error(offset, "no row of the stream starts there")),
+    }
+}
+
+/// CSV, with or without a header row. Fields are trimmed.
+struct CsvDecoder {
+    reader: csv::Reader<Source>,
+    record: StringRecord,
+    /// Index in the row of each engine column, None if the file doesn't have it.
+    indexes: Vec<Option<usize>>,
+}
+
+impl CsvDecoder {
+    fn new(source: Source, schema: &Schema, columns: &[&str]) -> Result<Self, EngineError> {
+        let mut reader = ReaderBuilder::new()
+            // Flexible so rows may omit the trailing optional columns.
+            .flexible(true)
+            .has_headers(schema.headers)
+            .delimiter(schema.delimiter as u8)
+            .from_reader(source);
+
+        let headers = match schema.headers {
+            true => Some(reader.headers().cloned().map_err(|e| {
+                EngineError::CsvFileError(format!("Failed to read the header row: {}", e))
+            })?),
+            false => None,
+        };
+        let find = |name: &str| {
+            headers.as_ref().and_then(|headers| {
+                headers
+                    .iter()
+                    .position(|header| header.trim().eq_ignore_ascii_case(name.trim()))
+            })
+        };
+
+        let indexes = columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| match schema.columns.get(*column) {
+                Some(ColumnSource::Position(position)) => Ok(Some(position - 1)),
+                Some(ColumnSource::Name(name)) => {
+                    find(name).map(Some).ok_or_else(|| missing_column(name))
+                }
+                None if headers.is_some() => Ok(find(column)),
+                None => Ok(Some(index)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some((column, _)) = columns
+            .iter()
+            .zip(&indexes)
+            .find(|(column, index)| REQUIRED_COLUMNS.contains(column) && index.is_none())
+        {
+            return Err(missing_column(column));
+        }
+
+        Ok(CsvDecoder {
+            reader,
+            record: StringRecord::new(),
+            indexes,
+        })
+    }
+}
+
+impl Decoder for CsvDecoder {
+    fn next_row(&mut self) -> Result<Option<DecodedRow>, EngineError> {
+        let more = self.reader.read_record(&mut self.record).map_err(|e| {
+            EngineError::InvalidTransaction(format!("Failed to fetch transaction record. {}", e))
+        })?;
+
+        if !more {
+            return Ok(None);
+        }
+
+        Ok(Some(DecodedRow {
+            line: self.record.position().map_or(0, |position| position.line()),
+            fields: self
+                .indexes
+                .iter()
+                .map(|index| {
+                    index
+                        .and_then(|index| self.record.get(index))
+                        .map_or("", str::trim)
+                })
+                .collect(),
+            error: None,
+        }))
+    }
+
+    fn position(&self) -> InputOffset {
+        self.reader.position().into()
+    }
+
+    fn seek(&mut self, offset: InputOffset) -> Result<(), EngineError> {
+        if self.reader.get_ref().is_stream() {
+            return skip_to(self, offset);
+        }
+
+        self.reader
+            .seek(offset.into())
+            .map_err(|e| resume_error(offset, e))
+    }
+}
+
+/// One JSON object per line. Blank lines are skipped and keys that aren't columns ignored;
+/// strings and numbers are read as the column's text, and missing or null values are empty.
+struct JsonLinesDecoder {
+    reader: BufReader<Source>,
+    /// Key of each engine column.
+    keys: Vec<String>,
+    offset: InputOffset,
+    buffer: String,
+}
+
+impl JsonLinesDecoder {
+    fn new(source: Source, schema: &Schema, columns: &[&str]) -> Result<Self, EngineError> {
+        let keys = columns
+            .iter()
+            .map(|column| match schema.columns.get(*column) {
+                Some(ColumnSource::Name(key)) => Ok(key.clone()),
+                Some(ColumnSource::Position(_)) => Err(EngineError::CsvFileError(format!(
+                    "JSON Lines fields have no position, map {column} to a key"
+                ))),
+                None => Ok(column.to_string()),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(JsonLinesDecoder {
+            reader: BufReader::new(source),
+            keys,
+            offset: InputOffset {
+                byte: 0,
+                line: 1,
+                record: 0,
+            },
+            buffer: String::new(),
+        })
+    }
+}
+
+/// Decodes `text`, a JSON object, reading the engine's columns from `keys`.
+fn json_row(line: u64, text: &str, keys: &[String]) -> DecodedRow {
+    let fields = |object: &Map<String, Value>| {
+        keys.iter()
+            .map(|key| match object.get(key) {
+                None | Some(Value::Null) => Ok(String::new()),
+                Some(Value::String(text)) => Ok(text.trim().to_string()),
+                Some(Value::Number(number)) => Ok(number.to_string()),
+                Some(_) => Err(EngineError::InvalidTransaction(format!(
+                    "Failed to deserialize transaction record. {key} is not a string or number"
+                ))),
+            })
+            .collect::<Result<Vec<String>, _>>()
+            .map(StringRecord::from)
+    };
+
+    let fields = match serde_json::from_str::<Value>(text) {
+        Ok(Value::Object(object)) => fields(&object),
+        Ok(_) => Err(EngineError::InvalidTransaction(String::from(
+            "Failed to deserialize transaction record. Not a JSON object",
+        ))),
+        Err(e) => Err(EngineError::InvalidTransaction(format!(
+            "Failed to deserialize transaction record. {}",
+            e
+        ))),
+    };
+
+    match fields {
+        Ok(fields) => DecodedRow {
+            line,
+            fields,
+            error: None,
+        },
+        Err(e) => DecodedRow {
+            line,
+            fields: StringRecord::from(vec![text.trim()]),
+            error: Some(e),
+        },
+    }
+}
+
+impl Decoder for JsonLinesDecoder {
+    fn next_row(&mut self) -> Result<Option<DecodedRow>, EngineError> {
+        loop {
+            self.buffer.clear();
+            let read = self.reader.read_line(&mut self.buffer).map_err(|e| {
+                EngineError::InvalidTransaction(format!(
+                    "Failed to fetch transaction record. {}",
+                    e
+                ))
+            })?;
+
+            if read == 0 {
+                return Ok(None);
+            }
+
+            let line = self.offset.line;
+            self.offset.byte += read as u64;
+            self.offset.line += 1;
+
+            if self.buffer.trim().is_empty() {
+                continue;
+            }
+
+            self.offset.record += 1;
+
+            return Ok(Some(json_row(line, &self.buffer, &self.keys)));
+        }
+    }
+
+    fn position(&self) -> InputOffset {
+        self.offset
+    }
+
+    fn seek(&mut self, offset: InputOffset) -> Result<(), EngineError> {
+        if self.reader.get_ref().is_stream() {
+            return skip_to(self, offset);
+        }
+
+        self.reader
+            .seek(SeekFrom::Start(offset.byte))
+            .map_err(|e| resume_error(offset, e))?;
+        self.offset = offset;
+
+        Ok(())
+    }
+}
+
+/// Rows received one line at a time, as JSON objects keyed by column name or CSV rows without a
+/// header in the engine's column order. Blank lines are skipped. The rows can't be read again,
+/// so they can't be resumed.
+pub struct LinesDecoder {
+    lines: std::vec::IntoIter<(u64, String)>,
+    columns: Vec<String>,
+    offset: InputOffset,
+}
+
+impl LinesDecoder {
+    /// `lines` with their line numbers, in order.
+    pub fn new(lines: Vec<(u64, String)>, columns: &[&str]) -> Self {
+        LinesDecoder {
+            lines: lines.into_iter(),
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            offset: InputOffset {
+                byte: 0,
+                line: 1,
+                record: 0,
+            },
+        }
+    }
+}
+
+impl Decoder for LinesDecoder {
+    fn next_row(&mut self) -> Result<Option<DecodedRow>, EngineError> {
+        for (line, text) in self.lines.by_ref() {
+            self.offset.line = line + 1;
+
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            self.offset.record += 1;
+
+            if text.trim_start().starts_with('{') {
+                return Ok(Some(json_row(line, &text, &self.columns)));
+            }
+
+            let mut reader = ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(text.as_bytes());
+
+            return Ok(Some(match reader.records().next() {
+                Some(Ok(record)) => DecodedRow {
+                    line,
+                    fields: (0..self.columns.len())
+                        .map(|index| record.get(index).map_or("", str::trim))
+                        .collect(),
+                    error: None,
+                },
+                Some(Err(e)) => DecodedRow {
+                    line,
+                    fields: StringRecord::from(vec![text.trim()]),
+                    error: Some(EngineError::InvalidTransaction(format!(
+                        "Failed to deserialize transaction record. {}",
+                        e
+                    ))),
+                },
+                None => continue,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    fn position(&self) -> InputOffset {
+        self.offset
+    }
+
+    fn seek(&mut self, offset: InputOffset) -> Result<(), EngineError> {
+        Err(resume_error(offset, "lines can't be read again"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::Amount, fixtures::TempDir};
+
+    #[test]
+    fn test_formats_decode_alike() -> Result<(), EngineError> {
+        let dir = TempDir::new("input");
+        let columns: &[&str] = &["type", "client", "tx", "amount"];
+
+        let csv = dir.join("input.csv");
+        std::fs::write(
+            &csv,
+            "type,client,tx,amount\ndeposit, 1,1, 2.5\ndispute,1,1,\n",
+        )
+        .unwrap();
+        let jsonl = dir.join("input.jsonl");
+        std::fs::write(
+            &jsonl,
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2.5,\"note\":\"x\"}\n\
+             \n\
+             {\"tx\":1,\"client\":1,\"type\":\"dispute\",\"amount\":null}\n\
+             {\"type\":\"deposit\",\n\
+             [1]\n",
+        )
+        .unwrap();
+
+        let read_all = |path: &Path| -> Result<Vec<DecodedRow>, EngineError> {
+            let format = InputFormat::from_path(path).unwrap();
+            let mut decoder = open(&path.to_string_lossy(), format, &Schema::default(), columns)?;
+            std::iter::from_fn(|| decoder.next_row().transpose()).collect()
+        };
+
+        let csv_rows = read_all(&csv)?;
+        let jsonl_rows = read_all(&jsonl)?;
+        let fields = |rows: &[DecodedRow]| -> Vec<(Vec<String>, bool)> {
+            rows.iter()
+                .map(|row| {
+                    let fields = row.fields.iter().map(str::to_string).collect();
+                    (fields, row.error.is_some())
+                })
+                .collect()
+        };
+
+        assert_eq!(fields(&jsonl_rows)[..2], fields(&csv_rows)[..]);
+        assert_eq!(
+            jsonl_rows.iter().map(|row| row.line).collect::<Vec<_>>(),
+            [1, 3, 4, 5]
+        );
+        assert_eq!(
+            fields(&jsonl_rows)[2..],
+            [
+                (vec!["{\"type\":\"deposit\",".to_string()], true),
+                (vec!["[1]".to_string()], true)
+            ]
+        );
+
+        // Resuming after the first row reads the same rows as reading on.
+        let mut decoder = open(
+            &jsonl.to_string_lossy(),
+            InputFormat::JsonLines,
+            &Schema::default(),
+            columns,
+        )?;
+        decoder.next_row()?;
+        let offset = decoder.position();
+        let next = decoder.next_row()?.unwrap();
+
+        let mut resumed = open(
+            &jsonl.to_string_lossy(),
+            InputFormat::JsonLines,
+            &Schema::default(),
+            columns,
+        )?;
+        resumed.seek(offset)?;
+        let again = resumed.next_row()?.unwrap();
+        assert_eq!((again.line, again.fields), (next.line, next.fields));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_number_amounts() {
+        let keys = ["amount".to_string()];
+        let amount = |text: &str| {
+            let row = json_row(1, text, &keys);
+            assert_eq!(row.error, None, "{text}");
+            row.fields[0].to_string()
+        };
+
+        // Numbers are read as written, without going through a float.
+        let max = amount("{\"amount\": 922337203685477.5807}");
+        assert_eq!(max, "922337203685477.5807");
+        assert_eq!(max.parse::<Amount>(), Ok(Amount::from_raw(i64::MAX)));
+        assert_eq!(
+            amount("{\"amount\": 12345678901234.5678}"),
+            "12345678901234.5678"
+        );
+
+        // So amounts the engine can't represent exactly are refused, not rounded.
+        assert!(amount("{\"amount\": 1e20}").parse::<Amount>().is_err());
+        assert!(amount("{\"amount\": 1.00001}").parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn test_compressed_sources() -> Result<(), EngineError> {
+        use std::io::Write;
+
+        let dir = TempDir::new("compressed");
+        let columns: &[&str] = &["type", "client", "tx", "amount"];
+        let text = "type,client,tx,amount\ndeposit,1,1,2.5\ndeposit,1,2,1.0\nwithdrawal,1,3,0.5\n";
+
+        let plain = dir.join("input.csv");
+        std::fs::write(&plain, text).unwrap();
+        let gz = dir.join("input.csv.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gz).unwrap(), Default::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let zst = dir.join("input.csv.zst");
+        std::fs::write(&zst, zstd::encode_all(text.as_bytes(), 0).unwrap()).unwrap();
+
+        assert_eq!(InputFormat::from_path(&gz), Some(InputFormat::Csv));
+        assert_eq!(
+            InputFormat::from_path(Path::new("input.ndjson.zst")),
+            Some(InputFormat::JsonLines)
+        );
+        assert_eq!(InputFormat::from_path(Path::new("input.gz")), None);
+
+        let rows = |path: &Path, skip: usize| -> Result<Vec<(u64, String)>, EngineError> {
+            let mut decoder = open(
+                &path.to_string_lossy(),
+                InputFormat::Csv,
+                &Schema::default(),
+                columns,
+            )?;
+            for _ in 0..skip {
+                decoder.next_row()?;
+            }
+
+            // A new decoder resumed at the offset reads the rows the first one would.
+            let offset = decoder.position();
+            let mut decoder = open(
+                &path.to_string_lossy(),
+                InputFormat::Csv,
+                &Schema::default(),
+                columns,
+            )?;
+            decoder.seek(offset)?;
+
+            let rows = std::iter::from_fn(|| decoder.next_row().transpose())
+                .map(|row| row.map(|row| (row.line, row.fields.as_slice().to_string())))
+                .collect();
+            rows
+        };
+
+        for skip in [1, 2] {
+            let expected = rows(&plain, skip)?;
+            assert_eq!(expected.len(), 3 - skip);
+            assert_eq!(rows(&gz, skip)?, expected);
+            assert_eq!(rows(&zst, skip)?, expected);
+        }
+
+        // A stream can only resume ahead of where it is.
+        let mut decoder = open(
+            &gz.to_string_lossy(),
+            InputFormat::Csv,
+            &Schema::default(),
+            columns,
+        )?;
+        decoder.next_row()?;
+        let offset = decoder.position();
+        decoder.next_row()?;
+        assert!(decoder.seek(offset).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_mapping() -> Result<(), EngineError> {
+        let dir = TempDir::new("schema");
+        let columns: &[&str] = &["type", "client", "tx", "amount", "currency"];
+
+        let read_all = |name: &str, text: &str, schema: &Schema| {
+            let path = dir.join(name);
+            std::fs::write(&path, text).unwrap();
+            let format = InputFormat::from_path(&path).unwrap();
+            let mut decoder = open(&path.to_string_lossy(), format, schema, columns)?;
+
+            std::iter::from_fn(|| decoder.next_row().transpose())
+                .map(|row| {
+                    row.map(|row| (row.line, row.fields.iter().collect::<Vec<_>>().join(",")))
+                })
+                .collect::<Result<Vec<_>, EngineError>>()
+        };
+        let expected = |first_line: u64| {
+            vec![
+                (first_line, "deposit,1,1,2.5,".to_string()),
+                (first_line + 1, "withdrawal,1,2,1,EUR".to_string()),
+            ]
+        };
+
+        // Unmapped columns are found by name, whatever their order, case or padding.
+        assert_eq!(
+            read_all(
+                "named.csv",
+                "Reference, Amount ,TYPE,client\n1,2.5,deposit,1\n2,1,withdrawal,1,EUR\n",
+                &Schema {
+                    columns: BTreeMap::from([
+                        (
+                            "tx".to_string(),
+                            ColumnSource::Name("reference".to_string())
+                        ),
+                        ("currency".to_string(), ColumnSource::Position(5)),
+                    ]),
+                    ..Schema::default()
+                },
+            )?,
+            expected(2)
+        );
+
+        let headerless: Schema = serde_json::from_str(
+            r#"{"headers": false, "delimiter": ";", "columns": {"tx": 1, "type": 2, "client": 3}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_all(
+                "headerless.csv",
+                "1;deposit;1;2.5\n2;withdrawal;1;1;EUR\n",
+                &headerless
+            )?,
+            expected(1)
+        );
+
+        assert_eq!(
+            read_all(
+                "keys.jsonl",
+                "{\"id\":1,\"type\":\"deposit\",\"client\":1,\"amount\":2.5}\n\
+                 {\"id\":2,\"type\":\"withdrawal\",\"client\":1,\"amount\":1,\"currency\":\"EUR\"}\n",
+                &Schema {
+                    columns: BTreeMap::from([("tx".to_string(), ColumnSource::Name("id".to_string()))]),
+                    ..Schema::default()
+                },
+            )?,
+            expected(1)
+        );
+
+        // A file without a required column is refused before any row is read.
+        assert_eq!(
+            read_all(
+                "missing.csv",
+                "type,client,amount\ndeposit,1,2.5\n",
+                &Schema::default()
+            ),
+            Err(EngineError::CsvFileError("Missing column: tx".to_string()))
+        );
+        assert!(read_all("keys.jsonl", "", &headerless).is_err());
+
+        assert_eq!(
+            "amount=4".parse::<ColumnMapping>()?,
+            ColumnMapping {
+                column: "amount".to_string(),
+                source: ColumnSource::Position(4)
+            }
+        );
+        assert!(headerless.validate(columns).is_ok());
+        for invalid in [
+            r#"{"columns": {"kind": 1}}"#,
+            r#"{"columns": {"tx": 0}}"#,
+            r#"{"delimiter": "§"}"#,
+        ] {
+            let schema: Schema = serde_json::from_str(invalid).unwrap();
+            assert!(schema.validate(columns).is_err());
+        }
+
+        Ok(())
+    }
+}
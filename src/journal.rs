@@ -0,0 +1,331 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{amount::Amount, currency::Currency, transaction::TransactionType, EngineError};
+
+/// Ledger accounts a client's money can sit in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Account {
+    /// Funds the client can withdraw.
+    ClientAvailable,
+    /// Funds frozen by an open dispute.
+    ClientHeld,
+    /// Money entering or leaving the system through deposits and withdrawals.
+    ExternalFunding,
+    /// Money reversed out of the system by chargebacks.
+    ChargebackLoss,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    pub account: Account,
+    pub side: Side,
+    pub amount: Amount,
+}
+
+/// One engine operation, recorded as postings that must balance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub tx_id: u32,
+    pub tx_type: TransactionType,
+    pub currency: Currency,
+    pub postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    /// The double-entry postings of an accepted operation: a single transfer from the
+    /// debited account to the credited one.
+    fn for_operation(
+        tx_id: u32,
+        tx_type: TransactionType,
+        currency: Currency,
+        amount: Amount,
+    ) -> Result<Self, EngineError> {
+        let (debit, credit) = match tx_type {
+            TransactionType::Deposit => (Account::ExternalFunding, Account::ClientAvailable),
+            TransactionType::Withdrawal => (Account::ClientAvailable, Account::ExternalFunding),
+            TransactionType::Dispute => (Account::ClientAvailable, Account::ClientHeld),
+            TransactionType::Resolve => (Account::ClientHeld, Account::ClientAvailable),
+            TransactionType::ChargeBack => (Account::ClientHeld, Account::ChargebackLoss),
+            _ => {
+                return Err(EngineError::JournalError(format!(
+                    "TX {tx_id} {tx_type} moves no money"
+                )))
+            }
+        };
+
+        Ok(Self::transfer(
+            tx_id, tx_type, currency, amount, debit, credit,
+        ))
+    }
+
+    /// The postings of a dispute step on a withdrawal: the disputed amount comes back from
+    /// external funding into held, and either returns there on resolve or is released to the
+    /// client on chargeback.
+    fn for_withdrawal_dispute(
+        tx_id: u32,
+        tx_type: TransactionType,
+        currency: Currency,
+        amount: Amount,
+    ) -> Self {
+        let (debit, credit) = match tx_type {
+            TransactionType::Resolve => (Account::ClientHeld, Account::ExternalFunding),
+            TransactionType::ChargeBack => (Account::ClientHeld, Account::ClientAvailable),
+            _ => (Account::ExternalFunding, Account::ClientHeld),
+        };
+
+        Self::transfer(tx_id, tx_type, currency, amount, debit, credit)
+    }
+
+    fn transfer(
+        tx_id: u32,
+        tx_type: TransactionType,
+        currency: Currency,
+        amount: Amount,
+        debit: Account,
+        credit: Account,
+    ) -> Self {
+        JournalEntry {
+            tx_id,
+            tx_type,
+            currency,
+            postings: vec![
+                Posting {
+                    account: debit,
+                    side: Side::Debit,
+                    amount,
+                },
+                Posting {
+                    account: credit,
+                    side: Side::Credit,
+                    amount,
+                },
+            ],
+        }
+    }
+
+    fn is_balanced(&self) -> Result<bool, EngineError> {
+        let mut debits = Amount::ZERO;
+        let mut credits = Amount::ZERO;
+
+        for posting in &self.postings {
+            match posting.side {
+                Side::Debit => debits = debits.checked_add(posting.amount)?,
+                Side::Credit => credits = credits.checked_add(posting.amount)?,
+            }
+        }
+
+        Ok(debits == credits)
+    }
+}
+
+/// A client's sub-ledger. Account balances are kept credit-normal, so the client accounts
+/// read the same way as the matching [`ClientSummary`](crate::client::ClientSummary) fields.
+#[derive(Debug, Default, Clone)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+    balances: BTreeMap<(Currency, Account), Amount>,
+    /// Entries dropped by [`Journal::compact`] so far.
+    compacted: usize,
+}
+
+/// Where a journal stood, to [roll it back](Journal::roll_back) to.
+#[derive(Debug, Clone)]
+pub(crate) struct JournalMark {
+    /// Entries posted before it, including compacted ones.
+    posted: usize,
+    balances: BTreeMap<(Currency, Account), Amount>,
+}
+
+impl Journal {
+    /// A journal starting from previously saved account balances, with no entries.
+    pub(crate) fn from_balances(
+        balances: impl IntoIterator<Item = ((Currency, Account), Amount)>,
+    ) -> Self {
+        Journal {
+            entries: Vec::new(),
+            balances: balances.into_iter().collect(),
+            compacted: 0,
+        }
+    }
+
+    /// Current balance of every account that was ever posted to.
+    pub fn balances(&self) -> impl Iterator<Item = ((Currency, Account), Amount)> + '_ {
+        self.balances.iter().map(|(key, balance)| (*key, *balance))
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Drops the entries to save memory. The balances, and the books they keep, stay.
+    pub fn compact(&mut self) {
+        self.compacted += self.entries.len();
+        self.entries = Vec::new();
+    }
+
+    pub(crate) fn mark(&self) -> JournalMark {
+        JournalMark {
+            posted: self.compacted + self.entries.len(),
+            balances: self.balances.clone(),
+        }
+    }
+
+    /// Drops every entry posted since `mark` and restores the balances it saw. Entries
+    /// compacted in between are gone already.
+    pub(crate) fn roll_back(&mut self, mark: JournalMark) {
+        self.entries
+            .truncate(mark.posted.saturating_sub(self.compacted));
+        self.balances = mark.balances;
+    }
+
+    pub fn balance(&self, currency: Currency, account: Account) -> Amount {
+        self.balances
+            .get(&(currency, account))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Records an accepted operation moving `amount` in `currency`.
+    pub fn record(
+        &mut self,
+        tx_id: u32,
+        tx_type: TransactionType,
+        currency: Currency,
+        amount: Amount,
+    ) -> Result<(), EngineError> {
+        self.post(JournalEntry::for_operation(
+            tx_id, tx_type, currency, amount,
+        )?)
+    }
+
+    /// Records an accepted dispute, resolve or chargeback of a `disputed_type` transaction.
+    pub fn record_dispute(
+        &mut self,
+        tx_id: u32,
+        tx_type: TransactionType,
+        disputed_type: TransactionType,
+        currency: Currency,
+        amount: Amount,
+    ) -> Result<(), EngineError> {
+        match disputed_type {
+            TransactionType::Withdrawal => self.post(JournalEntry::for_withdrawal_dispute(
+                tx_id, tx_type, currency, amount,
+            )),
+            _ => self.record(tx_id, tx_type, currency, amount),
+        }
+    }
+
+    pub fn post(&mut self, entry: JournalEntry) -> Result<(), EngineError> {
+        if !entry.is_balanced()? {
+            return Err(EngineError::JournalError(format!(
+                "Unbalanced entry for TX {}",
+                entry.tx_id
+            )));
+        }
+
+        // Compute every new balance first so a failed entry leaves the journal untouched.
+        let mut updated = Vec::with_capacity(entry.postings.len());
+        for posting in &entry.postings {
+            let key = (entry.currency, posting.account);
+            let current = updated
+                .iter()
+                .rev()
+                .find(|(k, _)| *k == key)
+                .map(|(_, balance)| *balance)
+                .unwrap_or_else(|| self.balance(entry.currency, posting.account));
+
+            let balance = match posting.side {
+                Side::Credit => current.checked_add(posting.amount)?,
+                Side::Debit => current.checked_sub(posting.amount)?,
+            };
+
+            updated.push((key, balance));
+        }
+
+        self.balances.extend(updated);
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    /// Asserts that the sub-ledger's accounts sum to zero in every currency.
+    pub fn verify(&self) -> Result<(), EngineError> {
+        let mut sums: BTreeMap<Currency, Amount> = BTreeMap::new();
+
+        for ((currency, _), balance) in &self.balances {
+            let sum = sums.entry(*currency).or_default();
+            *sum = sum.checked_add(*balance)?;
+        }
+
+        if let Some((currency, sum)) = sums.iter().find(|(_, sum)| **sum != Amount::ZERO) {
+            return Err(EngineError::JournalError(format!(
+                "Books out of balance by {sum} {currency}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_balances() -> Result<(), EngineError> {
+        let usd = Currency::default();
+        let mut journal = Journal::default();
+
+        journal.record(1, TransactionType::Deposit, usd, "3.0".parse()?)?;
+        journal.record(2, TransactionType::Withdrawal, usd, "1.0".parse()?)?;
+        journal.record(1, TransactionType::Dispute, usd, "2.0".parse()?)?;
+        journal.record(1, TransactionType::ChargeBack, usd, "2.0".parse()?)?;
+
+        assert_eq!(journal.entries().len(), 4);
+        assert_eq!(journal.balance(usd, Account::ClientAvailable), Amount::ZERO);
+        assert_eq!(journal.balance(usd, Account::ClientHeld), Amount::ZERO);
+        assert_eq!(
+            journal.balance(usd, Account::ExternalFunding),
+            "-2.0".parse()?
+        );
+        assert_eq!(
+            journal.balance(usd, Account::ChargebackLoss),
+            "2.0".parse()?
+        );
+
+        journal.verify()
+    }
+
+    #[test]
+    fn test_unbalanced_entry() -> Result<(), EngineError> {
+        let mut journal = Journal::default();
+
+        let entry = JournalEntry {
+            tx_id: 1,
+            tx_type: TransactionType::Deposit,
+            currency: Currency::default(),
+            postings: vec![Posting {
+                account: Account::ClientAvailable,
+                side: Side::Credit,
+                amount: "1.0".parse()?,
+            }],
+        };
+
+        assert_eq!(
+            journal.post(entry),
+            Err(EngineError::JournalError(
+                "Unbalanced entry for TX 1".to_string()
+            ))
+        );
+        assert!(journal.entries().is_empty());
+
+        Ok(())
+    }
+}
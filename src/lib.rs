@@ -1,23 +1,81 @@
+use admin::AuditWriter;
 use client::Client;
-use std::{collections::HashMap, fmt::Display, sync::Arc};
-use tokio::sync::RwLock;
+use config::{DisputePolicy, EngineConfig};
+use event_log::EventLog;
+use rejects::{QuarantineWriter, RejectsWriter};
+use shard::ClientShards;
+use std::{collections::HashMap, fmt::Display, path::PathBuf, sync::Arc};
+use tokio::sync::{Mutex, RwLock};
+use transaction::Transaction;
+use tx_store::TxSpill;
+use wal::Wal;
 
+pub mod admin;
+pub mod amount;
+pub mod api;
 pub mod client;
+pub mod config;
+pub mod currency;
+pub mod dispute;
+pub mod event_log;
+pub mod input;
+pub mod journal;
+pub mod processor;
+pub mod rejects;
+pub mod server;
+pub mod shard;
+pub mod snapshot;
 pub mod transaction;
+pub mod tx_index;
+pub mod tx_store;
+pub mod wal;
+pub mod watcher;
+
+#[cfg(test)]
+mod fixtures;
 
 pub type EngineState = Arc<AppState>;
 
-#[derive(Debug, PartialEq)]
+/// Requests handled, in arrival order, by the engine's processing loop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    /// A CSV file is available for processing.
+    ProcessCsv(String),
+    /// An admin CSV file is available for processing, see [`processor::process_admin_csv`].
+    ProcessAdmin(String),
+    /// A file in the watched drop directory is ready; it is archived once processed.
+    ProcessDropped(PathBuf),
+    /// Write the current client summary.
+    EmitSummary,
+}
+
+/// Events waiting for the processing loop. Once it is full, producers wait for it to catch up.
+pub const EVENT_QUEUE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EngineError {
     InsufficientFunds,
+    AmountOverflow,
+    InvalidAmount(String),
+    InvalidCurrency(String),
     InvalidTransaction(String),
     DuplicateTransaction(String),
+    TxOwnerMismatch(String),
     AccountLocked,
+    AccountInDeficit,
+    AccountFrozen,
+    AccountClosed,
+    AdminError(String),
     DisputeError(String),
     ResolveError(String),
     ChargeBackError(String),
+    DisputeWindowExpired(String),
+    JournalError(String),
     CsvFileError(String),
+    SnapshotError(String),
     OutputError(String),
+    WalError(String),
+    BatchDiscarded(String),
     OtherError(String),
 }
 
@@ -25,19 +83,129 @@ impl Display for EngineError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             EngineError::InsufficientFunds => write!(f, "Insufficient funds"),
+            EngineError::AmountOverflow => write!(f, "Amount overflow"),
+            EngineError::InvalidAmount(msg) => write!(f, "Invalid amount: {msg}"),
+            EngineError::InvalidCurrency(msg) => write!(f, "Invalid currency: {msg}"),
             EngineError::InvalidTransaction(msg) => write!(f, "Invalid Tx: {msg}"),
             EngineError::DuplicateTransaction(msg) => write!(f, "Duplicate Tx: {msg}"),
+            EngineError::TxOwnerMismatch(msg) => write!(f, "Tx owner mismatch: {msg}"),
             EngineError::AccountLocked => write!(f, "Account Locked"),
+            EngineError::AccountInDeficit => write!(f, "Account in deficit"),
+            EngineError::AccountFrozen => write!(f, "Account frozen"),
+            EngineError::AccountClosed => write!(f, "Account closed"),
+            EngineError::AdminError(msg) => write!(f, "Admin Error: {msg}"),
             EngineError::DisputeError(msg) => write!(f, "Dispute Error: {msg}"),
             EngineError::ResolveError(msg) => write!(f, "Resolve Error: {msg}"),
             EngineError::ChargeBackError(msg) => write!(f, "Chargeback Error: {msg}"),
+            EngineError::DisputeWindowExpired(msg) => write!(f, "Dispute window expired: {msg}"),
+            EngineError::JournalError(msg) => write!(f, "Journal Error: {msg}"),
             EngineError::CsvFileError(msg) => write!(f, "CSV Error: {msg}"),
+            EngineError::SnapshotError(msg) => write!(f, "Snapshot Error: {msg}"),
             EngineError::OutputError(msg) => write!(f, "Output Error: {msg}"),
+            EngineError::WalError(msg) => write!(f, "WAL Error: {msg}"),
+            EngineError::BatchDiscarded(msg) => write!(f, "Batch discarded: {msg}"),
             EngineError::OtherError(msg) => write!(f, "{msg}"),
         }
     }
 }
 
+impl EngineError {
+    /// Machine-readable identifier of the error kind. Codes are part of the rejects report
+    /// format and never change once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::InsufficientFunds => "insufficient_funds",
+            EngineError::AmountOverflow => "amount_overflow",
+            EngineError::InvalidAmount(_) => "invalid_amount",
+            EngineError::InvalidCurrency(_) => "invalid_currency",
+            EngineError::InvalidTransaction(_) => "invalid_transaction",
+            EngineError::DuplicateTransaction(_) => "duplicate_transaction",
+            EngineError::TxOwnerMismatch(_) => "tx_owner_mismatch",
+            EngineError::AccountLocked => "account_locked",
+            EngineError::AccountInDeficit => "account_in_deficit",
+            EngineError::AccountFrozen => "account_frozen",
+            EngineError::AccountClosed => "account_closed",
+            EngineError::AdminError(_) => "admin_error",
+            EngineError::DisputeError(_) => "dispute_error",
+            EngineError::ResolveError(_) => "resolve_error",
+            EngineError::ChargeBackError(_) => "chargeback_error",
+            EngineError::DisputeWindowExpired(_) => "dispute_window_expired",
+            EngineError::JournalError(_) => "journal_error",
+            EngineError::CsvFileError(_) => "csv_file_error",
+            EngineError::SnapshotError(_) => "snapshot_error",
+            EngineError::OutputError(_) => "output_error",
+            EngineError::WalError(_) => "wal_error",
+            EngineError::BatchDiscarded(_) => "batch_discarded",
+            EngineError::OtherError(_) => "other_error",
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+// Locks are always taken in field order: ingest, client_map (shards in index order), event_log,
+// wal, rejects, quarantine, audit.
 pub struct AppState {
-    pub client_map: RwLock<HashMap<u16, Client>>, // map: client_id -> client
+    /// Taken by every file before its rows, exclusively by one that may abort so it has the
+    /// engine to itself until it is committed or discarded. Files that can't abort share it and
+    /// take turns a batch of rows at a time. Anything that needs the state between files, like
+    /// a snapshot, takes it shared.
+    pub ingest: RwLock<()>,
+    pub client_map: ClientShards,
+    pub event_log: RwLock<EventLog>,
+    /// With a write-ahead log attached, the event log only keeps the events of the file being
+    /// processed, as the write-ahead log has the rest, see [`wal::replay`].
+    pub wal: Mutex<Option<Wal>>,
+    pub rejects: Mutex<Option<RejectsWriter>>,
+    pub quarantine: Mutex<Option<QuarantineWriter>>,
+    pub audit: Mutex<Option<AuditWriter>>,
+    /// Where older transactions go once the clients keep too many in memory, see
+    /// [`TxSpill`]. With a spill file attached, the event log also only keeps the events of
+    /// the file being processed.
+    pub tx_spill: Option<Arc<TxSpill>>,
+    pub config: EngineConfig,
+}
+
+impl AppState {
+    pub fn new(config: EngineConfig) -> Self {
+        AppState {
+            ingest: RwLock::new(()),
+            client_map: ClientShards::new(config.shards),
+            event_log: RwLock::new(EventLog::default()),
+            wal: Mutex::new(None),
+            rejects: Mutex::new(None),
+            quarantine: Mutex::new(None),
+            audit: Mutex::new(None),
+            tx_spill: None,
+            config,
+        }
+    }
+
+    /// Checks every client's books, see [`Client::verify`].
+    pub async fn verify(&self) -> Result<(), EngineError> {
+        // A file being staged has posted to the journals what it has not committed yet.
+        let _ingest = self.ingest.read().await;
+        let client_map = self.client_map.read().await;
+
+        for client in client_map.values() {
+            client.verify()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Applies a transaction to its client, inserting a default client if none exists.
+///
+/// The transaction must have been admitted to the transaction index first, see
+/// [`TxIndex::admit`](tx_index::TxIndex::admit).
+pub fn apply_transaction(
+    client_map: &mut HashMap<u16, Client>,
+    transaction: &Transaction,
+    disputes: &DisputePolicy,
+) -> Result<(), EngineError> {
+    client_map
+        .entry(transaction.client_id)
+        .or_insert(Client::new(transaction.client_id))
+        .execute(transaction, disputes)
 }
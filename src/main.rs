@@ -1,140 +1,625 @@
-use csv::{Reader, StringRecord, Writer};
-use std::collections::HashMap;
-use std::env;
-use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-use tx_engine::client::Client;
+use clap::Parser;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::mpsc;
 use tx_engine::{
-    client::ClientSummary,
-    transaction::{Transaction, TransactionRecord, TransactionType},
-    AppState, EngineError, EngineState,
+    admin::AuditWriter,
+    amount::RoundingMode,
+    api,
+    config::{
+        DeficitMode, DisputePolicy, DisputeWindow, EngineConfig, ErrorAction, ErrorPolicy,
+        OutputMode,
+    },
+    currency::Currency,
+    input::{self, ColumnMapping, InputFormat, Schema},
+    processor::{client_summary_csv, deficit_report_csv, process_admin_csv, process_csv},
+    rejects::{QuarantineWriter, RejectsFormat, RejectsWriter},
+    server::{ListenAddress, Server},
+    snapshot,
+    transaction::TransactionRecord,
+    tx_store::{TxSpill, HOT_TX_BYTES},
+    wal,
+    watcher::{self, DropDirectory, ReadyConvention},
+    AppState, EngineError, EngineEvent, EngineState, EVENT_QUEUE,
 };
 
-async fn process_csv(path: String, state: EngineState) -> Result<(), EngineError> {
-    let mut rdr = Reader::from_path(path)
-        .map_err(|_| EngineError::CsvFileError(String::from("Invalid CSV file")))?;
+#[derive(Parser)]
+#[command(about = "Toy payments engine")]
+struct Args {
+    /// Transaction files to process, in order: CSV or JSON Lines, plain or compressed with
+    /// gzip or zstd. `-` reads stdin.
+    #[arg(required_unless_present_any = ["daemon", "admin"])]
+    paths: Vec<String>,
 
-    let mut client_map = state.client_map.write().await;
+    /// Admin CSV files of unlock, freeze, unfreeze and close operations, processed in order
+    /// after the transactions files.
+    #[arg(long, value_name = "PATH")]
+    admin: Vec<String>,
 
-    for result in rdr.records() {
-        let record = result.map_err(|e| {
-            EngineError::InvalidTransaction(format!(
-                "Failed to fetch transaction record. {}",
-                e.to_string()
-            ))
-        })?;
+    /// JSON Lines file every admin operation is appended to, with its reason, operator and
+    /// outcome.
+    #[arg(long, value_name = "PATH")]
+    audit: Option<PathBuf>,
 
-        let trimmed_record: StringRecord = record.into_iter().map(|field| field.trim()).collect();
+    /// Keep running after the given files and process every path written to stdin, one per
+    /// line, until SIGTERM. Queued files are drained before exiting.
+    #[arg(long)]
+    daemon: bool,
 
-        // Making sure the entire file is valid.
-        // Stop processing the rest of the records if any record failed to deserialize.
-        let record: TransactionRecord = trimmed_record.deserialize(None).map_err(|e| {
-            EngineError::InvalidTransaction(format!(
-                "Failed to deserialize transaction record. {}",
-                e.to_string()
-            ))
-        })?;
+    /// In daemon mode, only write a summary on SIGUSR1 and at shutdown instead of after every
+    /// file.
+    #[arg(long, requires = "daemon")]
+    summary_on_demand: bool,
 
-        let transaction = Transaction::try_from(record)?;
-
-        // Insert a default client if none exists.
-        let client = client_map
-            .entry(transaction.client_id)
-            .or_insert(Client::new(transaction.client_id));
-
-        // Print any transaction error to stderr and process the remaining transactions.
-        if let Err(e) = match transaction.tx_type {
-            TransactionType::Deposit => client.deposit(&transaction),
-            TransactionType::Withdrawal => client.withdraw(&transaction),
-            TransactionType::Dispute => client.dispute(&transaction),
-            TransactionType::Resolve => client.resolve(&transaction),
-            TransactionType::ChargeBack => client.charge_back(&transaction),
-        } {
-            eprintln!("{}", e);
-        }
-    }
+    /// In daemon mode, also process every input file that becomes ready in this directory, then
+    /// move it into its `processed/` or `failed/` subdirectory.
+    #[arg(long, value_name = "DIR", requires = "daemon")]
+    watch: Option<PathBuf>,
 
-    Ok(())
+    /// In daemon mode, also accept line-delimited transactions from producers on this TCP
+    /// address, or Unix domain socket with `unix:PATH`, and reply to every line with a JSON
+    /// ack. Repeatable.
+    #[arg(long, value_name = "ADDRESS", requires = "daemon")]
+    listen: Vec<ListenAddress>,
+
+    /// In daemon mode, also serve an HTTP API on this TCP address to submit transactions and
+    /// query accounts.
+    #[arg(long, value_name = "ADDRESS", requires = "daemon")]
+    http: Option<String>,
+
+    /// How producers mark a dropped file as complete: rename (written under a hidden,
+    /// `.tmp` or `.part` name and renamed into place) or marker (a `<file>.done` file).
+    #[arg(long, default_value = "rename", requires = "watch")]
+    ready: ReadyConvention,
+
+    /// Poll the watched directory every this many milliseconds instead of relying on native
+    /// file system notifications.
+    #[arg(long, value_name = "MS", requires = "watch")]
+    poll_ms: Option<u64>,
+
+    /// How to treat amounts with more than four decimal places:
+    /// reject, half-even, half-up or truncate.
+    #[arg(long, default_value = "reject")]
+    rounding: RoundingMode,
+
+    /// Currency for rows without a currency column or value.
+    #[arg(long, default_value = "USD")]
+    default_currency: Currency,
+
+    /// Output one row per client (default currency only) or per (client, currency):
+    /// per-client or per-currency.
+    #[arg(long, default_value = "per-client")]
+    output: OutputMode,
+
+    /// Number of client shards, each applied by its own worker thread. Defaults to the number
+    /// of available cores; 1 applies every transaction on the thread reading the file.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    shards: Option<u16>,
+
+    /// Check that the journal balances and agrees with every client's summary before
+    /// writing the output.
+    #[arg(long)]
+    verify: bool,
+
+    /// Output the client state as it was right after this event sequence number, instead of
+    /// the final state. It is rebuilt from the snapshot, if it is not past that event, or from
+    /// scratch, by replaying the write-ahead log, or without one the events of this run.
+    #[arg(long, value_name = "SEQ")]
+    replay_until: Option<u64>,
+
+    /// Engine state file. Loaded at startup if it exists and rewritten after processing, so
+    /// balances and disputable transactions carry over between runs.
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+
+    /// Format of the input files: csv or jsonl (one JSON object per line). By default it
+    /// follows the file extension, .jsonl or .ndjson for JSON Lines and CSV otherwise.
+    #[arg(long, value_name = "FORMAT")]
+    input_format: Option<InputFormat>,
+
+    /// JSON file describing the layout of transactions files, e.g. `{"headers": false,
+    /// "delimiter": ";", "columns": {"tx": 1, "type": 2}}`. The flags below override it.
+    #[arg(long, value_name = "PATH")]
+    schema: Option<PathBuf>,
+
+    /// Where an engine column (type, client, tx, amount, currency or timestamp) is read from
+    /// in transactions files: a header name or JSON key, or a position counted from 1. Repeat
+    /// for every column that isn't found under its own name.
+    #[arg(long = "column", value_name = "COLUMN=SOURCE")]
+    columns: Vec<ColumnMapping>,
+
+    /// Transactions CSV files have no header row, so their columns are read by position.
+    #[arg(long)]
+    no_headers: bool,
+
+    /// Field delimiter of transactions CSV files.
+    #[arg(long, value_name = "CHAR")]
+    delimiter: Option<char>,
+
+    /// Write-ahead log file. Rows are logged before they are applied; on startup the log is
+    /// replayed on top of the snapshot and partially processed files resume where they stopped.
+    #[arg(long, value_name = "PATH")]
+    wal: Option<PathBuf>,
+
+    /// Append every rejected transaction, with its source line and an error code, to this file.
+    #[arg(long, value_name = "PATH")]
+    rejects: Option<PathBuf>,
+
+    /// What to do with rows that cannot be read as a transaction: abort (roll the whole file
+    /// back and stop), skip (report and continue) or quarantine (also copy the row to the
+    /// quarantine file).
+    #[arg(long, default_value = "abort")]
+    on_malformed: ErrorAction,
+
+    /// What to do with transactions a client rejects, e.g. for insufficient funds: abort, skip
+    /// or quarantine.
+    #[arg(long, default_value = "skip")]
+    on_rejected: ErrorAction,
+
+    /// Treat every file as one batch: commit it only if every row is valid and accepted,
+    /// otherwise report all of its failed rows and leave the engine as it was. Overrides
+    /// --on-malformed and --on-rejected.
+    #[arg(long)]
+    atomic: bool,
+
+    /// CSV file quarantined rows are appended to, in the input columns.
+    #[arg(
+        long,
+        value_name = "PATH",
+        required_if_eq_any = [("on_malformed", "quarantine"), ("on_rejected", "quarantine")]
+    )]
+    quarantine: Option<PathBuf>,
+
+    /// Format of the rejects file: csv or jsonl.
+    #[arg(long, default_value = "csv", requires = "rejects")]
+    rejects_format: RejectsFormat,
+
+    /// Allow a resolved transaction to be disputed again.
+    #[arg(long)]
+    allow_redispute: bool,
+
+    /// Allow withdrawals to be disputed: the disputed amount is held until a resolve takes it
+    /// back or a chargeback returns it to the client.
+    #[arg(long)]
+    allow_withdrawal_disputes: bool,
+
+    /// What a dispute does when the client has already spent the disputed funds: strict
+    /// (reject it for insufficient funds), allow (hold them anyway, taking available negative)
+    /// or freeze (also refuse the client's withdrawals while it is in deficit).
+    #[arg(long, default_value = "strict")]
+    deficit: DeficitMode,
+
+    /// How long a transaction can be disputed: a time with an s, m, h or d unit, e.g. 120d,
+    /// which needs every row to have a timestamp, or a bare number of rows.
+    /// Settled transactions past the window are dropped from memory.
+    #[arg(long, value_name = "DURATION")]
+    dispute_window: Option<DisputeWindow>,
+
+    /// CSV file listing the clients in deficit, rewritten with every summary.
+    #[arg(long, value_name = "PATH")]
+    deficit_report: Option<PathBuf>,
+
+    /// Memory for transaction history, in MB. Older transactions beyond it are spilled to a
+    /// file and read back when disputed. The event log then only keeps the file being
+    /// processed, so this cannot be combined with --replay-until.
+    #[arg(long, value_name = "MB", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "replay_until")]
+    tx_memory: Option<u64>,
+
+    /// Directory of the spill file. Defaults to the system temporary directory.
+    #[arg(long, value_name = "DIR", requires = "tx_memory")]
+    spill_dir: Option<PathBuf>,
 }
 
 pub async fn output_client_summary(state: EngineState) -> Result<(), EngineError> {
-    let client_map = state.client_map.read().await;
+    let data = client_summary_csv(&state).await?;
 
-    let mut summary_vec: Vec<&ClientSummary> =
-        client_map.values().map(|client| &client.summary).collect();
+    eprintln!();
+    println!("{data}");
 
-    summary_vec.sort_by(|a, b| a.get_client_id().partial_cmp(&b.get_client_id()).unwrap());
+    Ok(())
+}
 
-    eprintln!();
+/// Rebuilds the state right after event `until`: from the snapshot on disk if it was taken no
+/// later, or from scratch otherwise, plus the events after it in the write-ahead log if there
+/// is one, or in the event log.
+async fn replay(state: &EngineState, args: &Args, until: u64) -> Result<AppState, EngineError> {
+    let mut base = AppState::new(state.config.clone());
 
-    let mut csv_writer = Writer::from_writer(vec![]);
+    if let Some(path) = args.snapshot.as_ref().filter(|path| path.exists()) {
+        let mut snapshot = snapshot::load(path, state.config.clone())?;
 
-    for summary in summary_vec {
-        csv_writer.serialize(summary).map_err(|e| {
-            EngineError::OutputError(format!(
-                "Failed to serialize client record: {}",
-                e.to_string()
-            ))
+        if snapshot.event_log.get_mut().last_seq() <= until {
+            base = snapshot;
+        }
+    }
+
+    match &args.wal {
+        Some(path) => wal::replay(path, base, until),
+        None => state.event_log.read().await.replay(base, until),
+    }
+}
+
+/// Replays or verifies the state as requested, then writes the client summary to stdout.
+async fn emit_summary(state: &EngineState, args: &Args) -> Result<(), EngineError> {
+    let state = match args.replay_until {
+        Some(seq) => Arc::new(replay(state, args, seq).await?),
+        None => state.clone(),
+    };
+
+    if args.verify {
+        state.verify().await?;
+    }
+
+    if let Some(path) = &args.deficit_report {
+        let data = deficit_report_csv(&state).await?;
+        std::fs::write(path, data).map_err(|e| {
+            EngineError::OutputError(format!("Failed to write {}: {}", path.display(), e))
         })?;
     }
 
-    let data = String::from_utf8(
-        csv_writer
-            .into_inner()
-            .map_err(|e| EngineError::OutputError(format!("{}", e.to_string())))?,
-    )
-    .map_err(|e| EngineError::OutputError(format!("{}", e.to_string())))?;
+    output_client_summary(state).await
+}
 
-    println!("{data}");
+async fn handle_csv(
+    path: String,
+    admin: bool,
+    state: &EngineState,
+    args: &Args,
+) -> Result<(), EngineError> {
+    match admin {
+        true => process_admin_csv(path.clone(), state.clone()).await?,
+        false => process_csv(path.clone(), state.clone()).await?,
+    }
+
+    if args.atomic {
+        eprintln!("Committed {}", path);
+    }
+
+    if let Some(snapshot_path) = &args.snapshot {
+        snapshot::save(state, snapshot_path).await?;
+    }
 
     Ok(())
 }
 
-pub async fn on_process_csv(
-    mut process_csv_reciever: mpsc::UnboundedReceiver<String>,
+fn listen(kind: SignalKind, name: &str) -> Result<Signal, EngineError> {
+    signal(kind)
+        .map_err(|e| EngineError::OtherError(format!("Failed to listen for {}: {}", name, e)))
+}
+
+/// The signals that stop a daemon, listened for from startup so that one arriving during
+/// the first file still lets the queue drain.
+struct Shutdown {
+    terminate: Signal,
+    interrupt: Signal,
+}
+
+impl Shutdown {
+    fn listen() -> Result<Self, EngineError> {
+        Ok(Shutdown {
+            terminate: listen(SignalKind::terminate(), "SIGTERM")?,
+            interrupt: listen(SignalKind::interrupt(), "SIGINT")?,
+        })
+    }
+
+    /// Resolves on the next SIGTERM or SIGINT, or never without a daemon to stop.
+    async fn recv(shutdown: &mut Option<Self>) {
+        let Some(shutdown) = shutdown else {
+            return std::future::pending().await;
+        };
+
+        tokio::select! {
+            _ = shutdown.terminate.recv() => {}
+            _ = shutdown.interrupt.recv() => {}
+        }
+    }
+}
+
+/// Turns every SIGUSR1 into a summary request.
+async fn forward_summary_requests(mut sigusr1: Signal, sender: mpsc::Sender<EngineEvent>) {
+    while sigusr1.recv().await.is_some() {
+        if sender.send(EngineEvent::EmitSummary).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Queues every non-empty line of stdin as a file to process.
+///
+/// Runs on a plain thread: a blocking read on the runtime would keep it from shutting down
+/// while stdin is still open.
+fn forward_stdin_paths(sender: mpsc::Sender<EngineEvent>) {
+    for line in std::io::stdin().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to read stdin: {}", e);
+                break;
+            }
+        };
+
+        let path = line.trim();
+
+        if !path.is_empty()
+            && sender
+                .blocking_send(EngineEvent::ProcessCsv(path.to_string()))
+                .is_err()
+        {
+            break;
+        }
+    }
+}
+
+async fn on_process_csv(
+    mut process_csv_reciever: mpsc::Receiver<EngineEvent>,
+    mut shutdown: Option<Shutdown>,
     state: EngineState,
+    args: Args,
+    drop_dir: Option<Arc<DropDirectory>>,
 ) -> Result<(), EngineError> {
+    let mut draining = false;
+    // Whether the state changed since the last summary.
+    let mut dirty = false;
+    let mut failed_files = 0;
+
     loop {
-        if let Some(path) = process_csv_reciever.recv().await {
-            process_csv(path, state.clone()).await?;
-            output_client_summary(state).await?;
+        let event = tokio::select! {
+            event = process_csv_reciever.recv() => event,
+            _ = Shutdown::recv(&mut shutdown), if !draining => {
+                eprintln!("Shutting down after the queued files");
 
-            // Remove this break to handle multiple csv processing events when refactoring this
-            // binary.
-            break;
-        } else {
-            eprintln!("Warning: failed to handle csv processing event")
+                // Refuse new events but keep receiving the ones already queued.
+                process_csv_reciever.close();
+                draining = true;
+                continue;
+            }
+        };
+
+        match event {
+            Some(EngineEvent::ProcessCsv(path)) => {
+                let result = handle_csv(path.clone(), false, &state, &args).await;
+                dirty = true;
+
+                match result {
+                    // A bad file must not take the daemon down, and a discarded batch leaves
+                    // nothing behind to stop the next file.
+                    Err(e) if args.daemon || matches!(e, EngineError::BatchDiscarded(_)) => {
+                        eprintln!("Failed to process {}: {}", path, e);
+                        failed_files += 1;
+                    }
+                    result => result?,
+                }
+
+                if args.daemon && !args.summary_on_demand {
+                    emit_summary(&state, &args).await?;
+                    dirty = false;
+                }
+            }
+            Some(EngineEvent::ProcessAdmin(path)) => {
+                // A refused admin file is reported, the transactions files are unaffected.
+                if let Err(e) = handle_csv(path.clone(), true, &state, &args).await {
+                    eprintln!("Failed to process admin file {}: {}", path, e);
+                    failed_files += 1;
+                }
+                dirty = true;
+
+                if args.daemon && !args.summary_on_demand {
+                    emit_summary(&state, &args).await?;
+                    dirty = false;
+                }
+            }
+            Some(EngineEvent::ProcessDropped(path)) => {
+                let result =
+                    handle_csv(path.to_string_lossy().into_owned(), false, &state, &args).await;
+                dirty = true;
+
+                if let Err(e) = &result {
+                    eprintln!("Failed to process {}: {}", path.display(), e);
+                }
+
+                if let Some(drop_dir) = &drop_dir {
+                    if let Err(e) = drop_dir.archive(&state, &path, &result).await {
+                        eprintln!("{}", e);
+                    }
+                }
+
+                if !args.summary_on_demand {
+                    emit_summary(&state, &args).await?;
+                    dirty = false;
+                }
+            }
+            Some(EngineEvent::EmitSummary) => {
+                emit_summary(&state, &args).await?;
+                dirty = false;
+            }
+            None => break,
         }
     }
 
+    // Lines from producers and HTTP requests change the state without any event here.
+    let live = !args.listen.is_empty() || args.http.is_some();
+    if dirty || live {
+        emit_summary(&state, &args).await?;
+    }
+
+    if let (Some(snapshot_path), true) = (&args.snapshot, live) {
+        snapshot::save(&state, snapshot_path).await?;
+    }
+
+    if failed_files > 0 && !args.daemon {
+        return Err(EngineError::OtherError(format!(
+            "{failed_files} of the files could not be processed"
+        )));
+    }
+
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), EngineError> {
-    let args: Vec<String> = env::args().collect();
+    let args = Args::parse();
+
+    // In daemon mode stdin carries the paths to process.
+    if args.daemon && args.paths.iter().any(|path| path == input::STDIN) {
+        return Err(EngineError::OtherError(String::from(
+            "Stdin can't be read as transactions in daemon mode",
+        )));
+    }
 
-    if args.len() == 2 {
-        let (process_csv_sender, process_csv_receiver) = mpsc::unbounded_channel::<String>();
+    // Listening before any file is processed, as a signal nobody listens for ends the engine.
+    let (shutdown, sigusr1) = match args.daemon {
+        true => (
+            Some(Shutdown::listen()?),
+            Some(listen(SignalKind::user_defined1(), "SIGUSR1")?),
+        ),
+        false => (None, None),
+    };
 
-        let state = Arc::new(AppState {
-            client_map: RwLock::new(HashMap::new()),
-        });
+    // The given files are queued before the processing loop starts, so there is room for all
+    // of them.
+    let (process_csv_sender, process_csv_receiver) =
+        mpsc::channel::<EngineEvent>(EVENT_QUEUE.max(args.paths.len() + args.admin.len()));
 
-        // Triggering csv processing with "relative" csv filepath received as an argument
-        process_csv_sender.send(args[1].clone()).map_err(|e| {
-            EngineError::OtherError(format!(
-                "Failed to trigger processing event\n{}",
-                e.to_string()
-            ))
-        })?;
+    let mut schema = match &args.schema {
+        Some(path) => Schema::load(path)?,
+        None => Schema::default(),
+    };
+    schema.headers &= !args.no_headers;
+    schema.delimiter = args.delimiter.unwrap_or(schema.delimiter);
+    schema.columns.extend(
+        args.columns
+            .iter()
+            .map(|mapping| (mapping.column.clone(), mapping.source.clone())),
+    );
+    schema.validate(TransactionRecord::COLUMNS)?;
+
+    let config = EngineConfig {
+        rounding: args.rounding,
+        default_currency: args.default_currency,
+        output_mode: args.output,
+        shards: args.shards.map_or_else(
+            || std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+            usize::from,
+        ),
+        error_policy: ErrorPolicy {
+            malformed: args.on_malformed,
+            rejected: args.on_rejected,
+        },
+        atomic: args.atomic,
+        disputes: DisputePolicy {
+            allow_redispute: args.allow_redispute,
+            allow_withdrawal_disputes: args.allow_withdrawal_disputes,
+            deficit: args.deficit,
+            window: args.dispute_window,
+        },
+        input_format: args.input_format,
+        schema,
+    };
+
+    // Created first so the snapshot's transactions are restored to disk rather than memory.
+    let spill = match args.tx_memory {
+        Some(megabytes) => {
+            let dir = args.spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+            let hot_limit = megabytes as usize * 1024 * 1024 / HOT_TX_BYTES;
+            Some(TxSpill::create(&dir, hot_limit)?)
+        }
+        None => None,
+    };
+
+    let mut state = match (&args.snapshot, spill) {
+        (Some(path), Some(spill)) if path.exists() => snapshot::load_spilled(path, config, spill)?,
+        (Some(path), None) if path.exists() => snapshot::load(path, config)?,
+        (_, spill) => {
+            let mut state = AppState::new(config);
+            state.tx_spill = spill;
+            state
+        }
+    };
+
+    if let Some(path) = &args.wal {
+        wal::recover(path, &mut state)?;
+    }
+
+    // Attached after recovery so rows replayed from the log are not reported twice.
+    if let Some(path) = &args.rejects {
+        *state.rejects.get_mut() = Some(RejectsWriter::open(path, args.rejects_format)?);
+    }
+
+    if let Some(path) = &args.quarantine {
+        *state.quarantine.get_mut() = Some(QuarantineWriter::open(path)?);
+    }
 
-        tokio::spawn(on_process_csv(process_csv_receiver, state.clone()))
-            .await
-            .map_err(|e| EngineError::OtherError(e.to_string()))??;
+    if let Some(path) = &args.audit {
+        *state.audit.get_mut() = Some(AuditWriter::open(path)?);
+    }
+
+    let state = Arc::new(state);
+
+    // Triggering csv processing with "relative" csv filepaths received as arguments
+    for path in &args.paths {
+        process_csv_sender
+            .try_send(EngineEvent::ProcessCsv(path.clone()))
+            .map_err(|e| {
+                EngineError::OtherError(format!("Failed to trigger processing event\n{}", e))
+            })?;
+    }
+
+    for path in &args.admin {
+        process_csv_sender
+            .try_send(EngineEvent::ProcessAdmin(path.clone()))
+            .map_err(|e| {
+                EngineError::OtherError(format!("Failed to trigger processing event\n{}", e))
+            })?;
+    }
+
+    let drop_dir = args
+        .watch
+        .clone()
+        .map(|dir| Arc::new(DropDirectory::new(dir, args.ready)));
+
+    // Dropping the handle stops the watcher, so keep it for the lifetime of the engine.
+    let _watcher = match &drop_dir {
+        Some(drop_dir) => Some(watcher::watch(
+            drop_dir.clone(),
+            process_csv_sender.clone(),
+            args.poll_ms.map(Duration::from_millis),
+        )?),
+        None => None,
+    };
+
+    if !args.listen.is_empty() {
+        let server = Server::start(state.clone());
+
+        for address in &args.listen {
+            eprintln!("Listening on {}", server.listen(address).await?);
+        }
+    }
+
+    if let Some(address) = &args.http {
+        eprintln!(
+            "Serving HTTP on {}",
+            api::listen(state.clone(), address).await?
+        );
+    }
+
+    if let Some(sigusr1) = sigusr1 {
+        let stdin_sender = process_csv_sender.clone();
+        std::thread::spawn(move || forward_stdin_paths(stdin_sender));
+        tokio::spawn(forward_summary_requests(sigusr1, process_csv_sender));
     } else {
-        eprintln!("This program expects the csv filepath");
+        // Without producers the channel closes once the given files are processed.
+        drop(process_csv_sender);
     }
 
+    tokio::spawn(on_process_csv(
+        process_csv_receiver,
+        shutdown,
+        state.clone(),
+        args,
+        drop_dir,
+    ))
+    .await
+    .map_err(|e| EngineError::OtherError(e.to_string()))??;
+
     Ok(())
 }
@@ -0,0 +1,1803 @@
+use csv::{StringRecord, Writer, WriterBuilder};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, SyncSender},
+        Arc,
+    },
+};
+
+use crate::{
+    admin::{AdminRecord, AuditEntry, AuditWriter},
+    api::ClientView,
+    apply_transaction,
+    client::{Client, CurrencySummary},
+    config::{DisputePolicy, ErrorAction, ErrorPolicy, OutputMode},
+    event_log::{Event, EventLog, Outcome},
+    input::{self, DecodedRow, Decoder, InputFormat, Schema},
+    rejects::{QuarantineWriter, Reject, RejectsWriter},
+    shard::{Shard, StagedShard},
+    transaction::{Transaction, TransactionRecord},
+    tx_store::{spill_shard, TxSpill},
+    wal::Wal,
+    AppState, EngineError, EngineState,
+};
+
+/// Rows handed to a shard worker at a time, to keep channel traffic off the hot path.
+const SHARD_BATCH: usize = 1024;
+
+/// Batches a shard worker may have queued before the reader waits for it.
+const SHARD_QUEUE_DEPTH: usize = 64;
+
+/// Rows a file reads into the engine before other files and readers get a turn.
+const FILE_BATCH: usize = SHARD_BATCH * 64;
+
+/// Transactions a client refused, by event sequence number.
+type Rejected = Vec<(u64, EngineError)>;
+
+/// A row that failed, with the row itself for the quarantine file.
+struct FailedRow {
+    error: EngineError,
+    reject: Reject,
+    row: StringRecord,
+}
+
+/// What became of a row: its event, unless it was malformed, and why it failed if it did.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RowOutcome {
+    pub line: u64,
+    pub seq: Option<u64>,
+    pub result: Result<(), EngineError>,
+    /// The client an accepted row applied to, as it stood once the row's batch was applied.
+    pub client: Option<ClientView>,
+}
+
+impl RowOutcome {
+    fn from_event(event: &Event, client: Option<ClientView>) -> Self {
+        RowOutcome {
+            line: event.line,
+            seq: Some(event.seq),
+            result: match &event.outcome {
+                Outcome::Accepted => Ok(()),
+                Outcome::Rejected(e) => Err(e.clone()),
+            },
+            client,
+        }
+    }
+}
+
+/// How [`process_rows`] treats a source.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RowOptions {
+    /// Accept only admin operations, see [`process_admin_csv`].
+    pub admin: bool,
+    /// Skip the rows the write-ahead log has from a previous run. Sources that are a new
+    /// stream every time are read from the start instead.
+    pub resume: bool,
+    pub policy: ErrorPolicy,
+    pub atomic: bool,
+    /// Return the [outcome](RowOutcome) of every row.
+    pub outcomes: bool,
+}
+
+enum Row {
+    /// Logged and ready to apply.
+    Logged(u64, Transaction),
+    Malformed(FailedRow),
+    End,
+}
+
+/// Applies a transaction to `staged` if the file is staged, or straight to `shard` otherwise.
+fn apply_to(
+    shard: &mut Shard,
+    staged: &mut Option<StagedShard>,
+    transaction: &Transaction,
+    disputes: &DisputePolicy,
+) -> Result<(), EngineError> {
+    match staged {
+        Some(staged) => staged.apply(shard, transaction, disputes),
+        None => apply_transaction(shard, transaction, disputes),
+    }
+}
+
+/// Spills the transactions of the clients of `shard`, and of those `staged` created, see
+/// [`spill_shard`].
+fn spill_to(
+    shard: &mut Shard,
+    staged: &mut Option<StagedShard>,
+    limit: usize,
+    spill: &Arc<TxSpill>,
+) -> Result<(), EngineError> {
+    match staged {
+        Some(staged) => spill_shard(staged.clients_mut(shard), limit, spill),
+        None => spill_shard(shard.values_mut(), limit, spill),
+    }
+}
+
+/// Streams the transactions of a CSV file into the engine.
+///
+/// Rows are read, logged and numbered in file order. With a single shard they are applied on
+/// the reading thread; otherwise each shard is applied by its own worker thread, so a client's
+/// transactions keep their order while different clients are processed in parallel. The engine's
+/// locks are taken for a batch of rows at a time, so other files and readers get a turn in
+/// between.
+///
+/// Failed rows are handled by the configured [error policy](crate::config::ErrorPolicy). If
+/// the file may abort, its changes are [staged](StagedShard) and only committed once it
+/// completes, and no other file is processed in the meantime. When a row aborts the file, its
+/// changes are undone, its events and write-ahead log records discarded and the row's error is
+/// returned.
+///
+/// An [atomic](crate::config::EngineConfig::atomic) file is committed only if every row is
+/// valid and accepted. It is read to the end either way, so every failed row is reported.
+///
+/// With a write-ahead log attached, every row is logged before it is applied and a file that
+/// was partially processed before a crash resumes after its last logged row.
+///
+/// Admin operations are not accepted here, see [`process_admin_csv`].
+pub async fn process_csv(path: String, state: EngineState) -> Result<(), EngineError> {
+    process_source(path, state, false).await
+}
+
+/// Streams the operations of an admin CSV file into the engine, like [`process_csv`].
+///
+/// Rows have the `type, client, tx, reason, operator` columns of an [`AdminRecord`] and only
+/// admin operations are accepted. Every operation of a committed file, applied or rejected,
+/// is written to the audit trail if one is attached.
+pub async fn process_admin_csv(path: String, state: EngineState) -> Result<(), EngineError> {
+    process_source(path, state, true).await
+}
+
+async fn process_source(path: String, state: EngineState, admin: bool) -> Result<(), EngineError> {
+    let format = state
+        .config
+        .input_format
+        .or_else(|| InputFormat::from_path(Path::new(&path)))
+        .unwrap_or_default();
+    // The schema describes partner transaction files; admin files are always our own.
+    let admin_schema = Schema::default();
+    let (schema, columns) = match admin {
+        true => (&admin_schema, AdminRecord::COLUMNS),
+        false => (&state.config.schema, TransactionRecord::COLUMNS),
+    };
+    let decoder = input::open(&path, format, schema, columns)?;
+
+    let options = RowOptions {
+        admin,
+        // Stdin is a new stream every time.
+        resume: path != input::STDIN,
+        policy: state.config.error_policy,
+        atomic: state.config.atomic,
+        outcomes: false,
+    };
+
+    process_rows(&state, Arc::from(path), decoder, options)
+        .await
+        .map(|_| ())
+}
+
+/// Reads every row of `decoder` into the engine as [`process_csv`] describes, and returns what
+/// became of them, in line order, once they are committed if [asked](RowOptions::outcomes).
+pub(crate) async fn process_rows(
+    state: &EngineState,
+    source: Arc<str>,
+    mut decoder: Box<dyn Decoder>,
+    options: RowOptions,
+) -> Result<Vec<RowOutcome>, EngineError> {
+    let RowOptions {
+        admin,
+        resume,
+        atomic,
+        ..
+    } = options;
+    let policy = match atomic {
+        true => ErrorPolicy {
+            malformed: ErrorAction::Abort,
+            rejected: ErrorAction::Abort,
+        },
+        false => options.policy,
+    };
+    let staging = policy.aborts();
+
+    // A file that may abort has the engine to itself until it is committed or discarded, so
+    // discarding it restores exactly the state before it.
+    let (_shared, _exclusive) = match staging {
+        true => (None, Some(state.ingest.write().await)),
+        false => (Some(state.ingest.read().await), None),
+    };
+
+    if policy.quarantines() && state.quarantine.lock().await.is_none() {
+        return Err(EngineError::OtherError(String::from(
+            "The error policy quarantines rows but no quarantine file is set",
+        )));
+    }
+
+    // Where the file started, to discard it if it aborts.
+    let first_seq = state.event_log.read().await.last_seq();
+
+    let wal_batch = {
+        let mut wal = state.wal.lock().await;
+
+        // A source that can't resume is read from the start whatever was logged.
+        if let Some(wal) = wal.as_mut().filter(|_| !resume) {
+            if wal.offset(&source).is_some() {
+                wal.forget(&source)?;
+            }
+        }
+
+        // Skip the rows a previous run already applied.
+        if let Some(offset) = wal.as_ref().and_then(|wal| wal.offset(&source)) {
+            decoder.seek(offset)?;
+        }
+
+        match wal.as_mut().filter(|_| staging) {
+            Some(wal) => Some(wal.begin(&source)?),
+            None => None,
+        }
+    };
+
+    // Logs and returns the next row.
+    let mut next_row = |event_log: &mut EventLog,
+                        wal: &mut Option<Wal>|
+     -> Result<Row, EngineError> {
+        let Some(DecodedRow {
+            line,
+            fields: row,
+            error,
+        }) = decoder.next_row()?
+        else {
+            return Ok(Row::End);
+        };
+
+        let deserialize_error = |e: csv::Error| {
+            EngineError::InvalidTransaction(format!(
+                "Failed to deserialize transaction record. {}",
+                e
+            ))
+        };
+
+        let transaction = match (error, admin) {
+            (Some(e), _) => Err(e),
+            (None, true) => row
+                .deserialize::<AdminRecord>(None)
+                .map_err(deserialize_error)
+                .and_then(|parsed| Transaction::from_admin_record(parsed, &state.config)),
+            (None, false) => row
+                .deserialize::<TransactionRecord>(None)
+                .map_err(deserialize_error)
+                .and_then(|parsed| Transaction::from_record(parsed, &state.config)),
+        };
+
+        let mut transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                return Ok(Row::Malformed(FailedRow {
+                    error: e.clone(),
+                    reject: Reject::malformed(&source, line, &row, &e, policy.malformed),
+                    row,
+                }))
+            }
+        };
+
+        // The clock never goes back.
+        let seq = event_log.last_seq() + 1;
+        transaction.seq = seq;
+        transaction.time = transaction.time.map(|time| time.max(event_log.clock()));
+
+        if let Some(wal) = wal.as_mut() {
+            wal.append(seq, &source, line, decoder.position(), &transaction)?;
+        }
+
+        // Recorded as accepted until the transaction is applied.
+        let seq = event_log.append(source.clone(), line, transaction.clone(), Outcome::Accepted);
+
+        Ok(Row::Logged(seq, transaction))
+    };
+
+    // Set once a row aborts a file that is not atomic, so the reader and workers stop early.
+    let abort = AtomicBool::new(false);
+    let mut failed: Vec<FailedRow> = Vec::new();
+    let mut outcomes: Vec<RowOutcome> = Vec::new();
+
+    let disputes = &state.config.disputes;
+    let abort_on_reject = policy.rejected == ErrorAction::Abort && !atomic;
+
+    let shard_count = state.client_map.shard_count();
+    let mut staged_shards: Vec<Option<StagedShard>> = (0..shard_count)
+        .map(|_| staging.then(StagedShard::default))
+        .collect();
+    // IDs this file claimed, to release if it is discarded.
+    let mut claimed: Vec<u32> = Vec::new();
+    // Rows applied on the reading thread, to spill after every `SHARD_BATCH` of them.
+    let mut applied = 0;
+
+    // Each shard's share of the transactions kept in memory.
+    let spill = state
+        .tx_spill
+        .as_ref()
+        .map(|spill| (spill, spill.hot_limit().div_ceil(shard_count)));
+
+    // The file is read a batch of rows at a time, each under the engine's locks so its rows
+    // are applied in the order they were logged. Other files and readers get a turn between
+    // batches.
+    let mut result = Ok(());
+    let mut done = false;
+
+    while !done {
+        let mut client_map = state.client_map.write().await;
+        let mut event_log = state.event_log.write().await;
+        let mut wal = state.wal.lock().await;
+        let mut rejects = state.rejects.lock().await;
+        let mut quarantine = state.quarantine.lock().await;
+        let mut audit = state.audit.lock().await;
+
+        let batch_seq = event_log.last_seq();
+        let (tx_index, shards) = client_map.split_mut();
+        // Rows the transaction index refused, never handed to a client.
+        let mut refused: Rejected = Vec::new();
+
+        // Reads up to a batch of rows, handing every logged row the transaction index admits
+        // to `apply`, and returns whether the file has more. Rows are admitted here, in file
+        // order, so which client owns an ID does not depend on how the workers interleave.
+        let mut feed = |apply: &mut dyn FnMut(u64, Transaction)| -> Result<bool, EngineError> {
+            for _ in 0..FILE_BATCH {
+                if abort.load(Ordering::Relaxed) {
+                    return Ok(false);
+                }
+
+                match next_row(&mut event_log, &mut wal)? {
+                    Row::Logged(seq, transaction) => match tx_index.admit(&transaction) {
+                        Ok(new) => {
+                            if new && staging {
+                                claimed.push(transaction.tx_id);
+                            }
+                            apply(seq, transaction);
+                        }
+                        Err(e) => {
+                            abort.fetch_or(abort_on_reject, Ordering::Relaxed);
+                            refused.push((seq, e));
+                        }
+                    },
+                    Row::Malformed(failed_row) if policy.malformed == ErrorAction::Abort => {
+                        abort.store(!atomic, Ordering::Relaxed);
+                        failed.push(failed_row);
+                    }
+                    Row::Malformed(failed_row) => failed.push(failed_row),
+                    Row::End => return Ok(false),
+                }
+            }
+
+            Ok(!abort.load(Ordering::Relaxed))
+        };
+
+        let (more, mut rejected) = if shard_count == 1 {
+            let shard = shards.into_iter().next().expect("at least one shard");
+            let staged = &mut staged_shards[0];
+            let mut rejected = Vec::new();
+            let mut spilled = Ok(());
+
+            let more = feed(&mut |seq, transaction| {
+                if let Err(e) = apply_to(shard, staged, &transaction, disputes) {
+                    abort.fetch_or(abort_on_reject, Ordering::Relaxed);
+                    rejected.push((seq, e));
+                }
+
+                applied += 1;
+                if let Some((spill, limit)) = spill.filter(|_| applied % SHARD_BATCH == 0) {
+                    if spilled.is_ok() {
+                        spilled = spill_to(shard, staged, limit, spill);
+                    }
+                }
+            });
+
+            if let Some((spill, limit)) = spill {
+                spilled = spilled.and_then(|()| spill_to(shard, staged, limit, spill));
+            }
+
+            (more.and_then(|more| spilled.map(|()| more)), rejected)
+        } else {
+            apply_sharded(
+                shards.into_iter().zip(staged_shards.iter_mut()).collect(),
+                spill,
+                disputes,
+                abort_on_reject,
+                &abort,
+                |router| feed(&mut |seq, transaction| router.send(seq, transaction)),
+            )
+        };
+
+        done = !matches!(more, Ok(true));
+        if let Err(e) = more {
+            result = Err(e);
+        }
+
+        rejected.extend(refused);
+        rejected.sort_by_key(|(seq, _)| *seq);
+        for (seq, e) in rejected {
+            event_log.set_outcome(seq, Outcome::Rejected(e.clone()));
+
+            if let Some(event) = event_log.get(seq) {
+                failed.push(FailedRow {
+                    error: e,
+                    reject: Reject::from_event(event, policy.rejected).expect("event was rejected"),
+                    row: QuarantineWriter::transaction_row(&event.transaction),
+                });
+            }
+        }
+
+        // Read before another file may compact the log, which drops the events, and before
+        // another batch changes the clients.
+        if options.outcomes {
+            let default_currency = state.config.default_currency;
+
+            outcomes.extend(
+                (batch_seq + 1..=event_log.last_seq())
+                    .filter_map(|seq| event_log.get(seq))
+                    .map(|event| {
+                        let client = match event.outcome {
+                            Outcome::Accepted => client_map
+                                .get(event.transaction.client_id)
+                                .map(|client| ClientView::new(client, default_currency)),
+                            Outcome::Rejected(_) => None,
+                        };
+
+                        RowOutcome::from_event(event, client)
+                    }),
+            );
+        }
+
+        // A staged file is audited and reported once it is committed or discarded. Anything
+        // else is done with its rows after every batch, so memory doesn't grow with the file.
+        if !staging {
+            write_audit(&mut audit, &event_log, batch_seq)?;
+
+            failed.sort_by_key(|failed_row| failed_row.reject.line);
+            if options.outcomes {
+                outcomes.extend(malformed_outcomes(&failed));
+            }
+            report(failed.drain(..), &mut rejects, &mut quarantine)?;
+
+            // The write-ahead log keeps the history for replays.
+            if state.tx_spill.is_some() || wal.is_some() {
+                event_log.compact();
+            }
+        }
+
+        drop((client_map, event_log, wal, rejects, quarantine, audit));
+        tokio::task::yield_now().await;
+    }
+
+    let mut client_map = state.client_map.write().await;
+    let mut event_log = state.event_log.write().await;
+    let mut wal = state.wal.lock().await;
+    let mut rejects = state.rejects.lock().await;
+    let mut quarantine = state.quarantine.lock().await;
+    let mut audit = state.audit.lock().await;
+
+    // Rows are collected from the reader and the workers, report them in file order.
+    failed.sort_by_key(|failed_row| failed_row.reject.line);
+
+    // The first row that aborts the file wins over any later one and over a reading error.
+    let mut result = match failed
+        .iter()
+        .find(|failed_row| failed_row.reject.action == ErrorAction::Abort)
+    {
+        Some(failed_row) if atomic => Err(EngineError::BatchDiscarded(format!(
+            "{} has {} failed row(s), the first on line {}: {}",
+            source,
+            failed.len(),
+            failed_row.reject.line,
+            failed_row.error
+        ))),
+        Some(failed_row) => Err(failed_row.error.clone()),
+        None => result,
+    };
+
+    // Unstaged files never abort on purpose, and a reading error keeps the rows before it like
+    // any other interrupted file.
+    let discard = result.is_err() && staging;
+
+    if discard {
+        event_log.truncate(first_seq);
+
+        for (shard, staged) in client_map.shards_mut().zip(staged_shards) {
+            if let Some(staged) = staged {
+                staged.discard(shard)?;
+            }
+        }
+
+        let tx_index = client_map.tx_index_mut();
+        for tx_id in claimed {
+            tx_index.release(tx_id);
+        }
+
+        if let (Some(wal), Some(batch)) = (wal.as_mut(), wal_batch) {
+            wal.abort(batch)?;
+        }
+
+        // Rows that did not abort a partially read file were discarded with it.
+        if !atomic {
+            failed.retain(|failed_row| failed_row.reject.action == ErrorAction::Abort);
+            failed.truncate(1);
+        }
+    } else {
+        if let (Some(wal), Some(batch)) = (wal.as_mut(), wal_batch) {
+            wal.commit(batch)?;
+        }
+
+        for (shard, staged) in client_map.shards_mut().zip(staged_shards) {
+            if let Some(staged) = staged {
+                staged.commit(shard);
+            }
+        }
+
+        // Transactions past the dispute window are dropped before any more are spilled.
+        if let Some(cutoff) = disputes
+            .window
+            .and_then(|window| window.cutoff(event_log.last_seq(), event_log.clock()))
+        {
+            result = result.and_then(|()| client_map.expire(cutoff));
+        }
+
+        if staging {
+            write_audit(&mut audit, &event_log, first_seq)?;
+        }
+    }
+
+    if result.is_ok() && options.outcomes {
+        outcomes.extend(malformed_outcomes(&failed));
+        outcomes.sort_by_key(|outcome| outcome.line);
+    }
+
+    report(failed.into_iter(), &mut rejects, &mut quarantine)?;
+
+    if let Some(wal) = wal.as_mut() {
+        wal.sync()?;
+    }
+
+    // The write-ahead log keeps the history for replays.
+    if state.tx_spill.is_some() || wal.is_some() {
+        event_log.compact();
+    }
+
+    result.map(|()| outcomes)
+}
+
+/// The outcomes of the malformed rows among `failed`, which have no event.
+fn malformed_outcomes(failed: &[FailedRow]) -> impl Iterator<Item = RowOutcome> + '_ {
+    failed
+        .iter()
+        .filter(|failed_row| failed_row.reject.seq.is_none())
+        .map(|failed_row| RowOutcome {
+            line: failed_row.reject.line,
+            seq: None,
+            result: Err(failed_row.error.clone()),
+            client: None,
+        })
+}
+
+/// Reports failed rows on stderr, to the rejects report and to the quarantine file, as their
+/// action says.
+fn report(
+    failed: impl Iterator<Item = FailedRow>,
+    rejects: &mut Option<RejectsWriter>,
+    quarantine: &mut Option<QuarantineWriter>,
+) -> Result<(), EngineError> {
+    for failed_row in failed {
+        eprintln!("{}", failed_row.reject.message);
+
+        if let Some(rejects) = rejects.as_mut() {
+            rejects.write(&failed_row.reject)?;
+        }
+
+        if let (ErrorAction::Quarantine, Some(quarantine)) =
+            (failed_row.reject.action, quarantine.as_mut())
+        {
+            quarantine.write(&failed_row.row)?;
+        }
+    }
+
+    if let Some(rejects) = rejects.as_mut() {
+        rejects.flush()?;
+    }
+
+    if let Some(quarantine) = quarantine.as_mut() {
+        quarantine.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Writes the admin operations logged after `after_seq` to the audit trail, if one is attached.
+fn write_audit(
+    audit: &mut Option<AuditWriter>,
+    event_log: &EventLog,
+    after_seq: u64,
+) -> Result<(), EngineError> {
+    if let Some(audit) = audit.as_mut() {
+        for seq in after_seq + 1..=event_log.last_seq() {
+            if let Some(entry) = event_log.get(seq).and_then(AuditEntry::from_event) {
+                audit.write(&entry)?;
+            }
+        }
+
+        audit.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Routes transactions to the worker owning their client's shard.
+struct ShardRouter {
+    senders: Vec<SyncSender<Vec<(u64, Transaction)>>>,
+    batches: Vec<Vec<(u64, Transaction)>>,
+}
+
+impl ShardRouter {
+    fn send(&mut self, seq: u64, transaction: Transaction) {
+        let index = transaction.client_id as usize % self.senders.len();
+        let batch = &mut self.batches[index];
+
+        batch.push((seq, transaction));
+
+        if batch.len() == SHARD_BATCH {
+            self.flush(index);
+        }
+    }
+
+    fn flush(&mut self, index: usize) {
+        let batch = std::mem::replace(&mut self.batches[index], Vec::with_capacity(SHARD_BATCH));
+
+        // A worker only hangs up by panicking, which is re-raised when it is joined.
+        let _ = self.senders[index].send(batch);
+    }
+}
+
+/// Runs `feed` with a router to one worker thread per shard and waits for every routed
+/// transaction to be applied, to each shard's staged changes if it has them.
+///
+/// Returns the result of `feed` and the rejected transactions. With `abort_on_reject`, the
+/// first rejection sets `abort` and the workers apply nothing more. With `spill`, each worker
+/// spills its shard down to the given share of the memory budget after every batch.
+fn apply_sharded<T>(
+    shards: Vec<(&mut Shard, &mut Option<StagedShard>)>,
+    spill: Option<(&Arc<TxSpill>, usize)>,
+    disputes: &DisputePolicy,
+    abort_on_reject: bool,
+    abort: &AtomicBool,
+    feed: impl FnOnce(&mut ShardRouter) -> Result<T, EngineError>,
+) -> (Result<T, EngineError>, Rejected) {
+    std::thread::scope(|scope| {
+        let (senders, workers): (Vec<_>, Vec<_>) = shards
+            .into_iter()
+            .map(|(shard, staged)| {
+                let (sender, receiver) = sync_channel::<Vec<(u64, Transaction)>>(SHARD_QUEUE_DEPTH);
+
+                let worker = scope.spawn(move || {
+                    let mut rejected = Vec::new();
+                    let mut spilled = Ok(());
+
+                    for batch in receiver {
+                        for (seq, transaction) in batch {
+                            if abort.load(Ordering::Relaxed) {
+                                continue;
+                            }
+
+                            if let Err(e) = apply_to(shard, staged, &transaction, disputes) {
+                                abort.fetch_or(abort_on_reject, Ordering::Relaxed);
+                                rejected.push((seq, e));
+                            }
+                        }
+
+                        if let Some((spill, limit)) = spill {
+                            spilled = spilled.and_then(|()| spill_to(shard, staged, limit, spill));
+                        }
+                    }
+
+                    (rejected, spilled)
+                });
+
+                (sender, worker)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .unzip();
+
+        let mut router = ShardRouter {
+            batches: senders
+                .iter()
+                .map(|_| Vec::with_capacity(SHARD_BATCH))
+                .collect(),
+            senders,
+        };
+
+        // Rows routed before a failure were logged, so they are applied either way.
+        let mut result = feed(&mut router);
+
+        for index in 0..router.senders.len() {
+            router.flush(index);
+        }
+        drop(router);
+
+        let mut rejected = Vec::new();
+
+        for worker in workers {
+            match worker.join() {
+                Ok((worker_rejected, spilled)) => {
+                    rejected.extend(worker_rejected);
+                    result = result.and_then(|value| spilled.map(|()| value));
+                }
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+
+        (result, rejected)
+    })
+}
+
+/// Renders the client summaries as CSV, sorted by client ID, in the configured output mode.
+pub async fn client_summary_csv(state: &AppState) -> Result<String, EngineError> {
+    let client_map = state.client_map.read().await;
+
+    let mut client_vec: Vec<&Client> = client_map.values().collect();
+
+    client_vec.sort_by_key(|client| client.get_client_id());
+
+    let mut csv_writer = Writer::from_writer(vec![]);
+    let default_currency = state.config.default_currency;
+
+    for client in client_vec {
+        let result = match state.config.output_mode {
+            OutputMode::PerClient => {
+                csv_writer.serialize(client.summary_or_empty(default_currency))
+            }
+            OutputMode::PerCurrency if client.summaries().next().is_none() => {
+                csv_writer.serialize(CurrencySummary(&client.summary_or_empty(default_currency)))
+            }
+            OutputMode::PerCurrency => client
+                .summaries()
+                .try_for_each(|summary| csv_writer.serialize(CurrencySummary(summary))),
+        };
+
+        result.map_err(|e| {
+            EngineError::OutputError(format!("Failed to serialize client record: {}", e))
+        })?;
+    }
+
+    let data = String::from_utf8(
+        csv_writer
+            .into_inner()
+            .map_err(|e| EngineError::OutputError(e.to_string()))?,
+    )
+    .map_err(|e| EngineError::OutputError(e.to_string()))?;
+
+    Ok(data)
+}
+
+/// Renders every (client, currency) whose available balance a dispute took below zero as
+/// CSV, sorted by client ID and currency.
+pub async fn deficit_report_csv(state: &AppState) -> Result<String, EngineError> {
+    let client_map = state.client_map.read().await;
+
+    let mut client_vec: Vec<&Client> = client_map
+        .values()
+        .filter(|client| client.in_deficit())
+        .collect();
+
+    client_vec.sort_by_key(|client| client.get_client_id());
+
+    // The header is written up front so a report without deficits still has one.
+    let mut csv_writer = WriterBuilder::new().has_headers(false).from_writer(vec![]);
+    csv_writer
+        .write_record([
+            "client",
+            " currency",
+            " available",
+            " held",
+            " total",
+            " locked",
+        ])
+        .map_err(|e| EngineError::OutputError(e.to_string()))?;
+
+    for summary in client_vec
+        .into_iter()
+        .flat_map(|client| client.summaries())
+        .filter(|summary| summary.in_deficit())
+    {
+        csv_writer
+            .serialize(CurrencySummary(summary))
+            .map_err(|e| {
+                EngineError::OutputError(format!("Failed to serialize deficit record: {}", e))
+            })?;
+    }
+
+    let data = String::from_utf8(
+        csv_writer
+            .into_inner()
+            .map_err(|e| EngineError::OutputError(e.to_string()))?,
+    )
+    .map_err(|e| EngineError::OutputError(e.to_string()))?;
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admin::AuditWriter,
+        config::{DeficitMode, DisputeWindow, EngineConfig, ErrorPolicy},
+        fixtures::TempDir,
+        rejects::RejectsWriter,
+        shard::ShardsRead,
+        snapshot,
+        transaction::TransactionType,
+    };
+    use std::{borrow::Cow, fmt::Write};
+
+    async fn process_with_shards(path: &str, shards: usize) -> Result<EngineState, EngineError> {
+        let state = Arc::new(AppState::new(EngineConfig {
+            shards,
+            ..EngineConfig::default()
+        }));
+
+        process_csv(path.to_string(), state.clone()).await?;
+
+        Ok(state)
+    }
+
+    #[tokio::test]
+    async fn test_sharded_matches_single_lock() -> Result<(), EngineError> {
+        let dir = TempDir::new("shards");
+        let path = dir.join("input.csv");
+
+        // Spans several batches per shard, with disputes and rejections interleaved across
+        // clients.
+        let mut csv = String::from("type,client,tx,amount\n");
+        for tx in 1..=20_000u32 {
+            let client = tx % 37;
+            let row = match tx % 10 {
+                0 => format!("dispute,{client},{},", tx - 5),
+                3 => format!("withdrawal,{client},{tx},7.5"),
+                6 => format!("resolve,{client},{},", tx - 1),
+                9 => format!("chargeback,{client},{},", tx - 9),
+                _ => format!("deposit,{client},{tx},2.25"),
+            };
+            writeln!(csv, "{row}").unwrap();
+        }
+        std::fs::write(&path, csv).unwrap();
+
+        let path = path.to_string_lossy().into_owned();
+        let single = process_with_shards(&path, 1).await?;
+        let sharded = process_with_shards(&path, 4).await?;
+
+        assert_eq!(
+            client_summary_csv(&single).await?,
+            client_summary_csv(&sharded).await?
+        );
+
+        let single_log = single.event_log.read().await;
+        let sharded_log = sharded.event_log.read().await;
+        let outcomes = |log: &EventLog| -> Vec<(u64, Outcome)> {
+            log.events()
+                .iter()
+                .map(|event| (event.seq, event.outcome.clone()))
+                .collect()
+        };
+
+        assert_eq!(outcomes(&single_log), outcomes(&sharded_log));
+        assert!(outcomes(&sharded_log)
+            .iter()
+            .any(|(_, outcome)| *outcome != Outcome::Accepted));
+
+        sharded.verify().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_files_take_turns() -> Result<(), EngineError> {
+        let dir = TempDir::new("turns");
+        let long = dir.join("long.csv").to_string_lossy().into_owned();
+        let short = dir.join("short.csv").to_string_lossy().into_owned();
+
+        let mut csv = String::from("type,client,tx,amount\n");
+        for tx in 1..=2 * FILE_BATCH as u32 + 10 {
+            writeln!(csv, "deposit,{},{tx},1.0", tx % 7).unwrap();
+        }
+        std::fs::write(&long, csv).unwrap();
+        std::fs::write(
+            &short,
+            "type,client,tx,amount\ndeposit,100,4000000000,1.0\n",
+        )
+        .unwrap();
+
+        let skip = ErrorPolicy {
+            malformed: ErrorAction::Skip,
+            rejected: ErrorAction::Skip,
+        };
+
+        // A file that can't abort lets the other in between its batches; one that may abort
+        // has the engine to itself until it is committed.
+        for (error_policy, interleaved) in [(skip, true), (ErrorPolicy::default(), false)] {
+            let state = Arc::new(AppState::new(EngineConfig {
+                shards: 2,
+                error_policy,
+                ..EngineConfig::default()
+            }));
+
+            let (long_result, short_result) = tokio::join!(
+                process_csv(long.clone(), state.clone()),
+                process_csv(short.clone(), state.clone())
+            );
+            long_result?;
+            short_result?;
+
+            let event_log = state.event_log.read().await;
+            let short_seq = event_log
+                .events()
+                .iter()
+                .find(|event| *event.source == *short)
+                .map(|event| event.seq)
+                .expect("the short file was logged");
+
+            assert_eq!(short_seq < event_log.last_seq(), interleaved);
+            assert_eq!(event_log.len(), 2 * FILE_BATCH + 11);
+            drop(event_log);
+
+            state.verify().await?;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deficit_report() -> Result<(), EngineError> {
+        let dir = TempDir::new("deficit");
+        let path = dir.join("input.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,currency\n\
+             deposit,1,1,10.0,\n\
+             withdrawal,1,2,4.0,\n\
+             dispute,1,1,,\n\
+             deposit,2,3,3.0,EUR\n\
+             withdrawal,2,4,3.0,EUR\n\
+             deposit,2,5,1.0,\n\
+             dispute,2,3,,\n",
+        )
+        .unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let strict = Arc::new(AppState::new(EngineConfig::default()));
+        process_csv(path.clone(), strict.clone()).await?;
+        assert_eq!(
+            deficit_report_csv(&strict).await?,
+            "client, currency, available, held, total, locked\n"
+        );
+
+        let allow = Arc::new(AppState::new(EngineConfig {
+            disputes: DisputePolicy {
+                deficit: DeficitMode::Allow,
+                ..DisputePolicy::default()
+            },
+            ..EngineConfig::default()
+        }));
+        process_csv(path.clone(), allow.clone()).await?;
+        assert_eq!(
+            deficit_report_csv(&allow).await?,
+            "client, currency, available, held, total, locked\n\
+             1, USD, -4.0000, 10.0000, 6.0000, false\n\
+             2, EUR, -3.0000, 3.0000, 0.0000, false\n"
+        );
+        allow.verify().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_abort_restores_state_before_file() -> Result<(), EngineError> {
+        let dir = TempDir::new("abort");
+        let good = dir.join("good.csv");
+        let bad = dir.join("bad.csv");
+
+        std::fs::write(
+            &good,
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,2,2,10.0\n\
+             deposit,3,3,10.0\n",
+        )
+        .unwrap();
+
+        // Touches existing and new clients, then fails on the last row.
+        std::fs::write(
+            &bad,
+            "type,client,tx,amount\n\
+             deposit,1,4,5.0\n\
+             dispute,2,2,\n\
+             withdrawal,3,5,50.0\n\
+             deposit,4,6,1.0\n\
+             chargeback,2,2,\n\
+             deposit,5,7,not-a-number\n",
+        )
+        .unwrap();
+
+        let policies = [
+            ErrorPolicy::default(),
+            // Aborts on the insufficient withdrawal instead.
+            ErrorPolicy {
+                malformed: ErrorAction::Skip,
+                rejected: ErrorAction::Abort,
+            },
+        ];
+
+        for (case, (shards, error_policy)) in [1, 3]
+            .into_iter()
+            .flat_map(|shards| policies.map(|policy| (shards, policy)))
+            .enumerate()
+        {
+            let wal_path = dir.join(format!("{case}.wal"));
+            let mut state = AppState::new(EngineConfig {
+                shards,
+                error_policy,
+                ..EngineConfig::default()
+            });
+            crate::wal::recover(&wal_path, &mut state)?;
+            let state = Arc::new(state);
+
+            process_csv(good.to_string_lossy().into_owned(), state.clone()).await?;
+
+            let before = dir.join(format!("{case}_before.json"));
+            snapshot::save(&state, &before).await?;
+            let wal_before = std::fs::read(&wal_path).unwrap();
+
+            let expected = match error_policy.rejected {
+                ErrorAction::Abort => EngineError::InsufficientFunds,
+                _ => EngineError::InvalidAmount("not-a-number".to_string()),
+            };
+            assert_eq!(
+                process_csv(bad.to_string_lossy().into_owned(), state.clone()).await,
+                Err(expected)
+            );
+
+            let after = dir.join(format!("{case}_after.json"));
+            snapshot::save(&state, &after).await?;
+
+            assert_eq!(
+                std::fs::read(&before).unwrap(),
+                std::fs::read(&after).unwrap()
+            );
+            assert_eq!(state.event_log.read().await.last_seq(), 3);
+
+            // Only the aborted file's rows were added to the log, and they are never replayed.
+            assert!(std::fs::read(&wal_path).unwrap().starts_with(&wal_before));
+            let mut recovered = AppState::new(EngineConfig {
+                shards,
+                error_policy,
+                ..EngineConfig::default()
+            });
+            crate::wal::recover(&wal_path, &mut recovered)?;
+            let recovered_path = dir.join(format!("{case}_recovered.json"));
+            snapshot::save(&recovered, &recovered_path).await?;
+            assert_eq!(
+                std::fs::read(&before).unwrap(),
+                std::fs::read(&recovered_path).unwrap()
+            );
+
+            // The file is read from the start again next time.
+            let wal = state.wal.lock().await;
+            assert_eq!(wal.as_ref().unwrap().offset(&bad.to_string_lossy()), None);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_and_quarantine() -> Result<(), EngineError> {
+        let dir = TempDir::new("quarantine");
+        let input = dir.join("input.csv");
+        let quarantine = dir.join("quarantine.csv");
+
+        std::fs::write(
+            &input,
+            "type,client,tx,amount,currency,timestamp\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,abc\n\
+             refund,2,3,1.0\n\
+             withdrawal,1,4,9.0,,1700000000\n\
+             deposit,2,5,2.0\n",
+        )
+        .unwrap();
+
+        let mut state = AppState::new(EngineConfig {
+            error_policy: ErrorPolicy {
+                malformed: ErrorAction::Quarantine,
+                rejected: ErrorAction::Quarantine,
+            },
+            ..EngineConfig::default()
+        });
+        *state.quarantine.get_mut() = Some(QuarantineWriter::open(&quarantine)?);
+        let state = Arc::new(state);
+
+        process_csv(input.to_string_lossy().into_owned(), state.clone()).await?;
+
+        assert_eq!(
+            client_summary_csv(&state).await?,
+            "client, available, held, total, locked\n\
+             1, 5.0000, 0.0000, 5.0000, false\n\
+             2, 2.0000, 0.0000, 2.0000, false\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&quarantine).unwrap(),
+            "type,client,tx,amount,currency,timestamp\n\
+             deposit,2,2,abc\n\
+             refund,2,3,1.0\n\
+             withdrawal,1,4,9.0000,USD,1700000000\n"
+        );
+
+        // Malformed rows never become events; rejected ones do.
+        let event_log = state.event_log.read().await;
+        assert_eq!(event_log.len(), 3);
+        assert_eq!(
+            event_log.events()[1].outcome,
+            Outcome::Rejected(EngineError::InsufficientFunds)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_atomic_batch() -> Result<(), EngineError> {
+        let dir = TempDir::new("atomic");
+        let bad = dir.join("bad.csv");
+        let good = dir.join("good.csv");
+
+        std::fs::write(
+            &bad,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             withdrawal,1,2,9.0\n\
+             deposit,2,3,1.0\n\
+             deposit,2,4,\n\
+             deposit,3,5,2.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &good,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             withdrawal,1,2,1.0\n",
+        )
+        .unwrap();
+
+        for shards in [1, 3] {
+            let rejects = dir.join(format!("rejects_{shards}.csv"));
+            let mut state = AppState::new(EngineConfig {
+                shards,
+                atomic: true,
+                ..EngineConfig::default()
+            });
+            *state.rejects.get_mut() = Some(RejectsWriter::open(
+                &rejects,
+                crate::rejects::RejectsFormat::Csv,
+            )?);
+            let state = Arc::new(state);
+
+            assert_eq!(
+                process_csv(bad.to_string_lossy().into_owned(), state.clone()).await,
+                Err(EngineError::BatchDiscarded(format!(
+                    "{} has 2 failed row(s), the first on line 3: Insufficient funds",
+                    bad.display()
+                )))
+            );
+            assert!(state.client_map.read().await.is_empty());
+            assert!(state.event_log.read().await.is_empty());
+
+            // Every failed row is reported, not just the first.
+            let reported: Vec<(u64, String, ErrorAction)> = csv::Reader::from_path(&rejects)
+                .unwrap()
+                .deserialize::<Reject>()
+                .map(|reject| {
+                    let reject = reject.unwrap();
+                    (reject.line, reject.code, reject.action)
+                })
+                .collect();
+            assert_eq!(
+                reported,
+                [
+                    (3, "insufficient_funds".to_string(), ErrorAction::Abort),
+                    (5, "invalid_transaction".to_string(), ErrorAction::Abort),
+                ]
+            );
+
+            process_csv(good.to_string_lossy().into_owned(), state.clone()).await?;
+            assert_eq!(
+                client_summary_csv(&state).await?,
+                "client, available, held, total, locked\n\
+                 1, 4.0000, 0.0000, 4.0000, false\n"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_admin_operations() -> Result<(), EngineError> {
+        let dir = TempDir::new("admin");
+        let transactions = dir.join("transactions.csv");
+        let admin = dir.join("admin.csv");
+        let later = dir.join("later.csv");
+        let audit = dir.join("audit.jsonl");
+
+        std::fs::write(
+            &transactions,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,2,2,3.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &admin,
+            "type,client,tx,reason,operator\n\
+             unlock,1,1,chargeback reviewed,alice\n\
+             unfreeze,2,2,,bob\n\
+             freeze,2,3,kyc review,bob\n",
+        )
+        .unwrap();
+        let mut state = AppState::new(EngineConfig::default());
+        *state.audit.get_mut() = Some(AuditWriter::open(&audit)?);
+        let state = Arc::new(state);
+
+        process_csv(transactions.to_string_lossy().into_owned(), state.clone()).await?;
+
+        // Admin operations are only accepted from admin files.
+        std::fs::write(&later, "type,client,tx,amount\nunlock,1,5,\n").unwrap();
+        assert_eq!(
+            process_csv(later.to_string_lossy().into_owned(), state.clone()).await,
+            Err(EngineError::InvalidTransaction(
+                "TX ID: 5, Type: unlock is an admin operation".to_string()
+            ))
+        );
+        assert!(state.client_map.read().await[&1].is_locked());
+
+        process_admin_csv(admin.to_string_lossy().into_owned(), state.clone()).await?;
+        std::fs::write(
+            &later,
+            "type,client,tx,amount\n\
+             deposit,1,3,1.0\n\
+             deposit,2,4,1.0\n",
+        )
+        .unwrap();
+
+        process_csv(later.to_string_lossy().into_owned(), state.clone()).await?;
+
+        assert_eq!(
+            client_summary_csv(&state).await?,
+            "client, available, held, total, locked\n\
+             1, 1.0000, 0.0000, 1.0000, false\n\
+             2, 3.0000, 0.0000, 3.0000, true\n"
+        );
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&audit)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            (
+                entries[0].tx_type,
+                entries[0].reason.as_str(),
+                entries[0].operator.as_str()
+            ),
+            (TransactionType::Unlock, "chargeback reviewed", "alice")
+        );
+        assert_eq!(entries[0].code, None);
+        assert_eq!(entries[1].code.as_deref(), Some("admin_error"));
+        assert_eq!(entries[2].client, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_global_tx_ids() -> Result<(), EngineError> {
+        let dir = TempDir::new("tx_ids");
+        let input = dir.join("input.csv");
+        let retry = dir.join("retry.csv");
+
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             deposit,2,1,3.0\n\
+             withdrawal,3,2,1.0\n\
+             deposit,4,2,1.0\n\
+             dispute,2,1,\n\
+             deposit,3,2,2.0\n\
+             dispute,1,1,\n",
+        )
+        .unwrap();
+
+        // Owners are settled in file order, however the clients are sharded.
+        for shards in [1, 4] {
+            let state = process_with_shards(&input.to_string_lossy(), shards).await?;
+            let event_log = state.event_log.read().await;
+
+            let codes: Vec<Option<&str>> = event_log
+                .events()
+                .iter()
+                .map(|event| match &event.outcome {
+                    Outcome::Accepted => None,
+                    Outcome::Rejected(e) => Some(e.code()),
+                })
+                .collect();
+            assert_eq!(
+                codes,
+                [
+                    None,
+                    Some("duplicate_transaction"),
+                    Some("insufficient_funds"),
+                    Some("duplicate_transaction"),
+                    Some("tx_owner_mismatch"),
+                    None,
+                    None
+                ],
+                "{shards} shards"
+            );
+
+            event_log.replay(AppState::new(EngineConfig::default()), event_log.last_seq())?;
+        }
+
+        // A discarded file gives back the IDs it claimed.
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,5,10,1.0\n\
+             deposit,5,11,abc\n",
+        )
+        .unwrap();
+        std::fs::write(&retry, "type,client,tx,amount\ndeposit,6,10,1.0\n").unwrap();
+
+        let state = Arc::new(AppState::new(EngineConfig {
+            atomic: true,
+            ..EngineConfig::default()
+        }));
+
+        assert!(
+            process_csv(input.to_string_lossy().into_owned(), state.clone())
+                .await
+                .is_err()
+        );
+        process_csv(retry.to_string_lossy().into_owned(), state.clone()).await?;
+
+        assert_eq!(state.client_map.read().await.tx_index().owner(10), Some(6));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spilled_transactions() -> Result<(), EngineError> {
+        let dir = TempDir::new("spill");
+        let hot_limit = 500;
+
+        // Disputes reach back into earlier files, long after their deposits were spilled.
+        let mut files = Vec::new();
+        for file in 0..3u32 {
+            let mut csv = String::from("type,client,tx,amount\n");
+            for tx in file * 4000 + 1..=(file + 1) * 4000 {
+                let client = tx % 23;
+                let row = match tx % 10 {
+                    0 => format!("dispute,{client},{},", tx.saturating_sub(3000)),
+                    4 => format!("resolve,{client},{},", tx.saturating_sub(3004)),
+                    7 => format!("chargeback,{client},{},", tx.saturating_sub(2997)),
+                    _ => format!("deposit,{client},{tx},1.5"),
+                };
+                writeln!(csv, "{row}").unwrap();
+            }
+
+            let path = dir.join(format!("input_{file}.csv"));
+            std::fs::write(&path, csv).unwrap();
+            files.push(path.to_string_lossy().into_owned());
+        }
+
+        // A file that aborts on its last row unless malformed rows are skipped, after disputing
+        // spilled deposits and adding more than fit in memory, some for new clients.
+        let mut csv = String::from("type,client,tx,amount\n");
+        for tx in 20_001..=24_000u32 {
+            let client = tx % 29;
+            match tx % 10 {
+                0 => writeln!(csv, "dispute,{client},{},", tx - 19_000).unwrap(),
+                _ => writeln!(csv, "deposit,{client},{tx},2.5").unwrap(),
+            }
+        }
+        csv.push_str("deposit,1,30000,abc\n");
+        let aborting = dir.join("aborting.csv");
+        std::fs::write(&aborting, csv).unwrap();
+        files.insert(2, aborting.to_string_lossy().into_owned());
+
+        let skip = ErrorPolicy {
+            malformed: ErrorAction::Skip,
+            rejected: ErrorAction::Skip,
+        };
+
+        for (shards, error_policy) in [
+            (1, skip),
+            (4, skip),
+            (1, ErrorPolicy::default()),
+            (4, ErrorPolicy::default()),
+        ] {
+            let config = EngineConfig {
+                shards,
+                error_policy,
+                ..EngineConfig::default()
+            };
+            let in_memory = Arc::new(AppState::new(config.clone()));
+            let mut state = AppState::new(config.clone());
+            state.tx_spill = Some(TxSpill::create(&dir, hot_limit)?);
+            let state = Arc::new(state);
+
+            for path in &files {
+                let result = process_csv(path.clone(), in_memory.clone()).await;
+                assert_eq!(process_csv(path.clone(), state.clone()).await, result);
+                assert_eq!(result.is_err(), error_policy.aborts() && *path == files[2]);
+
+                let client_map = state.client_map.read().await;
+                let hot: usize = client_map.values().map(Client::hot_transactions).sum();
+                assert!(hot <= hot_limit + shards, "{hot} transactions in memory");
+                assert!(state.event_log.read().await.is_empty());
+            }
+
+            // Staged rows were spilled as the aborting file was read, then dropped with it. With
+            // a single shard, every row before the malformed one is applied before it is read.
+            let spilled = std::fs::read_dir(&*dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| path.extension().is_some_and(|extension| extension == "bin"))
+                .map(|path| std::fs::metadata(path).unwrap().len());
+            if shards == 1 {
+                assert!(spilled > Some(20_001 * 56), "{spilled:?}");
+            }
+
+            let expected = client_summary_csv(&in_memory).await?;
+            assert_eq!(client_summary_csv(&state).await?, expected);
+            state.verify().await?;
+
+            // A snapshot holds the spilled transactions too, and restores them to disk. The
+            // spill file is named after the process, so it goes in a directory of its own.
+            let path = dir.join("snapshot.json");
+            snapshot::save(&state, &path).await?;
+            let restored_dir = dir.join("restored");
+            std::fs::create_dir_all(&restored_dir).unwrap();
+            let restored = Arc::new(snapshot::load_spilled(
+                &path,
+                config.clone(),
+                TxSpill::create(&restored_dir, hot_limit)?,
+            )?);
+            assert_eq!(client_summary_csv(&restored).await?, expected);
+            restored.verify().await?;
+
+            let in_memory = in_memory.client_map.read().await;
+            let restored = restored.client_map.read().await;
+            let hot: usize = restored.values().map(Client::hot_transactions).sum();
+            assert_eq!(hot, 0);
+            let transactions = |client_map: &ShardsRead| {
+                let mut transactions = client_map
+                    .values()
+                    .flat_map(|client| client.transactions().map(|tx| tx.map(Cow::into_owned)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                transactions.sort_by_key(|tx| tx.tx_id);
+                Ok::<_, EngineError>(transactions)
+            };
+            assert_eq!(transactions(&restored)?, transactions(&in_memory)?);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dispute_window() -> Result<(), EngineError> {
+        let dir = TempDir::new("window");
+        let first = dir.join("first.csv");
+        let second = dir.join("second.csv");
+        let untimed = dir.join("untimed.csv");
+
+        std::fs::write(
+            &first,
+            "type,client,tx,amount,currency,timestamp\n\
+             deposit,1,1,5.0,,1000\n\
+             deposit,1,2,3.0,,1050\n\
+             dispute,1,2,,,1100\n\
+             dispute,1,1,,,1101\n\
+             deposit,2,3,1.0,,900\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "type,client,tx,amount,currency,timestamp\n\
+             resolve,1,2,,,1300\n\
+             dispute,1,1,,,1300\n\
+             dispute,1,2,,,1301\n\
+             dispute,2,3,,,1150\n",
+        )
+        .unwrap();
+
+        let config = EngineConfig {
+            disputes: DisputePolicy {
+                window: Some(DisputeWindow::Seconds(100)),
+                ..DisputePolicy::default()
+            },
+            ..EngineConfig::default()
+        };
+        let state = Arc::new(AppState::new(config.clone()));
+
+        process_csv(first.to_string_lossy().into_owned(), state.clone()).await?;
+
+        // Past the window of the latest time, settled transactions are dropped.
+        {
+            let client_map = state.client_map.read().await;
+            assert_eq!(client_map[&1].transaction(1)?, None);
+            assert!(client_map[&1].transaction(2)?.is_some());
+            assert!(client_map.tx_index().is_expired(1));
+            assert_eq!(state.event_log.read().await.clock(), 1101);
+        }
+
+        process_csv(second.to_string_lossy().into_owned(), state.clone()).await?;
+
+        let event_log = state.event_log.read().await;
+        let codes: Vec<Option<&str>> = event_log
+            .events()
+            .iter()
+            .map(|event| match &event.outcome {
+                Outcome::Accepted => None,
+                Outcome::Rejected(e) => Some(e.code()),
+            })
+            .collect();
+        // An open dispute can still be settled, and the clock never goes back.
+        assert_eq!(
+            codes,
+            [
+                None,
+                None,
+                None,
+                Some("dispute_window_expired"),
+                None,
+                None,
+                Some("dispute_window_expired"),
+                Some("dispute_window_expired"),
+                Some("dispute_window_expired")
+            ]
+        );
+        assert_eq!(
+            event_log.events()[4].transaction.time,
+            event_log.events()[3].transaction.time
+        );
+
+        // Dropping transactions does not change any outcome.
+        event_log.replay(AppState::new(config.clone()), event_log.last_seq())?;
+        drop(event_log);
+
+        let path = dir.join("snapshot.json");
+        snapshot::save(&state, &path).await?;
+        let restored = Arc::new(snapshot::load(&path, config.clone())?);
+        std::fs::write(
+            &second,
+            "type,client,tx,amount,currency,timestamp\n\
+             dispute,1,1,,,1400\n",
+        )
+        .unwrap();
+        process_csv(second.to_string_lossy().into_owned(), restored.clone()).await?;
+        assert_eq!(
+            restored.event_log.read().await.events()[0].outcome,
+            Outcome::Rejected(EngineError::DisputeWindowExpired(
+                "TX 1 is past its dispute window".to_string()
+            ))
+        );
+
+        // Without timestamps, the window counts rows.
+        std::fs::write(
+            &untimed,
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,1,2,1.0\n\
+             dispute,1,2,\n\
+             dispute,1,1,\n",
+        )
+        .unwrap();
+        let state = Arc::new(AppState::new(EngineConfig {
+            disputes: DisputePolicy {
+                window: Some("2".parse()?),
+                ..DisputePolicy::default()
+            },
+            ..EngineConfig::default()
+        }));
+        process_csv(untimed.to_string_lossy().into_owned(), state.clone()).await?;
+
+        let event_log = state.event_log.read().await;
+        assert_eq!(event_log.events()[2].outcome, Outcome::Accepted);
+        assert_eq!(
+            event_log.events()[3].outcome.clone(),
+            Outcome::Rejected(EngineError::DisputeWindowExpired(
+                "TX 1 is past its dispute window".to_string()
+            ))
+        );
+
+        drop(event_log);
+
+        // A window in time needs every row to have a timestamp.
+        let state = Arc::new(AppState::new(config));
+        assert!(matches!(
+            process_csv(untimed.to_string_lossy().into_owned(), state.clone()).await,
+            Err(EngineError::InvalidTransaction(_))
+        ));
+        assert_eq!(state.event_log.read().await.last_seq(), 0);
+
+        assert_eq!("2".parse(), Ok(DisputeWindow::Rows(2)));
+        assert_eq!(
+            "120d".parse(),
+            Ok(DisputeWindow::Seconds(120 * 24 * 60 * 60))
+        );
+        assert!("120w".parse::<DisputeWindow>().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_json_lines_input() -> Result<(), EngineError> {
+        let dir = TempDir::new("json_lines");
+        let csv = dir.join("input.csv");
+        let jsonl = dir.join("input.jsonl");
+
+        std::fs::write(
+            &csv,
+            "type,client,tx,amount,currency,timestamp\n\
+             deposit,1,1,5.0\n\
+             deposit,2,2,abc\n\
+             withdrawal,1,3,9.0\n\
+             deposit,2,4,2.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &jsonl,
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"5.0\"}\n\
+             {\"type\":\"deposit\",\"client\":2,\"tx\":2,\"amount\":\"abc\"}\n\
+             {\"type\":\"withdrawal\",\"client\":1,\"tx\":3,\"amount\":9.0}\n\
+             {\"type\":\"deposit\",\"client\":2,\"tx\":4,\"amount\":2.0}\n",
+        )
+        .unwrap();
+
+        let mut reports = Vec::new();
+
+        for (case, input) in [&csv, &jsonl].into_iter().enumerate() {
+            let rejects = dir.join(format!("rejects_{case}.csv"));
+            let mut state = AppState::new(EngineConfig {
+                error_policy: ErrorPolicy {
+                    malformed: ErrorAction::Skip,
+                    rejected: ErrorAction::Skip,
+                },
+                ..EngineConfig::default()
+            });
+            *state.rejects.get_mut() = Some(RejectsWriter::open(
+                &rejects,
+                crate::rejects::RejectsFormat::Csv,
+            )?);
+            crate::wal::recover(&dir.join(format!("{case}.wal")), &mut state)?;
+            let state = Arc::new(state);
+
+            process_csv(input.to_string_lossy().into_owned(), state.clone()).await?;
+
+            let reported: Vec<(u64, Option<u32>, String)> = csv::Reader::from_path(&rejects)
+                .unwrap()
+                .deserialize::<Reject>()
+                .map(|reject| {
+                    let reject = reject.unwrap();
+                    (reject.line, reject.tx, reject.code)
+                })
+                .collect();
+            reports.push((client_summary_csv(&state).await?, reported));
+
+            // Rows appended later are read from where the last run stopped.
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(input)
+                .unwrap();
+            std::io::Write::write_all(
+                &mut file,
+                match case {
+                    0 => "deposit,2,5,1.0\n",
+                    _ => "{\"type\":\"deposit\",\"client\":2,\"tx\":5,\"amount\":1.0}\n",
+                }
+                .as_bytes(),
+            )
+            .unwrap();
+            process_csv(input.to_string_lossy().into_owned(), state.clone()).await?;
+            assert_eq!(state.event_log.read().await.last_seq(), 4);
+        }
+
+        // Line numbers differ by the CSV header row.
+        assert_eq!(reports[0].0, reports[1].0);
+        assert_eq!(
+            reports[1].1,
+            [
+                (2, Some(2), "invalid_amount".to_string()),
+                (3, Some(3), "insufficient_funds".to_string()),
+            ]
+        );
+        assert_eq!(
+            reports[0]
+                .1
+                .iter()
+                .map(|(line, ..)| line - 1)
+                .collect::<Vec<_>>(),
+            [2, 3]
+        );
+
+        // Quarantined rows are written as CSV, and lines that aren't JSON objects as they were.
+        let quarantine = dir.join("quarantine.csv");
+        std::fs::write(
+            &jsonl,
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"abc\"}\n\
+             {\"type\":\"deposit\",\"client\":1\n",
+        )
+        .unwrap();
+        let mut state = AppState::new(EngineConfig {
+            error_policy: ErrorPolicy {
+                malformed: ErrorAction::Quarantine,
+                rejected: ErrorAction::Quarantine,
+            },
+            ..EngineConfig::default()
+        });
+        *state.quarantine.get_mut() = Some(QuarantineWriter::open(&quarantine)?);
+        process_csv(jsonl.to_string_lossy().into_owned(), Arc::new(state)).await?;
+        assert_eq!(
+            std::fs::read_to_string(&quarantine).unwrap(),
+            "type,client,tx,amount,currency,timestamp\n\
+             deposit,1,1,abc\n\
+             \"{\"\"type\"\":\"\"deposit\"\",\"\"client\"\":1\"\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partner_schema() -> Result<(), EngineError> {
+        let dir = TempDir::new("partner_schema");
+        let input = dir.join("partner.csv");
+
+        std::fs::write(
+            &input,
+            "1|DEPOSIT|7|10.0\n\
+             2|Deposit|7|2.5\n\
+             1|Dispute|7|\n\
+             1|Charge-Back|7|\n",
+        )
+        .unwrap();
+
+        let state = Arc::new(AppState::new(EngineConfig {
+            schema: Schema {
+                headers: false,
+                delimiter: '|',
+                columns: [("tx", 1), ("type", 2), ("client", 3), ("amount", 4)]
+                    .into_iter()
+                    .map(|(column, position)| {
+                        (column.to_string(), input::ColumnSource::Position(position))
+                    })
+                    .collect(),
+            },
+            ..EngineConfig::default()
+        }));
+
+        process_csv(input.to_string_lossy().into_owned(), state.clone()).await?;
+
+        assert_eq!(
+            client_summary_csv(&state).await?,
+            "client, available, held, total, locked\n\
+             7, 2.5000, 0.0000, 2.5000, true\n"
+        );
+
+        Ok(())
+    }
+}
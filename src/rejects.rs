@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use csv::StringRecord;
+
+use crate::{
+    amount::Amount,
+    config::ErrorAction,
+    currency::Currency,
+    event_log::{Event, Outcome},
+    transaction::{Transaction, TransactionRecord, TransactionType},
+    EngineError,
+};
+
+/// File format of the rejects report.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RejectsFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl FromStr for RejectsFormat {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(RejectsFormat::Csv),
+            "jsonl" => Ok(RejectsFormat::JsonLines),
+            _ => Err(EngineError::OtherError(format!(
+                "Unknown rejects format: {s}"
+            ))),
+        }
+    }
+}
+
+/// A row the engine refused, with where it came from, why, and what was done with it.
+///
+/// Fields of a malformed row that could not be read are left empty.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reject {
+    /// Event sequence number; malformed rows never become events.
+    pub seq: Option<u64>,
+    pub source: String,
+    pub line: u64,
+    pub client: Option<u16>,
+    pub tx: Option<u32>,
+    #[serde(rename = "type")]
+    pub tx_type: Option<TransactionType>,
+    pub amount: Option<Amount>,
+    pub currency: Option<Currency>,
+    /// Stable identifier of the error, see [`EngineError::code`].
+    pub code: String,
+    pub message: String,
+    pub action: ErrorAction,
+}
+
+impl Reject {
+    /// The reject report for `event`, if it was rejected.
+    pub fn from_event(event: &Event, action: ErrorAction) -> Option<Self> {
+        let Outcome::Rejected(e) = &event.outcome else {
+            return None;
+        };
+
+        Some(Reject {
+            seq: Some(event.seq),
+            source: event.source.to_string(),
+            line: event.line,
+            client: Some(event.transaction.client_id),
+            tx: Some(event.transaction.tx_id),
+            tx_type: Some(event.transaction.tx_type),
+            amount: event.transaction.amount,
+            currency: Some(event.transaction.currency),
+            code: e.code().to_string(),
+            message: e.to_string(),
+            action,
+        })
+    }
+
+    /// The reject report for a row that could not be read as a transaction, keeping whichever
+    /// of its `type,client,tx,amount,currency` fields are valid.
+    pub fn malformed(
+        source: &str,
+        line: u64,
+        row: &StringRecord,
+        e: &EngineError,
+        action: ErrorAction,
+    ) -> Self {
+        let field = |index: usize| row.get(index).filter(|field| !field.is_empty());
+
+        Reject {
+            seq: None,
+            source: source.to_string(),
+            line,
+            client: field(1).and_then(|client| client.parse().ok()),
+            tx: field(2).and_then(|tx| tx.parse().ok()),
+            tx_type: field(0).and_then(|tx_type| tx_type.parse().ok()),
+            amount: field(3).and_then(|amount| amount.parse().ok()),
+            currency: field(4).and_then(|currency| currency.parse().ok()),
+            code: e.code().to_string(),
+            message: e.to_string(),
+            action,
+        }
+    }
+}
+
+enum RejectsSink {
+    Csv(Box<csv::Writer<File>>),
+    JsonLines(BufWriter<File>),
+}
+
+/// Appends rejected transactions to a report file, kept across runs.
+pub struct RejectsWriter {
+    sink: RejectsSink,
+}
+
+fn rejects_error(e: impl std::fmt::Display) -> EngineError {
+    EngineError::OutputError(format!("Failed to write rejects: {}", e))
+}
+
+impl RejectsWriter {
+    /// Opens the report at `path` for appending, creating it if needed. A new CSV report
+    /// starts with a header row.
+    pub fn open(path: &Path, format: RejectsFormat) -> Result<Self, EngineError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(rejects_error)?;
+
+        let sink = match format {
+            RejectsFormat::Csv => {
+                let is_new = file.metadata().map_err(rejects_error)?.len() == 0;
+                RejectsSink::Csv(Box::new(
+                    csv::WriterBuilder::new()
+                        .has_headers(is_new)
+                        .from_writer(file),
+                ))
+            }
+            RejectsFormat::JsonLines => RejectsSink::JsonLines(BufWriter::new(file)),
+        };
+
+        Ok(RejectsWriter { sink })
+    }
+
+    pub fn write(&mut self, reject: &Reject) -> Result<(), EngineError> {
+        match &mut self.sink {
+            RejectsSink::Csv(writer) => writer.serialize(reject).map_err(rejects_error),
+            RejectsSink::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, reject).map_err(rejects_error)?;
+                writer.write_all(b"\n").map_err(rejects_error)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), EngineError> {
+        match &mut self.sink {
+            RejectsSink::Csv(writer) => writer.flush(),
+            RejectsSink::JsonLines(writer) => writer.flush(),
+        }
+        .map_err(rejects_error)
+    }
+}
+
+/// Appends failed rows to a CSV file in the engine's input format, so they can be fixed and
+/// fed back in.
+pub struct QuarantineWriter {
+    writer: csv::Writer<File>,
+}
+
+impl QuarantineWriter {
+    /// Opens the quarantine file at `path` for appending, creating it with a header row if
+    /// needed.
+    pub fn open(path: &Path) -> Result<Self, EngineError> {
+        let quarantine_error =
+            |e: std::io::Error| EngineError::OutputError(format!("Failed to quarantine: {}", e));
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(quarantine_error)?;
+        let is_new = file.metadata().map_err(quarantine_error)?.len() == 0;
+
+        let mut quarantine = QuarantineWriter {
+            // Malformed rows keep however many fields they had.
+            writer: csv::WriterBuilder::new().flexible(true).from_writer(file),
+        };
+
+        if is_new {
+            quarantine.write(&StringRecord::from(TransactionRecord::COLUMNS.to_vec()))?;
+        }
+
+        Ok(quarantine)
+    }
+
+    /// The row a rejected transaction was read from.
+    pub fn transaction_row(transaction: &Transaction) -> StringRecord {
+        StringRecord::from(vec![
+            transaction.tx_type.to_string(),
+            transaction.client_id.to_string(),
+            transaction.tx_id.to_string(),
+            transaction
+                .amount
+                .map_or_else(String::new, |amount| amount.to_string()),
+            transaction.currency.to_string(),
+            transaction
+                .time
+                .map_or_else(String::new, |time| time.to_string()),
+        ])
+    }
+
+    /// Writes `row` without its trailing empty fields, as optional columns may be left out.
+    pub fn write(&mut self, row: &StringRecord) -> Result<(), EngineError> {
+        let len = row.len()
+            - row
+                .iter()
+                .rev()
+                .take_while(|field| field.is_empty())
+                .count();
+
+        self.writer
+            .write_record(row.iter().take(len))
+            .map_err(|e| EngineError::OutputError(format!("Failed to quarantine: {}", e)))
+    }
+
+    pub fn flush(&mut self) -> Result<(), EngineError> {
+        self.writer
+            .flush()
+            .map_err(|e| EngineError::OutputError(format!("Failed to quarantine: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::EngineConfig, fixtures::TempDir, processor::process_csv, AppState};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_rejects_report() -> Result<(), EngineError> {
+        let dir = TempDir::new("rejects");
+
+        let input = dir.join("input.csv");
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,5.0\n\
+             withdrawal,1,2,7.5\n\
+             dispute,2,9,\n\
+             deposit,1,1,1.0\n",
+        )
+        .unwrap();
+        let source = input.to_string_lossy().into_owned();
+
+        for format in [RejectsFormat::Csv, RejectsFormat::JsonLines] {
+            let report = dir.join(format!("rejects.{format:?}"));
+            let mut state = AppState::new(EngineConfig::default());
+            *state.rejects.get_mut() = Some(RejectsWriter::open(&report, format)?);
+            let state = Arc::new(state);
+
+            // Reports from later runs are appended to the same file.
+            process_csv(source.clone(), state.clone()).await?;
+            process_csv(source.clone(), state.clone()).await?;
+
+            let rejects: Vec<Reject> = match format {
+                RejectsFormat::Csv => csv::Reader::from_path(&report)
+                    .unwrap()
+                    .deserialize()
+                    .collect::<Result<_, _>>()
+                    .unwrap(),
+                RejectsFormat::JsonLines => std::fs::read_to_string(&report)
+                    .unwrap()
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect(),
+            };
+
+            let codes: Vec<(u64, &str)> = rejects
+                .iter()
+                .map(|reject| (reject.line, reject.code.as_str()))
+                .collect();
+            assert_eq!(
+                codes[..3],
+                [
+                    (3, "insufficient_funds"),
+                    (4, "dispute_error"),
+                    (5, "duplicate_transaction")
+                ]
+            );
+            assert_eq!(rejects.len(), 7);
+
+            assert_eq!(
+                rejects[0],
+                Reject {
+                    seq: Some(2),
+                    source: source.clone(),
+                    line: 3,
+                    client: Some(1),
+                    tx: Some(2),
+                    tx_type: Some(TransactionType::Withdrawal),
+                    amount: Some("7.5".parse()?),
+                    currency: Some(Currency::default()),
+                    code: "insufficient_funds".to_string(),
+                    message: "Insufficient funds".to_string(),
+                    action: ErrorAction::Skip,
+                }
+            );
+        }
+
+        Ok(())
+    }
+}
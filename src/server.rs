@@ -0,0 +1,386 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap, fmt::Display, path::PathBuf, str::FromStr, sync::Arc, time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, UnixListener},
+    sync::{mpsc, Semaphore},
+};
+
+use crate::{
+    config::{ErrorAction, ErrorPolicy},
+    input::LinesDecoder,
+    processor::{process_rows, RowOptions, RowOutcome},
+    transaction::TransactionRecord,
+    EngineError, EngineState,
+};
+
+/// Lines read from every producer and waiting to be applied. Once it is full, no more lines
+/// are read until the engine catches up.
+const QUEUE_DEPTH: usize = 8192;
+
+/// Lines of a producer that may be waiting for their ack. A producer that doesn't read its
+/// acks stops being read, without holding up the others.
+const IN_FLIGHT: usize = 1024;
+
+/// Lines applied at a time, across producers.
+const BATCH: usize = 1024;
+
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Where the server accepts producers: a TCP address, or `unix:PATH` for a Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err(EngineError::OtherError(format!(
+                "Invalid listen address: {s}"
+            ))),
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => Ok(ListenAddress::Tcp(s.to_string())),
+        }
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{address}"),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AckStatus {
+    /// Applied.
+    Accepted,
+    /// Logged as an event and refused by its client.
+    Rejected,
+    /// Could not be read as a transaction.
+    Malformed,
+    /// The engine failed while applying it, e.g. to write the write-ahead log. Whether it was
+    /// applied is unknown.
+    Failed,
+}
+
+/// The reply to a line, written back to its producer as a line of JSON once the line is
+/// committed. Producers get one ack per non-blank line, in the order they sent them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ack {
+    pub line: u64,
+    pub status: AckStatus,
+    /// Event sequence number, for accepted and rejected lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Stable identifier of the error, see [`EngineError::code`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Ack {
+    fn failed(line: u64, seq: Option<u64>, e: &EngineError, status: AckStatus) -> Self {
+        Ack {
+            line,
+            status,
+            seq,
+            code: Some(e.code().to_string()),
+            message: Some(e.to_string()),
+        }
+    }
+}
+
+impl From<RowOutcome> for Ack {
+    fn from(outcome: RowOutcome) -> Self {
+        match (outcome.result, outcome.seq) {
+            (Ok(()), seq) => Ack {
+                line: outcome.line,
+                status: AckStatus::Accepted,
+                seq,
+                code: None,
+                message: None,
+            },
+            (Err(e), Some(seq)) => Ack::failed(outcome.line, Some(seq), &e, AckStatus::Rejected),
+            (Err(e), None) => Ack::failed(outcome.line, None, &e, AckStatus::Malformed),
+        }
+    }
+}
+
+/// A producer's connection.
+struct Producer {
+    /// Source of its lines in the event log and reports.
+    source: Arc<str>,
+    acks: mpsc::Sender<Ack>,
+}
+
+/// Lines of a producer with their line numbers.
+type Lines = Vec<(u64, String)>;
+
+struct Request {
+    producer: Arc<Producer>,
+    line: u64,
+    text: String,
+}
+
+/// Accepts line-delimited transactions from any number of producers and applies them to the
+/// engine, acknowledging every line.
+///
+/// Lines are JSON objects keyed by column name or CSV rows in the engine's column order,
+/// without a header. The lines queued from every producer are applied together, in batches, as
+/// if each producer's lines were a file of its own. A row that would abort a file is skipped
+/// instead, so one producer's bad line doesn't discard the others'.
+pub struct Server {
+    state: EngineState,
+    requests: mpsc::Sender<Request>,
+}
+
+impl Server {
+    /// Starts applying the lines producers send. Must be called within the runtime.
+    pub fn start(state: EngineState) -> Self {
+        let (requests, receiver) = mpsc::channel(QUEUE_DEPTH);
+        tokio::spawn(ingest(receiver, state.clone()));
+
+        Server { state, requests }
+    }
+
+    /// Accepts producers on `address` until the runtime shuts down. Returns the address
+    /// listened on, which has the port picked by the system if `address` has port 0.
+    pub async fn listen(&self, address: &ListenAddress) -> Result<ListenAddress, EngineError> {
+        let listen_error = |e: std::io::Error| {
+            EngineError::OtherError(format!("Failed to listen on {}: {}", address, e))
+        };
+
+        match address {
+            ListenAddress::Tcp(tcp) => {
+                let listener = TcpListener::bind(tcp).await.map_err(listen_error)?;
+                let local = listener.local_addr().map_err(listen_error)?;
+                let (state, requests) = (self.state.clone(), self.requests.clone());
+
+                tokio::spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, peer)) => {
+                                let source = Arc::from(format!("tcp:{peer}"));
+                                tokio::spawn(serve(
+                                    stream,
+                                    source,
+                                    requests.clone(),
+                                    state.clone(),
+                                ));
+                            }
+                            Err(e) => accept_failed(e).await,
+                        }
+                    }
+                });
+
+                Ok(ListenAddress::Tcp(local.to_string()))
+            }
+            ListenAddress::Unix(path) => {
+                // A socket left behind by a previous run would keep the path taken.
+                if std::fs::symlink_metadata(path).is_ok_and(|metadata| {
+                    std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type())
+                }) {
+                    std::fs::remove_file(path).map_err(listen_error)?;
+                }
+
+                let listener = UnixListener::bind(path).map_err(listen_error)?;
+                let (state, requests) = (self.state.clone(), self.requests.clone());
+                let path = path.clone();
+
+                tokio::spawn(async move {
+                    // Unix peers are unnamed, so they are numbered.
+                    for producer in 1.. {
+                        match listener.accept().await {
+                            Ok((stream, _)) => {
+                                let source =
+                                    Arc::from(format!("unix:{}#{producer}", path.display()));
+                                tokio::spawn(serve(
+                                    stream,
+                                    source,
+                                    requests.clone(),
+                                    state.clone(),
+                                ));
+                            }
+                            Err(e) => accept_failed(e).await,
+                        }
+                    }
+                });
+
+                Ok(address.clone())
+            }
+        }
+    }
+}
+
+/// Reports a failed accept and gives the system a moment, e.g. to free file descriptors.
+async fn accept_failed(e: std::io::Error) {
+    eprintln!("Failed to accept a producer: {}", e);
+    tokio::time::sleep(ACCEPT_RETRY).await;
+}
+
+/// Reads a producer's lines into the queue and writes their acks back.
+async fn serve(
+    stream: impl AsyncRead + AsyncWrite + Send + 'static,
+    source: Arc<str>,
+    requests: mpsc::Sender<Request>,
+    state: EngineState,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    // Every ack holds a permit until it is written, so the channel never fills up.
+    let (acks, mut pending) = mpsc::channel::<Ack>(IN_FLIGHT);
+    let in_flight = Arc::new(Semaphore::new(IN_FLIGHT));
+    let producer = Arc::new(Producer {
+        source: source.clone(),
+        acks,
+    });
+
+    // Ends once every ack is written, after the reader hangs up and the queued lines are
+    // applied, or when the producer stops reading. No more permits come back then, so they
+    // are closed, which stops the reader too.
+    let released = in_flight.clone();
+    let writing = tokio::spawn(async move {
+        while let Some(ack) = pending.recv().await {
+            let mut text = serde_json::to_string(&ack).expect("acks serialize");
+            text.push('\n');
+
+            if writer.write_all(text.as_bytes()).await.is_err() {
+                break;
+            }
+
+            released.add_permits(1);
+
+            if pending.is_empty() && writer.flush().await.is_err() {
+                break;
+            }
+        }
+
+        released.close();
+        let _ = writer.shutdown().await;
+    });
+
+    let mut lines = BufReader::new(reader).lines();
+    let mut line = 0;
+
+    loop {
+        let text = match lines.next_line().await {
+            Ok(Some(text)) => text,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to read from {}: {}", source, e);
+                break;
+            }
+        };
+        line += 1;
+
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        // Waits for the producer to read its acks, then for room in the queue.
+        match in_flight.acquire().await {
+            Ok(permit) => permit.forget(),
+            Err(_) => break,
+        }
+
+        let request = Request {
+            producer: producer.clone(),
+            line,
+            text,
+        };
+
+        if requests.send(request).await.is_err() {
+            break;
+        }
+    }
+
+    drop(producer);
+    let _ = writing.await;
+
+    // The source is never resumed, so its offset would only linger in the log.
+    if let Some(wal) = state.wal.lock().await.as_mut() {
+        if wal.offset(&source).is_some() {
+            if let Err(e) = wal.forget(&source) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+/// Applies the queued lines, a batch at a time.
+async fn ingest(mut requests: mpsc::Receiver<Request>, state: EngineState) {
+    let mut batch = Vec::with_capacity(BATCH);
+
+    // A row that would abort its file is skipped, as a batch mixes producers.
+    let skip_abort = |action| match action {
+        ErrorAction::Abort => ErrorAction::Skip,
+        action => action,
+    };
+    let options = RowOptions {
+        admin: false,
+        resume: false,
+        policy: ErrorPolicy {
+            malformed: skip_abort(state.config.error_policy.malformed),
+            rejected: skip_abort(state.config.error_policy.rejected),
+        },
+        atomic: false,
+        outcomes: true,
+    };
+
+    while requests.recv_many(&mut batch, BATCH).await > 0 {
+        // Each producer's lines, in the order they were sent.
+        let mut producers: Vec<(Arc<Producer>, Lines)> = Vec::new();
+
+        for request in batch.drain(..) {
+            match producers
+                .iter_mut()
+                .find(|(producer, _)| Arc::ptr_eq(producer, &request.producer))
+            {
+                Some((_, lines)) => lines.push((request.line, request.text)),
+                None => producers.push((request.producer, vec![(request.line, request.text)])),
+            }
+        }
+
+        for (producer, lines) in producers {
+            let numbers: Vec<u64> = lines.iter().map(|(line, _)| *line).collect();
+            let decoder = Box::new(LinesDecoder::new(lines, TransactionRecord::COLUMNS));
+
+            let (mut outcomes, error) =
+                match process_rows(&state, producer.source.clone(), decoder, options).await {
+                    Ok(outcomes) => (
+                        outcomes
+                            .into_iter()
+                            .map(|outcome| (outcome.line, outcome))
+                            .collect::<HashMap<_, _>>(),
+                        EngineError::OtherError(String::from("The line was not applied")),
+                    ),
+                    Err(e) => {
+                        eprintln!("Failed to apply lines from {}: {}", producer.source, e);
+                        (HashMap::new(), e)
+                    }
+                };
+
+            // One ack per line, so every permit is returned.
+            for line in numbers {
+                let ack = match outcomes.remove(&line) {
+                    Some(outcome) => Ack::from(outcome),
+                    None => Ack::failed(line, None, &error, AckStatus::Failed),
+                };
+
+                // A producer that hung up no longer reads its acks, and one that didn't has
+                // room for every line it was allowed to send.
+                let _ = producer.acks.try_send(ack);
+            }
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut, Index},
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    apply_transaction,
+    client::{Client, ClientUndo},
+    config::{Cutoff, DisputePolicy},
+    transaction::Transaction,
+    tx_index::TxIndex,
+    EngineError,
+};
+
+pub type Shard = HashMap<u16, Client>; // map: client_id -> client
+
+/// Clients partitioned by client ID into independently locked shards, so transactions for
+/// clients in different shards can be applied in parallel.
+///
+/// Client `id` always lives in shard `id % shard_count`. Shards are locked in index order,
+/// followed by the transaction index shared by all of them.
+pub struct ClientShards {
+    shards: Vec<RwLock<Shard>>,
+    tx_index: RwLock<TxIndex>,
+}
+
+/// Every shard, locked or borrowed by `G`, viewed as a single client map, with the
+/// transaction index locked or borrowed by `I`.
+pub struct Shards<G, I> {
+    shards: Vec<G>,
+    tx_index: I,
+}
+
+pub type ShardsRead<'a> = Shards<RwLockReadGuard<'a, Shard>, RwLockReadGuard<'a, TxIndex>>;
+pub type ShardsWrite<'a> = Shards<RwLockWriteGuard<'a, Shard>, RwLockWriteGuard<'a, TxIndex>>;
+pub type ShardsMut<'a> = Shards<&'a mut Shard, &'a mut TxIndex>;
+
+impl ClientShards {
+    /// Splits clients into `count` shards, at least one.
+    pub fn new(count: usize) -> Self {
+        ClientShards {
+            shards: (0..count.max(1))
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            tx_index: RwLock::new(TxIndex::default()),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub async fn read(&self) -> ShardsRead<'_> {
+        let mut shards = Vec::with_capacity(self.shards.len());
+
+        for shard in &self.shards {
+            shards.push(shard.read().await);
+        }
+
+        Shards {
+            shards,
+            tx_index: self.tx_index.read().await,
+        }
+    }
+
+    pub async fn write(&self) -> ShardsWrite<'_> {
+        let mut shards = Vec::with_capacity(self.shards.len());
+
+        for shard in &self.shards {
+            shards.push(shard.write().await);
+        }
+
+        Shards {
+            shards,
+            tx_index: self.tx_index.write().await,
+        }
+    }
+
+    /// Borrows every shard without locking, given exclusive access.
+    pub fn get_mut(&mut self) -> ShardsMut<'_> {
+        Shards {
+            shards: self.shards.iter_mut().map(RwLock::get_mut).collect(),
+            tx_index: self.tx_index.get_mut(),
+        }
+    }
+}
+
+impl<G: Deref<Target = Shard>, I: Deref<Target = TxIndex>> Shards<G, I> {
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Index of the shard owning `client_id`.
+    pub fn shard_index(&self, client_id: u16) -> usize {
+        client_id as usize % self.shards.len()
+    }
+
+    pub fn get(&self, client_id: u16) -> Option<&Client> {
+        self.shards[self.shard_index(client_id)].get(&client_id)
+    }
+
+    /// Every client, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &Client> {
+        self.shards.iter().flat_map(|shard| shard.values())
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.is_empty())
+    }
+
+    /// The owner of every deposit and withdrawal ID, see [`TxIndex`].
+    pub fn tx_index(&self) -> &TxIndex {
+        &self.tx_index
+    }
+}
+
+impl<G: DerefMut<Target = Shard>, I: DerefMut<Target = TxIndex>> Shards<G, I> {
+    pub fn get_mut(&mut self, client_id: u16) -> Option<&mut Client> {
+        let index = self.shard_index(client_id);
+        self.shards[index].get_mut(&client_id)
+    }
+
+    /// Inserts a client, claiming the IDs of its deposits and withdrawals. An ID another client
+    /// already owns stays with that client.
+    pub fn insert(&mut self, client: Client) {
+        // Clients are inserted as restored, before anything is spilled.
+        for tx in client.transactions().flatten() {
+            let _ = self.tx_index.claim(tx.tx_id, client.get_client_id());
+        }
+
+        let index = self.shard_index(client.get_client_id());
+        self.shards[index].insert(client.get_client_id(), client);
+    }
+
+    /// Admits a transaction to the [transaction index](TxIndex::admit) and applies it to its
+    /// client's shard, see [`apply_transaction`].
+    pub fn apply(
+        &mut self,
+        transaction: &Transaction,
+        disputes: &DisputePolicy,
+    ) -> Result<(), EngineError> {
+        self.tx_index.admit(transaction)?;
+
+        let index = self.shard_index(transaction.client_id);
+        apply_transaction(&mut self.shards[index], transaction, disputes)
+    }
+
+    /// Drops every client's transactions made before `cutoff` that are not under an open
+    /// dispute, see [`Client::expire`], and flags their IDs in the transaction index.
+    pub fn expire(&mut self, cutoff: Cutoff) -> Result<(), EngineError> {
+        for shard in self.shards.iter_mut() {
+            for client in shard.values_mut() {
+                for tx_id in client.expire(cutoff)? {
+                    self.tx_index.expire(tx_id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Each shard, in index order, for handing to its own worker.
+    pub fn shards_mut(&mut self) -> impl Iterator<Item = &mut Shard> {
+        self.shards.iter_mut().map(|shard| &mut **shard)
+    }
+
+    pub fn tx_index_mut(&mut self) -> &mut TxIndex {
+        &mut self.tx_index
+    }
+
+    /// The transaction index and each shard, borrowed separately so the index can be updated
+    /// while the shards are with their workers.
+    pub fn split_mut(&mut self) -> (&mut TxIndex, Vec<&mut Shard>) {
+        (
+            &mut self.tx_index,
+            self.shards.iter_mut().map(|shard| &mut **shard).collect(),
+        )
+    }
+}
+
+/// The changes a file that may abort makes to a shard, kept apart until the file commits.
+///
+/// Clients the file creates are kept here. The clients it changes keep their transactions
+/// and journal live, with an undo log of the changes, while the summaries and status it leaves
+/// them with are staged here, so the live clients' balances only change once it commits.
+/// Transactions can be spilled either way, and are dropped from the spill file if the file
+/// is discarded.
+#[derive(Debug, Default)]
+pub struct StagedShard {
+    created: Shard,
+    changed: HashMap<u16, ClientUndo>, // map: client_id -> staged changes
+}
+
+impl StagedShard {
+    /// Applies a transaction to its client's staged state, see [`apply_transaction`].
+    pub fn apply(
+        &mut self,
+        live: &mut Shard,
+        transaction: &Transaction,
+        disputes: &DisputePolicy,
+    ) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+
+        match live.get_mut(&client_id) {
+            Some(client) => {
+                let undo = self
+                    .changed
+                    .entry(client_id)
+                    .or_insert_with(|| client.stage());
+                client.execute_staged(undo, transaction, disputes)
+            }
+            None => apply_transaction(&mut self.created, transaction, disputes),
+        }
+    }
+
+    /// Keeps the staged changes in the live shard.
+    pub fn commit(self, live: &mut Shard) {
+        for (client_id, undo) in self.changed {
+            if let Some(client) = live.get_mut(&client_id) {
+                client.commit(undo);
+            }
+        }
+
+        live.extend(self.created);
+    }
+
+    /// Undoes the staged changes, leaving the live shard as it was before the file.
+    pub fn discard(self, live: &mut Shard) -> Result<(), EngineError> {
+        for (client_id, undo) in self.changed {
+            if let Some(client) = live.get_mut(&client_id) {
+                client.roll_back(undo)?;
+            }
+        }
+
+        for client in self.created.into_values() {
+            client.discard()?;
+        }
+
+        Ok(())
+    }
+
+    /// The clients of the live shard and those the file created, to spill their transactions
+    /// while the file is read.
+    pub fn clients_mut<'a>(
+        &'a mut self,
+        live: &'a mut Shard,
+    ) -> impl Iterator<Item = &'a mut Client> + 'a {
+        live.values_mut().chain(self.created.values_mut())
+    }
+}
+
+impl<G: Deref<Target = Shard>, I: Deref<Target = TxIndex>> Index<&u16> for Shards<G, I> {
+    type Output = Client;
+
+    fn index(&self, client_id: &u16) -> &Client {
+        self.get(*client_id).expect("no client with this ID")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::tx,
+        transaction::{TransactionRecord, TransactionType},
+    };
+
+    #[tokio::test]
+    async fn test_clients_stay_in_their_shard() -> Result<(), EngineError> {
+        let shards = ClientShards::new(4);
+
+        {
+            let mut clients = shards.write().await;
+
+            for client_id in 0..10 {
+                clients.apply(
+                    &Transaction::try_from(TransactionRecord {
+                        tx_type: "deposit".to_string(),
+                        client_id,
+                        tx_id: client_id as u32,
+                        amount: Some("1.0".to_string()),
+                        currency: None,
+                        timestamp: None,
+                    })?,
+                    &DisputePolicy::default(),
+                )?;
+            }
+        }
+
+        let clients = shards.read().await;
+
+        assert_eq!(clients.len(), 10);
+        assert_eq!(clients[&7].get_client_id(), 7);
+
+        for (index, shard) in shards.shards.iter().enumerate() {
+            let shard = shard.try_read().expect("shard is read-locked only");
+            assert!(shard
+                .keys()
+                .all(|client_id| *client_id as usize % 4 == index));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_staged_changes() -> Result<(), EngineError> {
+        let policy = DisputePolicy::default();
+        let before = [
+            tx(TransactionType::Deposit, 1, 1, Some("10.0")),
+            tx(TransactionType::Deposit, 1, 2, Some("4.0")),
+            tx(TransactionType::Dispute, 1, 2, None),
+        ];
+        let file = [
+            tx(TransactionType::Deposit, 1, 3, Some("5.0")),
+            tx(TransactionType::Dispute, 1, 1, Some("6.0")),
+            tx(TransactionType::Resolve, 1, 2, None),
+            tx(TransactionType::Dispute, 1, 3, None),
+            tx(TransactionType::ChargeBack, 1, 1, None),
+            tx(TransactionType::Deposit, 2, 4, Some("1.0")),
+        ];
+
+        // What a client looks like to compare it: its balances, transactions and journal.
+        let view = |shard: &Shard, client_id| {
+            shard.get(&client_id).map(|client: &Client| {
+                let mut transactions: Vec<String> = client
+                    .transactions()
+                    .map(|tx| format!("{:?}", tx.unwrap()))
+                    .collect();
+                transactions.sort();
+
+                (
+                    client.to_string(),
+                    transactions,
+                    client.journal().entries().to_vec(),
+                    client.journal().balances().collect::<Vec<_>>(),
+                )
+            })
+        };
+
+        let mut live = Shard::new();
+        for transaction in &before {
+            apply_transaction(&mut live, transaction, &policy)?;
+        }
+        let mut direct = live.clone();
+        for transaction in &file {
+            apply_transaction(&mut direct, transaction, &policy)?;
+        }
+
+        let staged_file = |live: &mut Shard| -> Result<StagedShard, EngineError> {
+            let mut staged = StagedShard::default();
+            for transaction in &file {
+                staged.apply(live, transaction, &policy)?;
+            }
+            Ok(staged)
+        };
+
+        // Until it commits, the balances and the clients are as they were before the file.
+        let mut committed = live.clone();
+        let staged = staged_file(&mut committed)?;
+        assert_eq!(
+            view(&committed, 1).map(|view| view.0),
+            view(&live, 1).map(|view| view.0)
+        );
+        assert!(!committed.contains_key(&2));
+
+        staged.commit(&mut committed);
+        for client_id in [1, 2] {
+            assert_eq!(view(&committed, client_id), view(&direct, client_id));
+            committed[&client_id].verify()?;
+        }
+
+        let mut discarded = live.clone();
+        staged_file(&mut discarded)?.discard(&mut discarded)?;
+        for client_id in [1, 2] {
+            assert_eq!(view(&discarded, client_id), view(&live, client_id));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,522 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    admin::AccountStatus,
+    amount::Amount,
+    client::{Client, ClientSummary},
+    config::EngineConfig,
+    currency::Currency,
+    dispute::DisputeState,
+    event_log::EventLog,
+    journal::{Account, Journal},
+    transaction::{Transaction, TransactionType},
+    tx_store::TxSpill,
+    AppState, EngineError,
+};
+
+/// Version written by this build. Snapshots from older versions must keep loading: fields
+/// added in later versions are `#[serde(default)]`, unknown fields and record kinds are
+/// ignored.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A line of the snapshot. The header comes first, each client before its transactions, so
+/// a snapshot is written and read one record at a time.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum SnapshotRecord {
+    Header(Header),
+    Client(ClientSnapshot),
+    Transaction(TransactionSnapshot),
+    Claim(ClaimSnapshot),
+
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+
+    /// Sequence number of the last event reflected in the snapshot.
+    event_seq: u64,
+
+    /// The engine clock, see [`EventLog::clock`].
+    clock: u64,
+}
+
+/// An ID owned by a client without a transaction to show for it, because the deposit or
+/// withdrawal that claimed it was rejected or dropped past the dispute window. The other IDs
+/// are claimed again from the clients' transactions.
+#[derive(Debug, Serialize, Deserialize)]
+struct ClaimSnapshot {
+    tx: u32,
+    client: u16,
+
+    /// Dropped past the dispute window.
+    #[serde(default)]
+    expired: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ClientSnapshot {
+    client: u16,
+
+    #[serde(default)]
+    balances: Vec<BalanceSnapshot>,
+
+    #[serde(default)]
+    journal: Vec<JournalBalanceSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BalanceSnapshot {
+    currency: Currency,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    status: AccountStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TransactionSnapshot {
+    tx: u32,
+    client: u16,
+
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+
+    amount: Option<Amount>,
+    currency: Currency,
+    dispute: DisputeState,
+    disputed_amount: Amount,
+    disputed_total: Amount,
+
+    /// The timestamp, if the transaction's row had one.
+    #[serde(default)]
+    time: Option<u64>,
+
+    seq: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalBalanceSnapshot {
+    currency: Currency,
+    account: Account,
+    balance: Amount,
+}
+
+impl From<&Client> for ClientSnapshot {
+    fn from(client: &Client) -> Self {
+        ClientSnapshot {
+            client: client.get_client_id(),
+            balances: client
+                .summaries()
+                .map(|summary| BalanceSnapshot {
+                    currency: summary.get_currency(),
+                    available: summary.get_available(),
+                    held: summary.get_held(),
+                    total: summary.get_total(),
+                    status: summary.status(),
+                })
+                .collect(),
+            journal: client
+                .journal()
+                .balances()
+                .map(|((currency, account), balance)| JournalBalanceSnapshot {
+                    currency,
+                    account,
+                    balance,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<ClientSnapshot> for Client {
+    fn from(snapshot: ClientSnapshot) -> Self {
+        let client_id = snapshot.client;
+
+        let journal = Journal::from_balances(
+            snapshot
+                .journal
+                .into_iter()
+                .map(|entry| ((entry.currency, entry.account), entry.balance)),
+        );
+
+        Client::from_parts(
+            client_id,
+            snapshot
+                .balances
+                .into_iter()
+                .map(|balance| {
+                    ClientSummary::from_parts(
+                        client_id,
+                        balance.currency,
+                        balance.available,
+                        balance.held,
+                        balance.total,
+                        balance.status,
+                    )
+                })
+                .collect(),
+            journal,
+        )
+    }
+}
+
+impl From<&Transaction> for TransactionSnapshot {
+    fn from(tx: &Transaction) -> Self {
+        TransactionSnapshot {
+            tx: tx.tx_id,
+            client: tx.client_id,
+            tx_type: tx.tx_type,
+            amount: tx.amount,
+            currency: tx.currency,
+            dispute: tx.dispute,
+            disputed_amount: tx.disputed_amount,
+            disputed_total: tx.disputed_total,
+            time: tx.time,
+            seq: tx.seq,
+        }
+    }
+}
+
+impl From<TransactionSnapshot> for Transaction {
+    fn from(tx: TransactionSnapshot) -> Self {
+        Transaction {
+            tx_id: tx.tx,
+            client_id: tx.client,
+            tx_type: tx.tx_type,
+            amount: tx.amount,
+            currency: tx.currency,
+            dispute: tx.dispute,
+            disputed_amount: tx.disputed_amount,
+            disputed_total: tx.disputed_total,
+            time: tx.time,
+            seq: tx.seq,
+            note: None,
+        }
+    }
+}
+
+/// Writes the complete client state to `path`, a record at a time, so spilled transactions
+/// are read back one by one rather than all at once. The file is written next to its
+/// destination and renamed into place, so an interrupted save never leaves a truncated
+/// snapshot behind.
+pub async fn save(state: &AppState, path: &Path) -> Result<(), EngineError> {
+    // Between files, or between the batches of one that can't abort, every logged event has
+    // been applied.
+    let _ingest = state.ingest.read().await;
+    let client_map = state.client_map.read().await;
+    let event_log = state.event_log.read().await;
+
+    let tmp_path = path.with_extension("tmp");
+    let snapshot_error = |e: std::io::Error| {
+        EngineError::SnapshotError(format!("Failed to write {}: {}", path.display(), e))
+    };
+
+    let mut writer = BufWriter::new(File::create(&tmp_path).map_err(snapshot_error)?);
+    let mut write = |record: SnapshotRecord| {
+        serde_json::to_writer(&mut writer, &record)
+            .map_err(|e| EngineError::SnapshotError(e.to_string()))?;
+        writer.write_all(b"\n").map_err(snapshot_error)
+    };
+
+    write(SnapshotRecord::Header(Header {
+        version: SNAPSHOT_VERSION,
+        event_seq: event_log.last_seq(),
+        clock: event_log.clock(),
+    }))?;
+
+    // In ID order, to keep snapshots of the same state byte-identical.
+    let mut clients: Vec<&Client> = client_map.values().collect();
+    clients.sort_by_key(|client| client.get_client_id());
+
+    for client in clients {
+        write(SnapshotRecord::Client(ClientSnapshot::from(client)))?;
+
+        for tx in client.transactions() {
+            write(SnapshotRecord::Transaction(TransactionSnapshot::from(
+                tx?.as_ref(),
+            )))?;
+        }
+    }
+
+    for (tx, client) in client_map.tx_index().iter() {
+        let stored = match client_map.get(client) {
+            Some(owner) => owner.transaction(tx)?.is_some(),
+            None => false,
+        };
+
+        if !stored {
+            write(SnapshotRecord::Claim(ClaimSnapshot {
+                tx,
+                client,
+                expired: client_map.tx_index().is_expired(tx),
+            }))?;
+        }
+    }
+
+    writer
+        .into_inner()
+        .map_err(|e| snapshot_error(e.into_error()))?
+        .sync_all()
+        .map_err(snapshot_error)?;
+
+    fs::rename(&tmp_path, path).map_err(snapshot_error)?;
+
+    Ok(())
+}
+
+/// Builds a fresh engine state from the snapshot at `path`.
+pub fn load(path: &Path, config: EngineConfig) -> Result<AppState, EngineError> {
+    read(path, AppState::new(config))
+}
+
+/// Like [`load`], but the restored transactions go straight to `spill`, to be read back from
+/// disk when disputed.
+pub fn load_spilled(
+    path: &Path,
+    config: EngineConfig,
+    spill: Arc<TxSpill>,
+) -> Result<AppState, EngineError> {
+    let mut state = AppState::new(config);
+    state.tx_spill = Some(spill);
+
+    read(path, state)
+}
+
+fn read(path: &Path, mut state: AppState) -> Result<AppState, EngineError> {
+    let file = File::open(path).map_err(|e| {
+        EngineError::SnapshotError(format!("Failed to open {}: {}", path.display(), e))
+    })?;
+    let read_error = |e: &dyn std::fmt::Display| {
+        EngineError::SnapshotError(format!("Failed to read {}: {}", path.display(), e))
+    };
+
+    let mut lines = BufReader::new(file).lines();
+
+    let header = match lines.next() {
+        Some(line) => {
+            let line = line.map_err(|e| read_error(&e))?;
+            serde_json::from_str(&line).map_err(|e| read_error(&e))?
+        }
+        None => return Err(read_error(&"the snapshot is empty")),
+    };
+    let SnapshotRecord::Header(header) = header else {
+        return Err(read_error(&"the snapshot does not start with its header"));
+    };
+
+    if header.version > SNAPSHOT_VERSION {
+        return Err(EngineError::SnapshotError(format!(
+            "Snapshot version {} is newer than the supported version {}",
+            header.version, SNAPSHOT_VERSION
+        )));
+    }
+
+    *state.event_log.get_mut() = EventLog::with_base(header.event_seq, header.clock);
+
+    let spill = state.tx_spill.clone();
+    let mut client_map = state.client_map.get_mut();
+
+    for line in lines {
+        let line = line.map_err(|e| read_error(&e))?;
+
+        match serde_json::from_str(&line).map_err(|e| read_error(&e))? {
+            SnapshotRecord::Client(client) => client_map.insert(Client::from(client)),
+            SnapshotRecord::Transaction(tx) => {
+                let (tx_id, client_id) = (tx.tx, tx.client);
+                let client = client_map.get_mut(client_id).ok_or_else(|| {
+                    read_error(&format!(
+                        "transaction {tx_id} comes before client {client_id}"
+                    ))
+                })?;
+
+                client.restore(Transaction::from(tx), spill.as_ref())?;
+                let _ = client_map.tx_index_mut().claim(tx_id, client_id);
+            }
+            SnapshotRecord::Claim(claim) => {
+                let tx_index = client_map.tx_index_mut();
+                let _ = tx_index.claim(claim.tx, claim.client);
+
+                if claim.expired {
+                    tx_index.expire(claim.tx);
+                }
+            }
+            SnapshotRecord::Header(_) | SnapshotRecord::Unknown => {}
+        }
+    }
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DisputePolicy,
+        fixtures::{tx, TempDir},
+    };
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() -> Result<(), EngineError> {
+        let mut state = AppState::new(EngineConfig::default());
+        *state.event_log.get_mut() = EventLog::with_base(10, 2);
+        // A timestamp is kept as it is, whatever the sequence numbers.
+        let timed = Transaction {
+            time: Some(2),
+            seq: 9,
+            ..tx(TransactionType::Deposit, 1, 5, Some("1.0"))
+        };
+
+        {
+            let mut client_map = state.client_map.write().await;
+            for tx in [
+                tx(TransactionType::Deposit, 1, 1, Some("5.0")),
+                tx(TransactionType::Deposit, 1, 2, Some("1.5")),
+                tx(TransactionType::Dispute, 1, 1, None),
+                tx(TransactionType::Deposit, 2, 3, Some("2.0")),
+                tx(TransactionType::Dispute, 2, 3, None),
+                tx(TransactionType::ChargeBack, 2, 3, None),
+                timed.clone(),
+            ] {
+                client_map.apply(&tx, &DisputePolicy::default())?;
+            }
+
+            // Rejected, but client 3 owns ID 4 from now on.
+            assert_eq!(
+                client_map.apply(
+                    &tx(TransactionType::Withdrawal, 3, 4, Some("1.0")),
+                    &DisputePolicy::default()
+                ),
+                Err(EngineError::InsufficientFunds)
+            );
+        }
+
+        let dir = TempDir::new("snapshot");
+        let path = dir.join("state.json");
+
+        save(&state, &path).await?;
+        let restored = load(&path, EngineConfig::default())?;
+        restored.verify().await?;
+
+        {
+            let mut client_map = restored.client_map.write().await;
+            let usd = Currency::default();
+
+            assert_eq!(
+                client_map[&1].summary_or_empty(usd).get_held(),
+                "5.0".parse()?
+            );
+            assert_eq!(client_map[&1].transaction(5)?.as_deref(), Some(&timed));
+            assert_eq!(client_map[&2].status(), AccountStatus::Locked);
+            assert_eq!(
+                client_map[&2].transaction(3)?.map(|tx| tx.dispute),
+                Some(DisputeState::ChargedBack)
+            );
+
+            // Disputes keep working against transactions from before the restart.
+            client_map.apply(
+                &tx(TransactionType::Resolve, 1, 1, None),
+                &DisputePolicy::default(),
+            )?;
+            assert_eq!(
+                client_map[&1].summary_or_empty(usd).get_available(),
+                "7.5".parse()?
+            );
+
+            assert_eq!(
+                client_map.apply(
+                    &tx(TransactionType::Deposit, 1, 2, Some("1.0")),
+                    &DisputePolicy::default()
+                ),
+                Err(EngineError::DuplicateTransaction("2".to_string()))
+            );
+
+            // Transaction IDs keep their owners.
+            assert_eq!(client_map.tx_index().owner(3), Some(2));
+            assert_eq!(client_map.tx_index().owner(4), Some(3));
+            assert_eq!(
+                client_map.apply(
+                    &tx(TransactionType::Deposit, 1, 4, Some("1.0")),
+                    &DisputePolicy::default()
+                ),
+                Err(EngineError::DuplicateTransaction("4".to_string()))
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_minimal_snapshot() -> Result<(), EngineError> {
+        // Without the optional sections, plus a field and a record kind this build does not
+        // know.
+        let dir = TempDir::new("snapshot_minimal");
+        let path = dir.join("state.json");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"kind":"header","version":1,"event_seq":3,"clock":0}"#,
+                "\n",
+                r#"{"kind":"client","client":4,"balances":[{"currency":"USD","available":"1.0000","held":"0.0000","total":"1.0000","status":"locked"}],"extra":true}"#,
+                "\n",
+                r#"{"kind":"later"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        let mut state = load(&path, EngineConfig::default())?;
+        let client_map = state.client_map.get_mut();
+        let client = client_map.get(4).unwrap();
+
+        assert_eq!(client.status(), AccountStatus::Locked);
+        assert_eq!(client.transactions().count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_transaction_before_client() {
+        let dir = TempDir::new("snapshot_orphan");
+        let path = dir.join("state.json");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"kind":"header","version":1,"event_seq":0,"clock":0}"#,
+                "\n",
+                r#"{"kind":"transaction","tx":1,"client":1,"type":"deposit","amount":"1.0","currency":"USD","dispute":"none","disputed_amount":"0","disputed_total":"0","seq":1}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            load(&path, EngineConfig::default()),
+            Err(EngineError::SnapshotError(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_newer_snapshot() {
+        let dir = TempDir::new("snapshot_new");
+        let path = dir.join("state.json");
+        fs::write(
+            &path,
+            r#"{"kind":"header","version":999,"event_seq":0,"clock":0}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            load(&path, EngineConfig::default()),
+            Err(EngineError::SnapshotError(_))
+        ));
+    }
+}
@@ -1,21 +1,51 @@
-use serde::Deserialize;
-use std::fmt::Display;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
 
-use crate::EngineError;
+use crate::{
+    admin::{AdminNote, AdminRecord},
+    amount::Amount,
+    config::{DisputeWindow, EngineConfig},
+    currency::Currency,
+    dispute::DisputeState,
+    EngineError,
+};
 
 const DEPOSIT: &str = "deposit";
 const WITHDRAWAL: &str = "withdrawal";
 const DISPUTE: &str = "dispute";
 const RESOLVE: &str = "resolve";
 const CHARGE_BACK: &str = "chargeback";
+const UNLOCK: &str = "unlock";
+const FREEZE: &str = "freeze";
+const UNFREEZE: &str = "unfreeze";
+const CLOSE: &str = "close";
 
-#[derive(Debug, PartialEq, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     ChargeBack,
+    // Admin operations on the account status, only accepted from admin sources.
+    Unlock,
+    Freeze,
+    Unfreeze,
+    Close,
+}
+
+impl TransactionType {
+    /// Whether this is a privileged operation on the account status.
+    pub fn is_admin(self) -> bool {
+        matches!(
+            self,
+            TransactionType::Unlock
+                | TransactionType::Freeze
+                | TransactionType::Unfreeze
+                | TransactionType::Close
+        )
+    }
 }
 
 impl Display for TransactionType {
@@ -26,10 +56,42 @@ impl Display for TransactionType {
             TransactionType::Dispute => write!(f, "{DISPUTE}"),
             TransactionType::Resolve => write!(f, "{RESOLVE}"),
             TransactionType::ChargeBack => write!(f, "{CHARGE_BACK}"),
+            TransactionType::Unlock => write!(f, "{UNLOCK}"),
+            TransactionType::Freeze => write!(f, "{FREEZE}"),
+            TransactionType::Unfreeze => write!(f, "{UNFREEZE}"),
+            TransactionType::Close => write!(f, "{CLOSE}"),
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = EngineError;
+
+    /// Ignores case and `-`, `_` or spaces between words, so `Charge-Back` is a chargeback.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized: String = s
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_' | ' '))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+
+        match normalized.as_str() {
+            DEPOSIT => Ok(TransactionType::Deposit),
+            WITHDRAWAL => Ok(TransactionType::Withdrawal),
+            DISPUTE => Ok(TransactionType::Dispute),
+            RESOLVE => Ok(TransactionType::Resolve),
+            CHARGE_BACK => Ok(TransactionType::ChargeBack),
+            UNLOCK => Ok(TransactionType::Unlock),
+            FREEZE => Ok(TransactionType::Freeze),
+            UNFREEZE => Ok(TransactionType::Unfreeze),
+            CLOSE => Ok(TransactionType::Close),
+            _ => Err(EngineError::InvalidTransaction(format!("Type: {s}"))),
         }
     }
 }
 
+/// A row of a transactions input file, with the fields in [`TransactionRecord::COLUMNS`]
+/// order.
 #[derive(Debug, Deserialize, Clone)]
 pub struct TransactionRecord {
     #[serde(rename = "type")]
@@ -41,45 +103,143 @@ pub struct TransactionRecord {
     #[serde(rename = "tx")]
     pub tx_id: u32,
 
+    // Kept as text so the configured rounding mode can be applied on conversion.
     #[serde(rename = "amount")]
-    pub amount: Option<f64>,
+    pub amount: Option<String>,
+
+    // Optional column, files without it fall back to the configured default currency.
+    #[serde(rename = "currency", default)]
+    pub currency: Option<String>,
+
+    // Optional column, seconds since the Unix epoch. Required by a dispute window in time
+    // units; rows without it are only ordered by their sequence number, see `Transaction::seq`.
+    #[serde(rename = "timestamp", default)]
+    pub timestamp: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Transaction {
     pub tx_id: u32,
     pub client_id: u16,
     pub tx_type: TransactionType,
-    pub amount: Option<f64>,
-    pub disputed: bool,
-    pub resolved: bool,
+    pub amount: Option<Amount>,
+    pub currency: Currency,
+    /// Where a deposit or withdrawal stands in the dispute lifecycle.
+    #[serde(default)]
+    pub dispute: DisputeState,
+    /// The part of the amount currently held by open disputes.
+    #[serde(default)]
+    pub disputed_amount: Amount,
+    /// The part of the amount disputed so far, whether still held, resolved or charged back.
+    /// Disputes never take it past the amount.
+    #[serde(default)]
+    pub disputed_total: Amount,
+    /// When the transaction happened, in seconds since the Unix epoch: the row's `timestamp`,
+    /// if it has one. The engine clock never goes back, so a timestamp earlier than one logged
+    /// before it is taken to be that one.
+    #[serde(default)]
+    pub time: Option<u64>,
+    /// Sequence number of the transaction's event, which orders rows with or without a
+    /// timestamp.
+    #[serde(default)]
+    pub seq: u64,
+    /// Reason and operator of an admin operation.
+    #[serde(skip)]
+    pub note: Option<Box<AdminNote>>,
 }
 
-impl TryFrom<TransactionRecord> for Transaction {
-    type Error = EngineError;
+impl TransactionRecord {
+    /// Column names, in the order the fields of a row are read.
+    pub const COLUMNS: &'static [&'static str] =
+        &["type", "client", "tx", "amount", "currency", "timestamp"];
+}
 
-    fn try_from(value: TransactionRecord) -> Result<Self, Self::Error> {
-        if let Some(tx_type) = match value.tx_type.as_str() {
-            DEPOSIT => Some(TransactionType::Deposit),
-            WITHDRAWAL => Some(TransactionType::Withdrawal),
-            DISPUTE => Some(TransactionType::Dispute),
-            RESOLVE => Some(TransactionType::Resolve),
-            CHARGE_BACK => Some(TransactionType::ChargeBack),
-            _ => None,
-        } {
-            Ok(Transaction {
+impl Transaction {
+    /// Converts a raw record, applying the configured rounding mode to amounts with excess
+    /// precision and the default currency to rows without one.
+    pub fn from_record(
+        value: TransactionRecord,
+        config: &EngineConfig,
+    ) -> Result<Self, EngineError> {
+        let amount = match value.amount.as_deref() {
+            Some(amount) => Some(Amount::parse(amount, config.rounding)?),
+            None => None,
+        };
+
+        let currency = match value.currency.as_deref() {
+            Some(currency) if !currency.is_empty() => currency.parse()?,
+            _ => config.default_currency,
+        };
+
+        match value.tx_type.parse::<TransactionType>() {
+            // Admin operations are only read from admin sources, see `from_admin_record`.
+            Ok(tx_type) if tx_type.is_admin() => Err(EngineError::InvalidTransaction(format!(
+                "TX ID: {}, Type: {} is an admin operation",
+                value.tx_id, value.tx_type
+            ))),
+            Ok(_)
+                if value.timestamp.is_none()
+                    && config.disputes.window.is_some_and(DisputeWindow::is_timed) =>
+            {
+                Err(EngineError::InvalidTransaction(format!(
+                    "TX ID: {} has no timestamp, which the dispute window needs",
+                    value.tx_id
+                )))
+            }
+            Ok(tx_type) => Ok(Transaction {
                 tx_type,
                 client_id: value.client_id,
                 tx_id: value.tx_id,
-                amount: value.amount,
-                disputed: false,
-                resolved: false,
-            })
-        } else {
-            Err(EngineError::InvalidTransaction(format!(
+                amount,
+                currency,
+                dispute: DisputeState::None,
+                disputed_amount: Amount::ZERO,
+                disputed_total: Amount::ZERO,
+                time: value.timestamp,
+                seq: 0,
+                note: None,
+            }),
+            Err(_) => Err(EngineError::InvalidTransaction(format!(
                 "TX ID: {}, Type: {}",
                 value.tx_id, value.tx_type
-            )))
+            ))),
+        }
+    }
+
+    /// Converts a row of an admin input file, see [`AdminRecord`].
+    pub fn from_admin_record(
+        value: AdminRecord,
+        config: &EngineConfig,
+    ) -> Result<Self, EngineError> {
+        match value.tx_type.parse::<TransactionType>() {
+            Ok(tx_type) if tx_type.is_admin() => Ok(Transaction {
+                tx_type,
+                client_id: value.client_id,
+                tx_id: value.tx_id,
+                amount: None,
+                currency: config.default_currency,
+                dispute: DisputeState::None,
+                disputed_amount: Amount::ZERO,
+                disputed_total: Amount::ZERO,
+                time: None,
+                seq: 0,
+                note: Some(Box::new(AdminNote {
+                    reason: value.reason,
+                    operator: value.operator,
+                })),
+            }),
+            _ => Err(EngineError::InvalidTransaction(format!(
+                "TX ID: {}, Type: {} is not an admin operation",
+                value.tx_id, value.tx_type
+            ))),
         }
     }
 }
+
+impl TryFrom<TransactionRecord> for Transaction {
+    type Error = EngineError;
+
+    fn try_from(value: TransactionRecord) -> Result<Self, Self::Error> {
+        Transaction::from_record(value, &EngineConfig::default())
+    }
+}
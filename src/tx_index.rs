@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use crate::{
+    dispute::window_expired,
+    transaction::{Transaction, TransactionType},
+    EngineError,
+};
+
+/// Bits of a transaction ID that select its page; the rest select the slot in the page.
+const PAGE_BITS: u32 = 12;
+const PAGE_SLOTS: usize = 1 << PAGE_BITS;
+const SLOT_MASK: u32 = PAGE_SLOTS as u32 - 1;
+
+/// Entries above which a sparse page takes more memory than a dense one.
+const DENSE_THRESHOLD: usize = (PAGE_SLOTS * 2 + PAGE_SLOTS / 8) / 4;
+
+/// The client owning every deposit and withdrawal ID, engine wide, so a transaction ID is
+/// only ever used by one client.
+///
+/// IDs are grouped in pages of 4096. A page stores its claimed IDs as a sorted list of
+/// `(slot, owner)` pairs, 4 bytes each, until it is full enough that an owner for every slot
+/// plus a bitmap of claimed slots is smaller, a little over 2 bytes per ID. Hundreds of
+/// millions of IDs therefore take at most a few bytes each, and sequential IDs close to 2.
+///
+/// IDs whose transaction was dropped past the dispute window are flagged in a separate bitmap
+/// per page, so disputes naming them are still told why they fail.
+#[derive(Debug, Default)]
+pub struct TxIndex {
+    pages: HashMap<u32, Page>,
+    expired: HashMap<u32, Box<[u64; PAGE_SLOTS / 64]>>,
+    len: usize,
+}
+
+#[derive(Debug)]
+enum Page {
+    /// Claimed slots and their owners, sorted by slot.
+    Sparse(Vec<(u16, u16)>),
+    Dense {
+        claimed: Box<[u64; PAGE_SLOTS / 64]>,
+        owners: Box<[u16; PAGE_SLOTS]>,
+    },
+}
+
+impl Page {
+    fn owner(&self, slot: u16) -> Option<u16> {
+        match self {
+            Page::Sparse(entries) => entries
+                .binary_search_by_key(&slot, |(slot, _)| *slot)
+                .ok()
+                .map(|index| entries[index].1),
+            Page::Dense { claimed, owners } => {
+                let slot = slot as usize;
+                (claimed[slot / 64] & (1 << (slot % 64)) != 0).then(|| owners[slot])
+            }
+        }
+    }
+
+    /// Claims an unclaimed slot.
+    fn insert(&mut self, slot: u16, owner: u16) {
+        match self {
+            Page::Sparse(entries) => {
+                let index = entries.partition_point(|(claimed, _)| *claimed < slot);
+                entries.insert(index, (slot, owner));
+
+                if entries.len() > DENSE_THRESHOLD {
+                    *self = Page::dense(entries);
+                }
+            }
+            Page::Dense { claimed, owners } => {
+                let slot = slot as usize;
+                claimed[slot / 64] |= 1 << (slot % 64);
+                owners[slot] = owner;
+            }
+        }
+    }
+
+    /// Releases a claimed slot.
+    fn remove(&mut self, slot: u16) {
+        match self {
+            Page::Sparse(entries) => {
+                if let Ok(index) = entries.binary_search_by_key(&slot, |(slot, _)| *slot) {
+                    entries.remove(index);
+                }
+            }
+            Page::Dense { claimed, .. } => {
+                let slot = slot as usize;
+                claimed[slot / 64] &= !(1 << (slot % 64));
+            }
+        }
+    }
+
+    fn dense(entries: &[(u16, u16)]) -> Self {
+        let mut claimed = Box::new([0; PAGE_SLOTS / 64]);
+        let mut owners = Box::new([0; PAGE_SLOTS]);
+
+        for (slot, owner) in entries {
+            let slot = *slot as usize;
+            claimed[slot / 64] |= 1 << (slot % 64);
+            owners[slot] = *owner;
+        }
+
+        Page::Dense { claimed, owners }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Page::Sparse(entries) => entries.is_empty(),
+            Page::Dense { claimed, .. } => claimed.iter().all(|word| *word == 0),
+        }
+    }
+
+    /// Claimed slots and their owners, in slot order.
+    fn entries(&self) -> Box<dyn Iterator<Item = (u16, u16)> + '_> {
+        match self {
+            Page::Sparse(entries) => Box::new(entries.iter().copied()),
+            Page::Dense { claimed, owners } => Box::new(
+                (0..PAGE_SLOTS)
+                    .filter(|slot| claimed[slot / 64] & (1 << (slot % 64)) != 0)
+                    .map(|slot| (slot as u16, owners[slot])),
+            ),
+        }
+    }
+}
+
+fn split(tx_id: u32) -> (u32, u16) {
+    (tx_id >> PAGE_BITS, (tx_id & SLOT_MASK) as u16)
+}
+
+impl TxIndex {
+    /// The client that first used `tx_id` for a deposit or withdrawal.
+    pub fn owner(&self, tx_id: u32) -> Option<u16> {
+        let (page, slot) = split(tx_id);
+        self.pages.get(&page)?.owner(slot)
+    }
+
+    /// Records `client_id` as the owner of `tx_id` unless it already has one. Returns the
+    /// existing owner otherwise.
+    pub fn claim(&mut self, tx_id: u32, client_id: u16) -> Result<bool, u16> {
+        let (page, slot) = split(tx_id);
+        let page = self
+            .pages
+            .entry(page)
+            .or_insert_with(|| Page::Sparse(Vec::new()));
+
+        match page.owner(slot) {
+            Some(owner) if owner == client_id => Ok(false),
+            Some(owner) => Err(owner),
+            None => {
+                page.insert(slot, client_id);
+                self.len += 1;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Flags `tx_id` as past the dispute window, once its transaction is dropped.
+    pub fn expire(&mut self, tx_id: u32) {
+        let (page, slot) = split(tx_id);
+        let slot = slot as usize;
+
+        self.expired
+            .entry(page)
+            .or_insert_with(|| Box::new([0; PAGE_SLOTS / 64]))[slot / 64] |= 1 << (slot % 64);
+    }
+
+    pub fn is_expired(&self, tx_id: u32) -> bool {
+        let (page, slot) = split(tx_id);
+        let slot = slot as usize;
+
+        self.expired
+            .get(&page)
+            .is_some_and(|expired| expired[slot / 64] & (1 << (slot % 64)) != 0)
+    }
+
+    /// Forgets the owner of `tx_id`, for IDs claimed by a file that was discarded.
+    pub fn release(&mut self, tx_id: u32) {
+        let (page_id, slot) = split(tx_id);
+
+        if let Some(expired) = self.expired.get_mut(&page_id) {
+            let slot = slot as usize;
+            expired[slot / 64] &= !(1 << (slot % 64));
+        }
+
+        if let Some(page) = self.pages.get_mut(&page_id) {
+            if page.owner(slot).is_some() {
+                page.remove(slot);
+                self.len -= 1;
+            }
+
+            if page.is_empty() {
+                self.pages.remove(&page_id);
+            }
+        }
+    }
+
+    /// Checks a transaction against the index before it is applied, in log order:
+    ///
+    /// * a deposit or withdrawal claims its ID, and fails as a duplicate if another client
+    ///   already owns it, even if that client's transaction was rejected;
+    /// * a dispute, resolve or chargeback fails if it names another client's transaction, or
+    ///   one that was dropped past the dispute window.
+    ///
+    /// Returns whether the transaction claimed a new ID.
+    pub fn admit(&mut self, transaction: &Transaction) -> Result<bool, EngineError> {
+        let tx_id = transaction.tx_id;
+        let client_id = transaction.client_id;
+
+        match transaction.tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => self
+                .claim(tx_id, client_id)
+                .map_err(|_| EngineError::DuplicateTransaction(format!("{}", tx_id))),
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::ChargeBack => {
+                match self.owner(tx_id) {
+                    Some(owner) if owner != client_id => Err(EngineError::TxOwnerMismatch(
+                        format!("TX {} is not a transaction of client {}", tx_id, client_id),
+                    )),
+                    Some(_) if self.is_expired(tx_id) => Err(window_expired(tx_id)),
+                    _ => Ok(false),
+                }
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Every claimed ID and its owner, in ID order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u16)> + '_ {
+        let mut pages: Vec<u32> = self.pages.keys().copied().collect();
+        pages.sort_unstable();
+
+        pages.into_iter().flat_map(move |page| {
+            self.pages[&page]
+                .entries()
+                .map(move |(slot, owner)| (page << PAGE_BITS | slot as u32, owner))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_and_release() {
+        let mut index = TxIndex::default();
+
+        assert_eq!(index.claim(5, 1), Ok(true));
+        assert_eq!(index.claim(5, 1), Ok(false));
+        assert_eq!(index.claim(5, 2), Err(1));
+        assert_eq!(index.owner(5), Some(1));
+        assert_eq!(index.owner(6), None);
+
+        // Fill a page past the dense threshold, plus IDs at the ends of the range.
+        for tx_id in 4096..8192 {
+            assert_eq!(index.claim(tx_id, (tx_id % 7) as u16), Ok(true));
+        }
+        index.claim(u32::MAX, 9).unwrap();
+        index.claim(0, 0).unwrap();
+
+        assert_eq!(index.len(), 4096 + 3);
+        assert!(matches!(index.pages[&1], Page::Dense { .. }));
+        assert_eq!(index.owner(6000), Some(6000 % 7));
+        assert_eq!(index.owner(u32::MAX), Some(9));
+        assert_eq!(index.claim(4200, 3), Err(0));
+
+        let mut claimed: Vec<(u32, u16)> = index.iter().collect();
+        claimed.sort();
+        assert_eq!(claimed.len(), index.len());
+        assert_eq!(claimed[..2], [(0, 0), (5, 1)]);
+
+        for tx_id in 4096..8192 {
+            index.release(tx_id);
+        }
+        index.expire(5);
+        assert!(index.is_expired(5));
+        index.release(5);
+        index.release(5);
+        assert!(!index.is_expired(5));
+
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.owner(6000), None);
+        assert_eq!(index.claim(5, 2), Ok(true));
+        assert!(!index.pages.contains_key(&1));
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    amount::Amount,
+    client::Client,
+    config::Cutoff,
+    currency::Currency,
+    dispute::DisputeState,
+    transaction::{Transaction, TransactionType},
+    EngineError,
+};
+
+/// Approximate memory taken by a transaction kept in memory, including its share of the hash
+/// map and of the eviction queue. Used to turn a memory ceiling into a number of transactions.
+pub const HOT_TX_BYTES: usize = 120;
+
+/// Size of a transaction record in the spill file.
+const RECORD_LEN: usize = 64;
+
+/// File older transactions are spilled to once the transactions kept in memory exceed
+/// `hot_limit`, shared by every client.
+///
+/// The file is addressed by transaction ID: the record of transaction `id` lives at
+/// `id * 64`, so finding a spilled transaction takes no memory at all. Unused IDs are holes
+/// of a sparse file and take no disk space. Each record also links to the previous
+/// transaction its client spilled, so a client can list its spilled transactions.
+///
+/// Spilled transactions are a cache of the engine state, like the transactions kept in
+/// memory: the file is created empty and removed when the engine stops.
+#[derive(Debug)]
+pub struct TxSpill {
+    file: File,
+    path: PathBuf,
+    hot_limit: usize,
+}
+
+fn spill_error(e: impl std::fmt::Display) -> EngineError {
+    EngineError::OtherError(format!("Transaction spill file: {}", e))
+}
+
+impl TxSpill {
+    /// Creates an empty spill file in `dir`.
+    pub fn create(dir: &Path, hot_limit: usize) -> Result<Arc<Self>, EngineError> {
+        let path = dir.join(format!("tx_engine_spill_{}.bin", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(spill_error)?;
+
+        Ok(Arc::new(TxSpill {
+            file,
+            path,
+            hot_limit,
+        }))
+    }
+
+    /// Transactions kept in memory across all clients before older ones are spilled.
+    pub fn hot_limit(&self) -> usize {
+        self.hot_limit
+    }
+
+    fn read(&self, tx_id: u32) -> Result<Option<Record>, EngineError> {
+        let mut bytes = [0; RECORD_LEN];
+
+        match self
+            .file
+            .read_exact_at(&mut bytes, tx_id as u64 * RECORD_LEN as u64)
+        {
+            Ok(()) => Ok(Record::decode(tx_id, &bytes)),
+            // Past the end of the file, nothing was ever spilled this far.
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(spill_error(e)),
+        }
+    }
+
+    fn write(&self, record: &Record) -> Result<(), EngineError> {
+        self.file
+            .write_all_at(
+                &record.encode(),
+                record.transaction.tx_id as u64 * RECORD_LEN as u64,
+            )
+            .map_err(spill_error)
+    }
+
+    /// Forgets the record of `tx_id`, as if it had never been spilled.
+    fn clear(&self, tx_id: u32) -> Result<(), EngineError> {
+        self.file
+            .write_all_at(&[0; RECORD_LEN], tx_id as u64 * RECORD_LEN as u64)
+            .map_err(spill_error)
+    }
+}
+
+impl Drop for TxSpill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A spilled transaction and the transaction its client spilled before it.
+struct Record {
+    transaction: Transaction,
+    previous: Option<u32>,
+}
+
+impl Record {
+    /// Layout: present flag, type, dispute state, amount flag, client ID, time flag, 1 unused
+    /// byte, currency, amount, disputed amount, previous transaction ID + 1, time, disputed
+    /// total and sequence number, little endian.
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let tx = &self.transaction;
+        let mut bytes = [0; RECORD_LEN];
+
+        bytes[0] = 1;
+        bytes[1] = match tx.tx_type {
+            TransactionType::Withdrawal => 1,
+            _ => 0,
+        };
+        bytes[2] = match tx.dispute {
+            DisputeState::None => 0,
+            DisputeState::Open => 1,
+            DisputeState::Resolved => 2,
+            DisputeState::ChargedBack => 3,
+        };
+        bytes[3] = tx.amount.is_some() as u8;
+        bytes[4..6].copy_from_slice(&tx.client_id.to_le_bytes());
+        bytes[6] = tx.time.is_some() as u8;
+        bytes[8..16].copy_from_slice(&tx.currency.to_bytes());
+        bytes[16..24].copy_from_slice(&tx.amount.unwrap_or_default().raw().to_le_bytes());
+        bytes[24..32].copy_from_slice(&tx.disputed_amount.raw().to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.previous.map_or(0, |id| id as u64 + 1).to_le_bytes());
+        bytes[40..48].copy_from_slice(&tx.time.unwrap_or_default().to_le_bytes());
+        bytes[48..56].copy_from_slice(&tx.disputed_total.raw().to_le_bytes());
+        bytes[56..64].copy_from_slice(&tx.seq.to_le_bytes());
+
+        bytes
+    }
+
+    fn decode(tx_id: u32, bytes: &[u8; RECORD_LEN]) -> Option<Self> {
+        if bytes[0] == 0 {
+            return None;
+        }
+
+        let word = |range: std::ops::Range<usize>| -> [u8; 8] {
+            bytes[range].try_into().expect("eight bytes")
+        };
+        let previous = u64::from_le_bytes(word(32..40));
+
+        Some(Record {
+            transaction: Transaction {
+                tx_id,
+                client_id: u16::from_le_bytes([bytes[4], bytes[5]]),
+                tx_type: match bytes[1] {
+                    1 => TransactionType::Withdrawal,
+                    _ => TransactionType::Deposit,
+                },
+                amount: (bytes[3] == 1).then(|| Amount::from_raw(i64::from_le_bytes(word(16..24)))),
+                currency: Currency::from_bytes(word(8..16)),
+                dispute: match bytes[2] {
+                    1 => DisputeState::Open,
+                    2 => DisputeState::Resolved,
+                    3 => DisputeState::ChargedBack,
+                    _ => DisputeState::None,
+                },
+                disputed_amount: Amount::from_raw(i64::from_le_bytes(word(24..32))),
+                disputed_total: Amount::from_raw(i64::from_le_bytes(word(48..56))),
+                time: (bytes[6] == 1).then(|| u64::from_le_bytes(word(40..48))),
+                seq: u64::from_le_bytes(word(56..64)),
+                note: None,
+            },
+            previous: previous.checked_sub(1).map(|id| id as u32),
+        })
+    }
+}
+
+/// A client's deposits and withdrawals: the most recent in memory, older ones in the
+/// [spill file](TxSpill) once the engine runs out of its memory budget.
+///
+/// A spilled transaction that changes is brought back into memory, which then takes
+/// precedence over the copy on disk until it is spilled again.
+#[derive(Debug, Clone, Default)]
+pub struct TxStore {
+    client_id: u16,
+    hot: HashMap<u32, Transaction>,
+    /// IDs of the transactions in memory, oldest first.
+    order: VecDeque<u32>,
+    spill: Option<Arc<TxSpill>>,
+    /// The last transaction spilled, where the client's list on disk starts.
+    last_spilled: Option<u32>,
+}
+
+impl TxStore {
+    pub fn new(client_id: u16) -> Self {
+        TxStore {
+            client_id,
+            ..TxStore::default()
+        }
+    }
+
+    pub fn get(&self, tx_id: u32) -> Result<Option<Cow<'_, Transaction>>, EngineError> {
+        if let Some(tx) = self.hot.get(&tx_id) {
+            return Ok(Some(Cow::Borrowed(tx)));
+        }
+
+        match &self.spill {
+            Some(spill) => Ok(spill
+                .read(tx_id)?
+                .map(|record| record.transaction)
+                .filter(|tx| tx.client_id == self.client_id)
+                .map(Cow::Owned)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains(&self, tx_id: u32) -> Result<bool, EngineError> {
+        Ok(self.get(tx_id)?.is_some())
+    }
+
+    /// Adds or replaces a transaction, keeping it in memory.
+    pub fn insert(&mut self, tx: Transaction) {
+        let tx_id = tx.tx_id;
+
+        if self.hot.insert(tx_id, tx).is_none() {
+            self.order.push_back(tx_id);
+        }
+    }
+
+    /// Adds a transaction straight to `spill`, e.g. one restored from a snapshot, so memory is
+    /// left to the transactions to come.
+    pub fn insert_spilled(
+        &mut self,
+        tx: Transaction,
+        spill: &Arc<TxSpill>,
+    ) -> Result<(), EngineError> {
+        self.spill.get_or_insert_with(|| spill.clone());
+
+        let tx_id = tx.tx_id;
+        spill.write(&Record {
+            transaction: tx,
+            previous: self.last_spilled,
+        })?;
+        self.last_spilled = Some(tx_id);
+
+        Ok(())
+    }
+
+    /// Drops transactions, in memory or spilled, e.g. the ones a discarded file added.
+    ///
+    /// Spilled ones are unlinked from the client's list on disk, which takes reading back the
+    /// transactions spilled since the oldest of them, so they should be recent.
+    pub fn remove(&mut self, tx_ids: &[u32]) -> Result<(), EngineError> {
+        for tx_id in tx_ids {
+            self.hot.remove(tx_id);
+        }
+
+        self.order.retain(|tx_id| self.hot.contains_key(tx_id));
+
+        let Some(spill) = self.spill.clone() else {
+            return Ok(());
+        };
+
+        let removed: HashSet<u32> = tx_ids.iter().copied().collect();
+        let mut pending = 0;
+        for tx_id in &removed {
+            if spill
+                .read(*tx_id)?
+                .is_some_and(|record| record.transaction.client_id == self.client_id)
+            {
+                pending += 1;
+            }
+        }
+
+        // Walk back past the oldest removed transaction, keeping the others in order.
+        let mut kept = Vec::new();
+        let mut next = self.last_spilled;
+        while pending > 0 {
+            let tx_id = next.ok_or_else(|| spill_error("a removed TX is not listed"))?;
+            let record = spill
+                .read(tx_id)?
+                .ok_or_else(|| spill_error(format!("TX {tx_id} is missing")))?;
+            next = record.previous;
+
+            if removed.contains(&tx_id) {
+                spill.clear(tx_id)?;
+                pending -= 1;
+            } else {
+                kept.push(record);
+            }
+        }
+
+        // Relink the kept ones, oldest first.
+        for mut record in kept.into_iter().rev() {
+            record.previous = next;
+            next = Some(record.transaction.tx_id);
+            spill.write(&record)?;
+        }
+        self.last_spilled = next;
+
+        Ok(())
+    }
+
+    /// Drops every transaction, in memory or spilled, e.g. of a client a discarded file
+    /// created.
+    pub fn clear(&mut self) -> Result<(), EngineError> {
+        self.hot.clear();
+        self.order.clear();
+
+        if let Some(spill) = &self.spill {
+            while let Some(tx_id) = self.last_spilled {
+                self.last_spilled = spill.read(tx_id)?.and_then(|record| record.previous);
+                spill.clear(tx_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of transactions kept in memory.
+    pub fn hot_len(&self) -> usize {
+        self.hot.len()
+    }
+
+    /// Every transaction, those in memory first by ID, then the spilled ones from the most
+    /// recent, so the same transactions are always listed in the same order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<Cow<'_, Transaction>, EngineError>> + '_ {
+        let mut hot: Vec<&Transaction> = self.hot.values().collect();
+        hot.sort_unstable_by_key(|tx| tx.tx_id);
+
+        let mut next = self.last_spilled;
+
+        let spilled = std::iter::from_fn(move || loop {
+            let tx_id = next?;
+            let spill = self.spill.as_ref()?;
+
+            match spill.read(tx_id) {
+                Ok(Some(record)) => {
+                    next = record.previous;
+
+                    // The copy in memory is newer.
+                    if !self.hot.contains_key(&tx_id) {
+                        return Some(Ok(Cow::Owned(record.transaction)));
+                    }
+                }
+                Ok(None) => {
+                    next = None;
+                    return Some(Err(spill_error(format!("TX {tx_id} is missing"))));
+                }
+                Err(e) => {
+                    next = None;
+                    return Some(Err(e));
+                }
+            }
+        });
+
+        hot.into_iter()
+            .map(|tx| Ok(Cow::Borrowed(tx)))
+            .chain(spilled)
+    }
+
+    /// Drops the transactions in memory made before `cutoff` that are not under an open
+    /// dispute, see [`Client::expire`]. Returns their IDs.
+    pub fn expire(&mut self, cutoff: Cutoff) -> Result<Vec<u32>, EngineError> {
+        let expired: Vec<u32> = self
+            .hot
+            .values()
+            .filter(|tx| cutoff.passed(tx) && tx.dispute != DisputeState::Open)
+            .map(|tx| tx.tx_id)
+            .collect();
+
+        for tx_id in &expired {
+            // A copy spilled before would come back in its older state, bring it up to date.
+            if let (Some(spill), Some(transaction)) = (&self.spill, self.hot.get(tx_id)) {
+                let spilled = spill.read(*tx_id)?;
+
+                if let Some(record) =
+                    spilled.filter(|record| record.transaction.client_id == self.client_id)
+                {
+                    spill.write(&Record {
+                        transaction: transaction.clone(),
+                        previous: record.previous,
+                    })?;
+                }
+            }
+
+            self.hot.remove(tx_id);
+        }
+
+        self.order.retain(|tx_id| self.hot.contains_key(tx_id));
+
+        Ok(expired)
+    }
+
+    /// Moves up to `count` of the oldest transactions in memory to `spill`.
+    pub fn spill(&mut self, count: usize, spill: &Arc<TxSpill>) -> Result<(), EngineError> {
+        self.spill.get_or_insert_with(|| spill.clone());
+
+        for _ in 0..count {
+            let Some(&tx_id) = self.order.front() else {
+                break;
+            };
+            let Some(transaction) = self.hot.get(&tx_id) else {
+                self.order.pop_front();
+                continue;
+            };
+
+            // A transaction spilled before keeps its place in the client's list.
+            let (previous, last_spilled) = match spill.read(tx_id)? {
+                Some(record) => (record.previous, self.last_spilled),
+                None => (self.last_spilled, Some(tx_id)),
+            };
+
+            // Only forgotten once it is safely on disk.
+            spill.write(&Record {
+                transaction: transaction.clone(),
+                previous,
+            })?;
+
+            self.last_spilled = last_spilled;
+            self.hot.remove(&tx_id);
+            self.order.pop_front();
+        }
+
+        Ok(())
+    }
+}
+
+/// Spills the oldest transactions of the clients of a shard once they keep more than `limit`
+/// transactions in memory, down to three quarters of it, so the next spill is some way off.
+/// Each client gives up a share proportional to what it keeps in memory.
+///
+/// The journal entries of every client are dropped as well; their balances stay.
+pub fn spill_shard<'a>(
+    clients: impl IntoIterator<Item = &'a mut Client>,
+    limit: usize,
+    spill: &Arc<TxSpill>,
+) -> Result<(), EngineError> {
+    let mut clients: Vec<&mut Client> = clients.into_iter().collect();
+    let hot: usize = clients.iter().map(|client| client.hot_transactions()).sum();
+
+    if hot <= limit {
+        return Ok(());
+    }
+
+    let excess = hot - limit * 3 / 4;
+
+    for client in clients.iter_mut() {
+        let count = (client.hot_transactions() * excess).div_ceil(hot);
+        client.spill(count, spill)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DisputeWindow,
+        fixtures::{tx, TempDir},
+    };
+
+    fn deposit(tx_id: u32, amount: &str) -> Transaction {
+        Transaction {
+            currency: "EUR".parse().unwrap(),
+            time: Some(tx_id as u64),
+            ..tx(TransactionType::Deposit, 7, tx_id, Some(amount))
+        }
+    }
+
+    #[test]
+    fn test_spill_and_fetch() -> Result<(), EngineError> {
+        let dir = TempDir::new("tx_store");
+        let spill = TxSpill::create(&dir, 2)?;
+
+        let mut store = TxStore::new(7);
+        for tx_id in [3, 1, 4_000_000_000, 5] {
+            store.insert(deposit(tx_id, "1.5"));
+        }
+
+        store.spill(3, &spill)?;
+        assert_eq!(store.hot_len(), 1);
+        assert_eq!(store.get(1)?.as_deref(), Some(&deposit(1, "1.5")));
+        assert!(store.contains(4_000_000_000)?);
+        assert!(!store.contains(2)?);
+
+        // A changed transaction comes back into memory and shadows its spilled copy.
+        let mut disputed = store.get(3)?.unwrap().into_owned();
+        disputed.dispute = DisputeState::Open;
+        disputed.disputed_amount = "1.5".parse()?;
+        store.insert(disputed.clone());
+        assert_eq!(store.get(3)?.as_deref(), Some(&disputed));
+
+        // Spilled again, it keeps its place in the list.
+        store.spill(1, &spill)?;
+        store.spill(1, &spill)?;
+        assert_eq!(store.hot_len(), 0);
+        assert_eq!(store.get(3)?.as_deref(), Some(&disputed));
+
+        let mut listed: Vec<Transaction> = store
+            .iter()
+            .map(|tx| tx.map(Cow::into_owned))
+            .collect::<Result<_, _>>()?;
+        listed.sort_by_key(|tx| tx.tx_id);
+        assert_eq!(
+            listed,
+            [
+                deposit(1, "1.5"),
+                disputed.clone(),
+                deposit(5, "1.5"),
+                deposit(4_000_000_000, "1.5")
+            ]
+        );
+
+        // Spilled transactions that are removed leave the rest of the list on disk.
+        store.insert(deposit(6, "2.0"));
+        store.insert(deposit(7, "2.0"));
+        store.spill(2, &spill)?;
+        store.remove(&[6])?;
+        assert!(!store.contains(6)?);
+        assert_eq!(store.get(7)?.as_deref(), Some(&deposit(7, "2.0")));
+        assert_eq!(
+            store
+                .iter()
+                .map(|tx| tx.map(|tx| tx.tx_id))
+                .collect::<Result<Vec<_>, _>>()?,
+            [7, 5, 4_000_000_000, 1, 3]
+        );
+
+        // Dropped past the dispute window, a transaction leaves its latest state on disk.
+        let mut resolved = disputed;
+        resolved.dispute = DisputeState::Resolved;
+        resolved.disputed_amount = Amount::ZERO;
+        store.insert(resolved.clone());
+        store.insert(deposit(9, "1.0"));
+        let cutoff = DisputeWindow::Seconds(0).cutoff(0, 9).unwrap();
+        assert_eq!(store.expire(cutoff)?, [3]);
+        assert_eq!(store.hot_len(), 1);
+        assert_eq!(store.get(3)?.as_deref(), Some(&resolved));
+
+        // Another client's store does not see the transactions.
+        let other = TxStore {
+            spill: Some(spill.clone()),
+            ..TxStore::new(8)
+        };
+        assert!(!other.contains(1)?);
+
+        store.clear()?;
+        assert!(!store.contains(1)?);
+        assert_eq!(store.iter().count(), 0);
+
+        drop(spill);
+        Ok(())
+    }
+}
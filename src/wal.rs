@@ -0,0 +1,764 @@
+use csv::Position;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    admin::AdminNote,
+    amount::Amount,
+    config::DisputePolicy,
+    currency::Currency,
+    dispute::DisputeState,
+    event_log::{EventLog, Outcome},
+    shard::ShardsMut,
+    transaction::{Transaction, TransactionType},
+    AppState, EngineError,
+};
+
+/// Where to resume reading an input file: the start of the first row not yet applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputOffset {
+    pub byte: u64,
+    pub line: u64,
+    pub record: u64,
+}
+
+impl From<&Position> for InputOffset {
+    fn from(position: &Position) -> Self {
+        InputOffset {
+            byte: position.byte(),
+            line: position.line(),
+            record: position.record(),
+        }
+    }
+}
+
+impl From<InputOffset> for Position {
+    fn from(offset: InputOffset) -> Self {
+        let mut position = Position::new();
+        position
+            .set_byte(offset.byte)
+            .set_line(offset.line)
+            .set_record(offset.record);
+        position
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WalTransaction {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+    currency: Currency,
+
+    /// See [`Transaction::time`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<AdminNote>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum WalRecord {
+    /// A row about to be applied, logged with the event sequence number it will get and the
+    /// offset just past it in its source file.
+    Apply {
+        seq: u64,
+        source: String,
+        line: u64,
+        next: InputOffset,
+        tx: WalTransaction,
+    },
+    /// The source's offset no longer applies, e.g. because the file was moved away.
+    Forget { source: String },
+    /// A file that may abort starts: its rows are only replayed once it commits.
+    Begin { source: String },
+    /// The file begun last for the source is committed.
+    Commit { source: String },
+    /// The file begun last for the source was discarded, with its rows.
+    Abort { source: String },
+}
+
+/// A file begun in the log, to commit or abort once it completes.
+#[derive(Debug)]
+pub struct WalBatch {
+    source: String,
+    offset: Option<InputOffset>,
+}
+
+/// Append-only write-ahead log of every row the engine applies, with its input offset.
+///
+/// Each record is written and flushed before the row is applied, so after a crash the state
+/// can be rebuilt from the last snapshot plus the log, and every input file resumed from the
+/// row after the last one logged. The rows of a file that may abort are logged between a
+/// [begin](Wal::begin) and a [commit](Wal::commit) or [abort](Wal::abort) record, and only
+/// replayed if it committed, so a crash mid-file leaves none of it applied. Records survive
+/// a process crash once flushed; they are synced to disk at the end of each file. The log is
+/// never truncated, so it also keeps the history to [`replay`] the engine from.
+#[derive(Debug)]
+pub struct Wal {
+    writer: BufWriter<File>,
+    offsets: HashMap<String, InputOffset>, // map: source -> resume offset
+}
+
+impl Wal {
+    /// Offset to resume `source` from, if any of its rows were logged.
+    pub fn offset(&self, source: &str) -> Option<InputOffset> {
+        self.offsets.get(source).copied()
+    }
+
+    pub fn append(
+        &mut self,
+        seq: u64,
+        source: &str,
+        line: u64,
+        next: InputOffset,
+        tx: &Transaction,
+    ) -> Result<(), EngineError> {
+        self.write(&WalRecord::Apply {
+            seq,
+            source: source.to_string(),
+            line,
+            next,
+            tx: WalTransaction {
+                tx_type: tx.tx_type,
+                client: tx.client_id,
+                tx: tx.tx_id,
+                amount: tx.amount,
+                currency: tx.currency,
+                time: tx.time,
+                note: tx.note.as_deref().cloned(),
+            },
+        })?;
+
+        self.offsets.insert(source.to_string(), next);
+
+        Ok(())
+    }
+
+    /// Drops the resume offset of `source`, so a new file at the same path is read from the
+    /// start.
+    pub fn forget(&mut self, source: &str) -> Result<(), EngineError> {
+        self.write(&WalRecord::Forget {
+            source: source.to_string(),
+        })?;
+
+        self.offsets.remove(source);
+
+        Ok(())
+    }
+
+    /// Starts a file of `source` whose rows are kept only if it [commits](Wal::commit).
+    pub fn begin(&mut self, source: &str) -> Result<WalBatch, EngineError> {
+        self.write(&WalRecord::Begin {
+            source: source.to_string(),
+        })?;
+
+        Ok(WalBatch {
+            source: source.to_string(),
+            offset: self.offset(source),
+        })
+    }
+
+    pub fn commit(&mut self, batch: WalBatch) -> Result<(), EngineError> {
+        self.write(&WalRecord::Commit {
+            source: batch.source,
+        })
+    }
+
+    /// Discards the rows logged since `batch` began and restores its source's offset, as if
+    /// they had never been read.
+    pub fn abort(&mut self, batch: WalBatch) -> Result<(), EngineError> {
+        self.write(&WalRecord::Abort {
+            source: batch.source.clone(),
+        })?;
+
+        match batch.offset {
+            Some(offset) => self.offsets.insert(batch.source, offset),
+            None => self.offsets.remove(&batch.source),
+        };
+
+        Ok(())
+    }
+
+    /// Forces every flushed record to disk.
+    pub fn sync(&mut self) -> Result<(), EngineError> {
+        self.writer.get_ref().sync_data().map_err(wal_error)
+    }
+
+    fn write(&mut self, record: &WalRecord) -> Result<(), EngineError> {
+        let mut line =
+            serde_json::to_vec(record).map_err(|e| EngineError::WalError(e.to_string()))?;
+        line.push(b'\n');
+
+        self.writer.write_all(&line).map_err(wal_error)?;
+        self.writer.flush().map_err(wal_error)
+    }
+}
+
+fn wal_error(e: std::io::Error) -> EngineError {
+    EngineError::WalError(e.to_string())
+}
+
+/// A logged row, replayed once its file is known to be kept.
+struct LoggedRow {
+    seq: u64,
+    source: String,
+    line: u64,
+    next: InputOffset,
+    tx: WalTransaction,
+}
+
+/// A record whose file is known to be kept.
+enum Kept {
+    Row(LoggedRow),
+    Forget(String),
+}
+
+/// Where reading the log stopped.
+#[derive(Default)]
+struct LogEnd {
+    /// Length of the valid records, without a torn one a crash left at the end.
+    valid_len: u64,
+
+    /// Sources of the files begun and neither committed nor aborted, because of a crash.
+    open: Vec<String>,
+}
+
+/// Reads the log at `path`, if it exists, handing `keep` in order every row outside a file
+/// that may abort or of one that committed, and every forgotten source.
+fn read_log(
+    path: &Path,
+    mut keep: impl FnMut(Kept) -> Result<(), EngineError>,
+) -> Result<LogEnd, EngineError> {
+    let mut valid_len = 0;
+
+    if !path.exists() {
+        return Ok(LogEnd::default());
+    }
+
+    let mut reader = BufReader::new(File::open(path).map_err(wal_error)?);
+    // map: source -> rows of its file begun and not yet committed
+    let mut batches: HashMap<String, Vec<LoggedRow>> = HashMap::new();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line).map_err(wal_error)?;
+
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+
+        let record: WalRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            // Only the last record can be torn; anything earlier is corruption.
+            Err(_) if reader.fill_buf().map_err(wal_error)?.is_empty() => break,
+            Err(e) => {
+                return Err(EngineError::WalError(format!(
+                    "Corrupt record at byte {valid_len}: {e}"
+                )))
+            }
+        };
+
+        match record {
+            WalRecord::Apply {
+                seq,
+                source,
+                line,
+                next,
+                tx,
+            } => {
+                let row = LoggedRow {
+                    seq,
+                    source,
+                    line,
+                    next,
+                    tx,
+                };
+
+                match batches.get_mut(&row.source) {
+                    Some(batch) => batch.push(row),
+                    None => keep(Kept::Row(row))?,
+                }
+            }
+            WalRecord::Forget { source } => keep(Kept::Forget(source))?,
+            // A file begun again was cut short by a crash, its rows are dropped.
+            WalRecord::Begin { source } => {
+                batches.insert(source, Vec::new());
+            }
+            WalRecord::Commit { source } => {
+                for row in batches.remove(&source).unwrap_or_default() {
+                    keep(Kept::Row(row))?;
+                }
+            }
+            WalRecord::Abort { source } => {
+                batches.remove(&source);
+            }
+        }
+
+        valid_len += read as u64;
+    }
+
+    let mut open: Vec<String> = batches.into_keys().collect();
+    open.sort_unstable();
+
+    Ok(LogEnd { valid_len, open })
+}
+
+/// Applies `row` to the state if it is newer than its last event, and fails if the events
+/// in between are missing.
+fn apply_row(
+    client_map: &mut ShardsMut,
+    event_log: &mut EventLog,
+    disputes: &DisputePolicy,
+    row: LoggedRow,
+) -> Result<(), EngineError> {
+    let LoggedRow {
+        seq,
+        source,
+        line,
+        tx,
+        ..
+    } = row;
+
+    if seq <= event_log.last_seq() {
+        return Ok(());
+    }
+
+    if seq != event_log.last_seq() + 1 {
+        return Err(EngineError::WalError(format!(
+            "Missing records between event {} and {}",
+            event_log.last_seq(),
+            seq
+        )));
+    }
+
+    let transaction = Transaction {
+        tx_id: tx.tx,
+        client_id: tx.client,
+        tx_type: tx.tx_type,
+        amount: tx.amount,
+        currency: tx.currency,
+        dispute: DisputeState::None,
+        disputed_amount: Amount::ZERO,
+        disputed_total: Amount::ZERO,
+        time: tx.time,
+        seq,
+        note: tx.note.map(Box::new),
+    };
+
+    let outcome = match client_map.apply(&transaction, disputes) {
+        Ok(()) => Outcome::Accepted,
+        Err(e) => Outcome::Rejected(e),
+    };
+
+    event_log.append(Arc::from(source.as_str()), line, transaction, outcome);
+
+    Ok(())
+}
+
+/// Opens the write-ahead log at `path`, creating it if needed, and attaches it to `state`.
+///
+/// Records newer than the state's last event are applied to it, so `state` should be fresh
+/// or loaded from a snapshot. The rows of a file that was begun but never committed are
+/// skipped, and the file resumes from where it stood before; an abort record closes it, so
+/// rows logged for its source from now on are not taken for part of it. A torn record left
+/// at the end by a crash is discarded.
+pub fn recover(path: &Path, state: &mut AppState) -> Result<(), EngineError> {
+    let mut offsets = HashMap::new();
+
+    let end = {
+        let disputes = state.config.disputes;
+        let mut client_map = state.client_map.get_mut();
+        let event_log = state.event_log.get_mut();
+
+        read_log(path, |kept| {
+            match kept {
+                Kept::Row(row) => {
+                    offsets.insert(row.source.clone(), row.next);
+                    apply_row(&mut client_map, event_log, &disputes, row)?;
+                }
+                Kept::Forget(source) => {
+                    offsets.remove(&source);
+                }
+            }
+
+            Ok(())
+        })?
+    };
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(wal_error)?;
+
+    // Drop a torn tail so new records start on a clean line.
+    file.set_len(end.valid_len).map_err(wal_error)?;
+
+    let mut writer = BufWriter::new(file);
+    writer.seek(SeekFrom::End(0)).map_err(wal_error)?;
+
+    let mut wal = Wal { writer, offsets };
+    for source in end.open {
+        wal.write(&WalRecord::Abort { source })?;
+    }
+    wal.sync()?;
+
+    *state.wal.get_mut() = Some(wal);
+
+    Ok(())
+}
+
+/// Rebuilds the engine state as it was right after event `until`, by applying the rows of the
+/// write-ahead log at `path` after the last event of `base`: a fresh state, or one loaded from
+/// a snapshot taken no later than `until`.
+///
+/// Unlike [`recover`], the log is only read, so this works on the log of a running engine.
+/// Fails if the log skips any row after `base` up to `until`.
+pub fn replay(path: &Path, mut base: AppState, until: u64) -> Result<AppState, EngineError> {
+    let disputes = base.config.disputes;
+    let mut client_map = base.client_map.get_mut();
+    let event_log = base.event_log.get_mut();
+
+    if event_log.last_seq() > until {
+        return Err(EngineError::WalError(format!(
+            "Cannot replay to event {} from a state after event {}",
+            until,
+            event_log.last_seq()
+        )));
+    }
+
+    read_log(path, |kept| match kept {
+        Kept::Row(row) if row.seq <= until => apply_row(&mut client_map, event_log, &disputes, row),
+        _ => Ok(()),
+    })?;
+
+    drop(client_map);
+
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{EngineConfig, ErrorAction, ErrorPolicy},
+        fixtures::TempDir,
+        processor::{client_summary_csv, process_csv},
+        snapshot,
+    };
+    use std::fs;
+
+    /// Small deterministic generator so failures are reproducible.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    fn write_input(path: &Path, rng: &mut Lcg, first_tx: u32, rows: u32) {
+        let mut csv = String::from("type,client,tx,amount\n");
+        let mut deposits = Vec::new();
+
+        for tx in first_tx..first_tx + rows {
+            let client = rng.next(8) + 1;
+
+            match rng.next(10) {
+                0..=4 => {
+                    csv.push_str(&format!(
+                        "deposit,{client},{tx},{}.{}\n",
+                        rng.next(50),
+                        rng.next(10)
+                    ));
+                    deposits.push((client, tx));
+                }
+                5..=6 => csv.push_str(&format!("withdrawal,{client},{tx},{}.5\n", rng.next(20))),
+                _ if deposits.is_empty() => {}
+                kind => {
+                    let (client, tx) = deposits[rng.next(deposits.len() as u64) as usize];
+                    let tx_type = ["dispute", "resolve", "chargeback"][kind as usize - 7];
+                    csv.push_str(&format!("{tx_type},{client},{tx},\n"));
+                }
+            }
+        }
+
+        fs::write(path, csv).unwrap();
+    }
+
+    async fn final_output(state: &AppState) -> (String, u64) {
+        (
+            client_summary_csv(state).await.unwrap(),
+            state.event_log.read().await.last_seq(),
+        )
+    }
+
+    /// Processes every file in order, returning whether each was committed.
+    async fn process_all(paths: &[String], state: &Arc<AppState>) -> Vec<bool> {
+        let mut committed = Vec::new();
+        for path in paths {
+            committed.push(process_csv(path.clone(), state.clone()).await.is_ok());
+        }
+        committed
+    }
+
+    #[tokio::test]
+    async fn test_recover_after_random_crash() -> Result<(), EngineError> {
+        let dir = TempDir::new("wal_crash");
+        let input = dir.join("input.csv");
+        let aborting = dir.join("aborting.csv");
+        let deposits = dir.join("deposits.csv");
+        let mut rng = Lcg(7);
+
+        write_input(&input, &mut rng, 1, 300);
+
+        // A malformed row halfway aborts the file under the default policy.
+        write_input(&aborting, &mut rng, 2000, 200);
+        let mut lines: Vec<String> = fs::read_to_string(&aborting)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.insert(lines.len() / 2, String::from("deposit,1,5000,abc"));
+        fs::write(&aborting, lines.join("\n") + "\n").unwrap();
+
+        // Only valid rows, so an atomic file of them commits.
+        let rows: String = (1000..1100)
+            .map(|tx| format!("deposit,{},{tx},{}.5\n", rng.next(8) + 1, rng.next(50)))
+            .collect();
+        fs::write(&deposits, format!("type,client,tx,amount\n{rows}")).unwrap();
+
+        let path = |path: &Path| path.to_string_lossy().to_string();
+        // The random file rejects some rows, so it is discarded when atomic.
+        let cases = [
+            (
+                "staged",
+                EngineConfig::default(),
+                vec![path(&input), path(&aborting), path(&deposits)],
+            ),
+            (
+                "atomic",
+                EngineConfig {
+                    atomic: true,
+                    ..EngineConfig::default()
+                },
+                vec![path(&deposits), path(&input), path(&aborting)],
+            ),
+        ];
+
+        for (case, config, paths) in cases {
+            let wal_path = dir.join(format!("{case}.wal"));
+
+            // Reference run without a crash.
+            let mut state = AppState::new(config.clone());
+            recover(&wal_path, &mut state)?;
+            let state = Arc::new(state);
+            let committed = process_all(&paths, &state).await;
+            let expected = final_output(&state).await;
+            let full_wal = fs::read(&wal_path).unwrap();
+
+            assert_eq!(
+                committed,
+                [true, false, case == "staged"],
+                "{case}: every case commits a file and aborts another"
+            );
+
+            for _ in 0..25 {
+                // Dying at any point leaves some prefix of the log on disk, possibly ending in
+                // a partially written record.
+                let cut = rng.next(full_wal.len() as u64 + 1) as usize;
+                fs::write(&wal_path, &full_wal[..cut]).unwrap();
+
+                let mut state = AppState::new(config.clone());
+                recover(&wal_path, &mut state)?;
+                let state = Arc::new(state);
+                assert_eq!(process_all(&paths, &state).await, committed);
+
+                assert_eq!(
+                    final_output(&state).await,
+                    expected,
+                    "{case}: crash at byte {cut}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_across_files_with_snapshot() -> Result<(), EngineError> {
+        let dir = TempDir::new("wal_snapshot");
+        let first = dir.join("first.csv");
+        let second = dir.join("second.csv");
+        let first_path = first.to_string_lossy().to_string();
+        let second_path = second.to_string_lossy().to_string();
+        let wal_path = dir.join("engine.wal");
+        let snapshot_path = dir.join("state.json");
+        let mut rng = Lcg(11);
+
+        write_input(&first, &mut rng, 1, 100);
+        write_input(&second, &mut rng, 1000, 100);
+
+        let mut state = AppState::new(EngineConfig::default());
+        recover(&wal_path, &mut state)?;
+        let state = Arc::new(state);
+        process_csv(first_path.clone(), state.clone()).await?;
+        snapshot::save(&state, &snapshot_path).await?;
+        let wal_after_first = fs::read(&wal_path).unwrap().len();
+        process_csv(second_path.clone(), state.clone()).await?;
+        let expected = final_output(&state).await;
+        let full_wal = fs::read(&wal_path).unwrap();
+
+        for _ in 0..10 {
+            let cut =
+                wal_after_first + rng.next((full_wal.len() - wal_after_first) as u64) as usize;
+            fs::write(&wal_path, &full_wal[..cut]).unwrap();
+
+            // Restart from the snapshot and resubmit both files: the first is already fully
+            // consumed and the second resumes where the log ends.
+            let mut state = snapshot::load(&snapshot_path, EngineConfig::default())?;
+            recover(&wal_path, &mut state)?;
+            let state = Arc::new(state);
+            process_csv(first_path.clone(), state.clone()).await?;
+            process_csv(second_path.clone(), state.clone()).await?;
+
+            assert_eq!(final_output(&state).await, expected, "crash at byte {cut}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_from_snapshot() -> Result<(), EngineError> {
+        let dir = TempDir::new("wal_replay");
+        let first = dir.join("first.csv");
+        let second = dir.join("second.csv");
+        let wal_path = dir.join("engine.wal");
+        let snapshot_path = dir.join("state.json");
+        let mut rng = Lcg(5);
+
+        write_input(&first, &mut rng, 1, 100);
+        write_input(&second, &mut rng, 1000, 100);
+
+        let mut state = AppState::new(EngineConfig::default());
+        recover(&wal_path, &mut state)?;
+        let state = Arc::new(state);
+        process_csv(first.to_string_lossy().into_owned(), state.clone()).await?;
+        snapshot::save(&state, &snapshot_path).await?;
+        process_csv(second.to_string_lossy().into_owned(), state.clone()).await?;
+
+        // Only the file being processed stays in memory with a log attached, so the expected
+        // states are replayed from a run without one.
+        assert!(state.event_log.read().await.is_empty());
+        let unlogged = Arc::new(AppState::new(EngineConfig::default()));
+        process_csv(first.to_string_lossy().into_owned(), unlogged.clone()).await?;
+        process_csv(second.to_string_lossy().into_owned(), unlogged.clone()).await?;
+
+        let snapshot_seq = snapshot::load(&snapshot_path, EngineConfig::default())?
+            .event_log
+            .get_mut()
+            .last_seq();
+        let event_log = unlogged.event_log.read().await;
+        let fresh = || AppState::new(EngineConfig::default());
+
+        // Before the snapshot from the start of the log, after it from the snapshot.
+        for (until, from_snapshot) in [(snapshot_seq - 20, false), (snapshot_seq + 30, true)] {
+            let base = match from_snapshot {
+                true => snapshot::load(&snapshot_path, EngineConfig::default())?,
+                false => fresh(),
+            };
+            let replayed = replay(&wal_path, base, until)?;
+            let expected = event_log.replay(fresh(), until)?;
+
+            assert_eq!(final_output(&replayed).await, final_output(&expected).await);
+            assert_eq!(replayed.event_log.read().await.last_seq(), until);
+        }
+
+        let snapshot = snapshot::load(&snapshot_path, EngineConfig::default())?;
+        assert!(replay(&wal_path, snapshot, snapshot_seq - 1).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_crash_in_begun_file() -> Result<(), EngineError> {
+        let dir = TempDir::new("wal_begun");
+        let input = dir.join("input.csv");
+        let input_path = input.to_string_lossy().to_string();
+        let wal_path = dir.join("engine.wal");
+        let mut rng = Lcg(3);
+
+        write_input(&input, &mut rng, 1, 100);
+
+        // The file may abort, so it is logged after a begin record.
+        let mut state = AppState::new(EngineConfig::default());
+        recover(&wal_path, &mut state)?;
+        process_csv(input_path.clone(), Arc::new(state)).await?;
+
+        // The crash comes right after the begin record.
+        let full_wal = fs::read_to_string(&wal_path).unwrap();
+        let begin = full_wal.find('\n').unwrap() + 1;
+        assert!(full_wal[..begin].contains(r#""kind":"begin""#));
+        fs::write(&wal_path, &full_wal[..begin]).unwrap();
+
+        // Resumed under a policy that can't abort, so its rows are logged outside a batch.
+        let config = EngineConfig {
+            error_policy: ErrorPolicy {
+                malformed: ErrorAction::Skip,
+                rejected: ErrorAction::Skip,
+            },
+            ..EngineConfig::default()
+        };
+        let mut state = AppState::new(config.clone());
+        recover(&wal_path, &mut state)?;
+        let state = Arc::new(state);
+        process_csv(input_path.clone(), state.clone()).await?;
+        let expected = final_output(&state).await;
+        assert_eq!(expected.1, 100);
+
+        // Recovering again keeps the rows applied since.
+        let mut state = AppState::new(config);
+        recover(&wal_path, &mut state)?;
+        let state = Arc::new(state);
+        assert_eq!(final_output(&state).await, expected);
+        process_csv(input_path, state.clone()).await?;
+        assert_eq!(final_output(&state).await, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_record() {
+        let dir = TempDir::new("wal_corrupt");
+        let wal_path = dir.join("engine.wal");
+        fs::write(
+            &wal_path,
+            "not json\n{\"kind\":\"forget\",\"source\":\"a.csv\"}\n",
+        )
+        .unwrap();
+
+        let mut state = AppState::new(EngineConfig::default());
+
+        assert!(matches!(
+            recover(&wal_path, &mut state),
+            Err(EngineError::WalError(_))
+        ));
+    }
+}
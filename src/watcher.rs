@@ -0,0 +1,404 @@
+use notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+use crate::{input::InputFormat, AppState, EngineError, EngineEvent};
+
+pub const PROCESSED_DIR: &str = "processed";
+pub const FAILED_DIR: &str = "failed";
+
+const MARKER_EXTENSION: &str = "done";
+const ERROR_REPORT_EXTENSION: &str = "error.txt";
+
+/// How a producer signals that a file in the drop directory is completely written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyConvention {
+    /// Files are written under a hidden or `.tmp`/`.part` name and renamed into place, so
+    /// every visible input file (`.csv`, `.jsonl` or `.ndjson`, possibly compressed) is
+    /// complete.
+    #[default]
+    Rename,
+    /// A `<name>.csv.done` marker is created once `<name>.csv` is complete.
+    Marker,
+}
+
+impl FromStr for ReadyConvention {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rename" => Ok(ReadyConvention::Rename),
+            "marker" => Ok(ReadyConvention::Marker),
+            _ => Err(EngineError::OtherError(format!(
+                "Unknown ready convention: {s}"
+            ))),
+        }
+    }
+}
+
+/// A directory producers drop transaction files into.
+#[derive(Debug)]
+pub struct DropDirectory {
+    dir: PathBuf,
+    convention: ReadyConvention,
+    // Unbounded, as the watcher may be waiting for the engine that archives the files.
+    archived: mpsc::UnboundedSender<PathBuf>,
+    // Taken by `watch`, which queues a path again once it has been archived.
+    archived_receiver: Mutex<Option<mpsc::UnboundedReceiver<PathBuf>>>,
+}
+
+impl DropDirectory {
+    pub fn new(dir: PathBuf, convention: ReadyConvention) -> Self {
+        let (archived, archived_receiver) = mpsc::unbounded_channel();
+
+        DropDirectory {
+            dir,
+            convention,
+            archived,
+            archived_receiver: Mutex::new(Some(archived_receiver)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    fn marker_path(path: &Path) -> PathBuf {
+        let mut marker = path.as_os_str().to_owned();
+        marker.push(".");
+        marker.push(MARKER_EXTENSION);
+        PathBuf::from(marker)
+    }
+
+    fn is_candidate(path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+
+        !name.starts_with('.') && InputFormat::from_path(path).is_some() && path.is_file()
+    }
+
+    /// Completely written files waiting to be processed, oldest first.
+    pub fn ready_files(&self) -> Result<Vec<PathBuf>, EngineError> {
+        let entries = fs::read_dir(&self.dir).map_err(|e| {
+            EngineError::OtherError(format!("Failed to read {}: {}", self.dir.display(), e))
+        })?;
+
+        let mut ready: Vec<(std::time::SystemTime, PathBuf)> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| Self::is_candidate(path))
+            .filter(|path| match self.convention {
+                ReadyConvention::Rename => true,
+                ReadyConvention::Marker => Self::marker_path(path).exists(),
+            })
+            .map(|path| {
+                let modified = fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or(std::time::UNIX_EPOCH);
+                (modified, path)
+            })
+            .collect();
+
+        ready.sort();
+
+        Ok(ready.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Moves a processed file into `processed/`, or into `failed/` next to a sidecar report
+    /// holding the error, and forgets its write-ahead log offset so a new file with the same
+    /// name is read from the start. The [watcher](watch) queues a file of that name again
+    /// from then on.
+    pub async fn archive(
+        &self,
+        state: &AppState,
+        path: &Path,
+        result: &Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        let archive_error = |e: std::io::Error| {
+            EngineError::OtherError(format!("Failed to archive {}: {}", path.display(), e))
+        };
+
+        let subdir = self.dir.join(match result {
+            Ok(()) => PROCESSED_DIR,
+            Err(_) => FAILED_DIR,
+        });
+        fs::create_dir_all(&subdir).map_err(archive_error)?;
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut target = subdir.join(&*file_name);
+        let mut suffix = 1;
+        while target.exists() {
+            target = subdir.join(format!("{file_name}.{suffix}"));
+            suffix += 1;
+        }
+
+        fs::rename(path, &target).map_err(archive_error)?;
+
+        if let Err(e) = result {
+            let mut report = target.as_os_str().to_owned();
+            report.push(".");
+            report.push(ERROR_REPORT_EXTENSION);
+            fs::write(report, format!("{e}\n")).map_err(archive_error)?;
+        }
+
+        let marker = Self::marker_path(path);
+        if marker.exists() {
+            fs::remove_file(marker).map_err(archive_error)?;
+        }
+
+        if let Some(wal) = state.wal.lock().await.as_mut() {
+            wal.forget(&path.to_string_lossy())?;
+        }
+
+        // Nobody listens unless the directory is watched.
+        let _ = self.archived.send(path.to_path_buf());
+
+        Ok(())
+    }
+}
+
+/// Keeps the underlying file system watcher alive.
+pub enum WatcherHandle {
+    Native(RecommendedWatcher),
+    Poll(PollWatcher),
+}
+
+/// Watches `drop_dir` and queues every ready file on `sender` exactly once, starting with the
+/// files already there. A file dropped under the name of one queued before is queued once that
+/// one is [archived](DropDirectory::archive). A directory is watched once.
+///
+/// Uses the platform's native notifications (inotify on Linux) unless `poll_interval` is
+/// given or they are unavailable, in which case the directory is polled. Notifications only
+/// trigger a rescan, so both paths apply the same readiness rules.
+pub fn watch(
+    drop_dir: Arc<DropDirectory>,
+    sender: mpsc::Sender<EngineEvent>,
+    poll_interval: Option<Duration>,
+) -> Result<WatcherHandle, EngineError> {
+    let mut archived = drop_dir
+        .archived_receiver
+        .lock()
+        .expect("the lock is never poisoned")
+        .take()
+        .ok_or_else(|| {
+            EngineError::OtherError(format!("{} is watched already", drop_dir.path().display()))
+        })?;
+
+    // A full channel already has a scan pending, which covers the new notification.
+    let (scan_sender, mut scan_receiver) = mpsc::channel::<()>(1);
+
+    let notify_sender = scan_sender.clone();
+    let on_event = move |_: notify::Result<notify::Event>| {
+        let _ = notify_sender.try_send(());
+    };
+
+    let native = match poll_interval {
+        Some(_) => None,
+        None => notify::recommended_watcher(on_event.clone())
+            .and_then(|mut watcher| {
+                watcher.watch(drop_dir.path(), RecursiveMode::NonRecursive)?;
+                Ok(watcher)
+            })
+            .map_err(|e| eprintln!("Native file watching unavailable, polling instead: {}", e))
+            .ok(),
+    };
+
+    let handle = match native {
+        Some(watcher) => WatcherHandle::Native(watcher),
+        None => {
+            let config = notify::Config::default()
+                .with_poll_interval(poll_interval.unwrap_or(Duration::from_secs(1)));
+
+            let mut watcher = PollWatcher::new(on_event, config)
+                .map_err(|e| EngineError::OtherError(format!("Failed to watch: {}", e)))?;
+            watcher
+                .watch(drop_dir.path(), RecursiveMode::NonRecursive)
+                .map_err(|e| {
+                    EngineError::OtherError(format!(
+                        "Failed to watch {}: {}",
+                        drop_dir.path().display(),
+                        e
+                    ))
+                })?;
+
+            WatcherHandle::Poll(watcher)
+        }
+    };
+
+    // Pick up files dropped while the engine was down.
+    let _ = scan_sender.try_send(());
+
+    tokio::spawn(async move {
+        // Files queued but not archived yet, so repeated notifications don't queue them twice.
+        let mut queued: HashSet<PathBuf> = HashSet::new();
+
+        loop {
+            tokio::select! {
+                scan = scan_receiver.recv() => {
+                    if scan.is_none() {
+                        return;
+                    }
+                }
+                // Rescans, in case a file of the same name was dropped in the meantime.
+                Some(path) = archived.recv() => {
+                    queued.remove(&path);
+                }
+            }
+
+            let ready = match drop_dir.ready_files() {
+                Ok(ready) => ready,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+
+            for path in ready {
+                if queued.insert(path.clone())
+                    && sender
+                        .send(EngineEvent::ProcessDropped(path))
+                        .await
+                        .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::EngineConfig, fixtures::TempDir, EVENT_QUEUE};
+
+    #[test]
+    fn test_ready_conventions() -> Result<(), EngineError> {
+        let dir = TempDir::new("drop_ready");
+
+        fs::write(dir.join("a.csv"), "").unwrap();
+        fs::write(dir.join(".b.csv"), "").unwrap();
+        fs::write(dir.join("c.csv.part"), "").unwrap();
+        fs::write(dir.join("d.csv"), "").unwrap();
+        fs::write(dir.join("d.csv.done"), "").unwrap();
+        fs::write(dir.join("e.jsonl"), "").unwrap();
+        fs::write(dir.join("f.txt"), "").unwrap();
+
+        let rename = DropDirectory::new(dir.to_path_buf(), ReadyConvention::Rename);
+        let mut ready = rename.ready_files()?;
+        ready.sort();
+        assert_eq!(
+            ready,
+            vec![dir.join("a.csv"), dir.join("d.csv"), dir.join("e.jsonl")]
+        );
+
+        let marker = DropDirectory::new(dir.to_path_buf(), ReadyConvention::Marker);
+        assert_eq!(marker.ready_files()?, vec![dir.join("d.csv")]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive() -> Result<(), EngineError> {
+        let dir = TempDir::new("drop_archive");
+        let drop_dir = DropDirectory::new(dir.to_path_buf(), ReadyConvention::Marker);
+        let state = AppState::new(EngineConfig::default());
+
+        fs::write(dir.join("good.csv"), "").unwrap();
+        fs::write(dir.join("good.csv.done"), "").unwrap();
+        fs::write(dir.join("bad.csv"), "").unwrap();
+
+        drop_dir
+            .archive(&state, &dir.join("good.csv"), &Ok(()))
+            .await?;
+        drop_dir
+            .archive(
+                &state,
+                &dir.join("bad.csv"),
+                &Err(EngineError::CsvFileError("Invalid CSV file".to_string())),
+            )
+            .await?;
+
+        assert!(dir.join(PROCESSED_DIR).join("good.csv").exists());
+        assert!(!dir.join("good.csv.done").exists());
+        assert!(dir.join(FAILED_DIR).join("bad.csv").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join(FAILED_DIR).join("bad.csv.error.txt")).unwrap(),
+            "CSV Error: Invalid CSV file\n"
+        );
+
+        // A second file with the same name does not overwrite the first.
+        fs::write(dir.join("good.csv"), "").unwrap();
+        drop_dir
+            .archive(&state, &dir.join("good.csv"), &Ok(()))
+            .await?;
+        assert!(dir.join(PROCESSED_DIR).join("good.csv.1").exists());
+
+        Ok(())
+    }
+
+    async fn next(receiver: &mut mpsc::Receiver<EngineEvent>) -> Option<EngineEvent> {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn test_watch_queues_each_file_once() -> Result<(), EngineError> {
+        let dir = TempDir::new("drop_watch");
+        fs::write(dir.join("early.csv"), "").unwrap();
+
+        let drop_dir = Arc::new(DropDirectory::new(
+            dir.to_path_buf(),
+            ReadyConvention::Rename,
+        ));
+        let (sender, mut receiver) = mpsc::channel(EVENT_QUEUE);
+        let _handle = watch(drop_dir.clone(), sender, Some(Duration::from_millis(20)))?;
+
+        assert_eq!(
+            next(&mut receiver).await,
+            Some(EngineEvent::ProcessDropped(dir.join("early.csv")))
+        );
+
+        // Written under a temporary name, then renamed into place.
+        fs::write(dir.join("late.csv.part"), "type,client,tx,amount\n").unwrap();
+        fs::rename(dir.join("late.csv.part"), dir.join("late.csv")).unwrap();
+
+        assert_eq!(
+            next(&mut receiver).await,
+            Some(EngineEvent::ProcessDropped(dir.join("late.csv")))
+        );
+
+        // Nothing is queued twice while the files are still waiting to be archived.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+
+        // A file dropped under the same name right after the first is archived is queued too.
+        let state = AppState::new(EngineConfig::default());
+        drop_dir
+            .archive(&state, &dir.join("early.csv"), &Ok(()))
+            .await?;
+        fs::write(dir.join("early.csv"), "").unwrap();
+
+        assert_eq!(
+            next(&mut receiver).await,
+            Some(EngineEvent::ProcessDropped(dir.join("early.csv")))
+        );
+
+        // A directory has a single watcher.
+        let (sender, _receiver) = mpsc::channel(EVENT_QUEUE);
+        assert!(watch(drop_dir, sender, Some(Duration::from_millis(20))).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Drives the HTTP API through its router, without binding a port.
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    Router,
+};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use tower::ServiceExt;
+use tx_engine::{
+    api::{router, ApiError, ClientView, TransactionView},
+    config::EngineConfig,
+    dispute::DisputeState,
+    AppState,
+};
+
+/// Sends a request and returns the status and body of the reply.
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("the router replies");
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("failed to read the body");
+
+    (
+        status,
+        String::from_utf8(body.to_vec()).expect("bodies are text"),
+    )
+}
+
+async fn submit<T: DeserializeOwned>(app: &Router, body: &str) -> (StatusCode, T) {
+    let request = Request::post("/transactions")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let (status, body) = send(app, request).await;
+
+    (
+        status,
+        serde_json::from_str(&body).expect("replies are JSON"),
+    )
+}
+
+async fn query(app: &Router, uri: &str) -> (StatusCode, String) {
+    send(app, Request::get(uri).body(Body::empty()).unwrap()).await
+}
+
+#[tokio::test]
+async fn test_submit_and_query() {
+    let state = Arc::new(AppState::new(EngineConfig::default()));
+    let app = router(state);
+
+    let (status, client): (_, ClientView) = submit(
+        &app,
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "5.0"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(client.client, 1);
+    assert_eq!(client.balances[0].available.to_string(), "5.0000");
+
+    let (status, client): (_, ClientView) =
+        submit(&app, r#"{"type": "dispute", "client": "1", "tx": "1"}"#).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(client.balances[0].held.to_string(), "5.0000");
+
+    // Errors are mapped to a status, with the engine's code.
+    for (body, expected, code) in [
+        (
+            r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "1.0"}"#,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "insufficient_funds",
+        ),
+        (
+            r#"{"type": "deposit", "client": 2, "tx": 1, "amount": "1.0"}"#,
+            StatusCode::CONFLICT,
+            "duplicate_transaction",
+        ),
+        (
+            r#"{"type": "deposit", "client": 2, "tx": 3, "amount": "abc"}"#,
+            StatusCode::BAD_REQUEST,
+            "invalid_amount",
+        ),
+        (
+            r#"["deposit", 2, 3, "1.0"]"#,
+            StatusCode::BAD_REQUEST,
+            "invalid_transaction",
+        ),
+    ] {
+        let (status, error): (_, ApiError) = submit(&app, body).await;
+        assert_eq!((status, error.code.as_str()), (expected, code), "{body}");
+    }
+
+    let (status, body) = query(&app, "/clients/1").await;
+    assert_eq!(status, StatusCode::OK);
+    let client: ClientView = serde_json::from_str(&body).unwrap();
+    assert_eq!(client.balances[0].total.to_string(), "5.0000");
+
+    let (status, body) = query(&app, "/clients/1/transactions").await;
+    assert_eq!(status, StatusCode::OK);
+    let transactions: Vec<TransactionView> = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        transactions
+            .iter()
+            .map(|tx| (tx.tx, tx.dispute))
+            .collect::<Vec<_>>(),
+        [(1, DisputeState::Open)]
+    );
+
+    let (status, body) = query(&app, "/clients/7").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        serde_json::from_str::<ApiError>(&body).unwrap().code,
+        "not_found"
+    );
+
+    let (_, body) = query(&app, "/clients").await;
+    assert_eq!(
+        body,
+        "client, available, held, total, locked\n1, 0.0000, 5.0000, 5.0000, false\n"
+    );
+
+    let (_, body) = query(&app, "/clients?format=json").await;
+    let clients: Vec<ClientView> = serde_json::from_str(&body).unwrap();
+    assert_eq!(clients, [client]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_submits_reply_with_their_own_result() {
+    let state = Arc::new(AppState::new(EngineConfig::default()));
+    let app = router(state);
+
+    let requests = (1..=50).map(|tx| {
+        let app = app.clone();
+        tokio::spawn(async move {
+            let body =
+                format!(r#"{{"type": "deposit", "client": 1, "tx": {tx}, "amount": "1.0"}}"#);
+            let (status, client): (_, ClientView) = submit(&app, &body).await;
+            assert_eq!(status, StatusCode::OK);
+            client.balances[0].available.to_string()
+        })
+    });
+
+    // Each reply is the balance right after its own deposit, so no two are the same.
+    let mut balances = Vec::new();
+    for request in requests.collect::<Vec<_>>() {
+        balances.push(request.await.unwrap());
+    }
+    balances.sort_by_key(|balance| balance.parse::<f64>().unwrap() as u32);
+
+    let expected: Vec<String> = (1..=50).map(|n| format!("{n}.0000")).collect();
+    assert_eq!(balances, expected);
+}
@@ -0,0 +1,140 @@
+//! Runs the engine binary as a daemon and drives it with signals.
+//!
+//! Its first file is a named pipe, so the test decides when that file is in flight and when
+//! it is done.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+};
+use tx_engine::{config::EngineConfig, processor::client_summary_csv, snapshot};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tx_engine_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn mkfifo(path: &Path) {
+    let status = Command::new("mkfifo").arg(path).status().unwrap();
+    assert!(status.success(), "mkfifo failed");
+}
+
+fn send(child: &Child, signal: &str) {
+    let status = Command::new("kill")
+        .args([signal, &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success(), "kill {signal} failed");
+}
+
+fn spawn(args: &[&Path]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_tx_engine"))
+        .arg("--daemon")
+        .arg("--summary-on-demand")
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+/// Reads a summary off `stdout`: its header and rows, up to the blank line closing it.
+fn read_summary(stdout: &mut BufReader<ChildStdout>) -> String {
+    let mut summary = String::new();
+
+    loop {
+        let mut line = String::new();
+        assert!(
+            stdout.read_line(&mut line).unwrap() > 0,
+            "stdout closed before the summary ended"
+        );
+
+        if line.trim().is_empty() {
+            return summary;
+        }
+
+        summary.push_str(&line);
+    }
+}
+
+#[tokio::test]
+async fn test_sigterm_drains_queued_files() {
+    let dir = temp_dir("daemon_sigterm");
+    let first = dir.join("first.csv");
+    let second = dir.join("second.csv");
+    let snapshot = dir.join("snapshot.json");
+    mkfifo(&first);
+    std::fs::write(&second, "type,client,tx,amount\ndeposit,2,3,4.0\n").unwrap();
+
+    let child = spawn(&[
+        Path::new("--snapshot"),
+        &snapshot,
+        first.as_path(),
+        second.as_path(),
+    ]);
+
+    // Opening the pipe waits for the engine to open the first file.
+    let mut pipe = File::create(&first).unwrap();
+    pipe.write_all(b"type,client,tx,amount\ndeposit,1,1,5.0\n")
+        .unwrap();
+
+    send(&child, "-TERM");
+    // The signal arrives with the first file still open.
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    pipe.write_all(b"withdrawal,1,2,1.5\n").unwrap();
+    drop(pipe);
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Shutting down after the queued files"));
+
+    // Both files are applied, the one in flight and the one queued behind it.
+    let expected = "client, available, held, total, locked\n\
+                    1, 3.5000, 0.0000, 3.5000, false\n\
+                    2, 4.0000, 0.0000, 4.0000, false\n";
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+        expected.trim_end()
+    );
+
+    let state = std::sync::Arc::new(snapshot::load(&snapshot, EngineConfig::default()).unwrap());
+    assert_eq!(client_summary_csv(&state).await.unwrap(), expected);
+}
+
+#[test]
+fn test_sigusr1_writes_summary() {
+    let dir = temp_dir("daemon_sigusr1");
+    let input = dir.join("input.csv");
+    mkfifo(&input);
+
+    let mut child = spawn(&[input.as_path()]);
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    let mut pipe = File::create(&input).unwrap();
+    pipe.write_all(b"type,client,tx,amount\ndeposit,1,1,5.0\n")
+        .unwrap();
+    drop(pipe);
+
+    // Queued behind the file, so the summary has its rows.
+    send(&child, "-USR1");
+    assert_eq!(
+        read_summary(&mut stdout),
+        "client, available, held, total, locked\n\
+         1, 5.0000, 0.0000, 5.0000, false\n"
+    );
+
+    // Nothing changed since, so there is no final summary.
+    send(&child, "-TERM");
+    let status = child.wait().unwrap();
+    assert!(status.success());
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "");
+}
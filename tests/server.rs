@@ -0,0 +1,127 @@
+//! Drives the ingestion server with local producers over TCP and a Unix domain socket.
+
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, UnixStream},
+};
+use tx_engine::{
+    config::EngineConfig,
+    processor::client_summary_csv,
+    server::{Ack, AckStatus, ListenAddress, Server},
+    AppState,
+};
+
+/// Sends `lines`, hangs up its side and reads every ack until the server hangs up too.
+async fn produce(mut stream: impl AsyncRead + AsyncWrite + Unpin, lines: &[String]) -> Vec<Ack> {
+    stream
+        .write_all(lines.join("\n").as_bytes())
+        .await
+        .expect("failed to send lines");
+    stream.shutdown().await.expect("failed to hang up");
+
+    let mut acks = Vec::new();
+    let mut replies = BufReader::new(stream).lines();
+    while let Some(reply) = replies.next_line().await.expect("failed to read acks") {
+        acks.push(serde_json::from_str(&reply).expect("acks are JSON"));
+    }
+
+    acks
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_concurrent_producers() {
+    let dir = std::env::temp_dir().join(format!("tx_engine_server_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let state = Arc::new(AppState::new(EngineConfig::default()));
+    let server = Server::start(state.clone());
+    let tcp = server
+        .listen(&"127.0.0.1:0".parse().unwrap())
+        .await
+        .expect("failed to listen on TCP");
+    let socket = dir.join("engine.sock");
+    server
+        .listen(&ListenAddress::Unix(socket.clone()))
+        .await
+        .expect("failed to listen on the socket");
+    let ListenAddress::Tcp(tcp) = tcp else {
+        panic!("a TCP address is still TCP");
+    };
+
+    // Every producer deposits to its own client, a row at a time, in CSV and JSON lines.
+    let producers = 8u32;
+    let rows = 500u32;
+    let lines = |producer: u32| -> Vec<String> {
+        (0..rows)
+            .map(|row| {
+                let tx = producer * rows + row + 1;
+                match row % 2 {
+                    0 => format!("deposit,{producer},{tx},1.0"),
+                    _ => format!(
+                        r#"{{"type": "deposit", "client": {producer}, "tx": {tx}, "amount": "1.0"}}"#
+                    ),
+                }
+            })
+            .collect()
+    };
+
+    let handles: Vec<_> = (0..producers)
+        .map(|producer| {
+            let (tcp, socket, lines) = (tcp.clone(), socket.clone(), lines(producer));
+            tokio::spawn(async move {
+                match producer % 2 {
+                    0 => produce(TcpStream::connect(tcp).await.unwrap(), &lines).await,
+                    _ => produce(UnixStream::connect(socket).await.unwrap(), &lines).await,
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        let acks = handle.await.unwrap();
+        assert_eq!(
+            acks.iter().map(|ack| ack.line).collect::<Vec<_>>(),
+            (1..=rows as u64).collect::<Vec<_>>()
+        );
+        assert!(acks.iter().all(|ack| ack.status == AckStatus::Accepted));
+    }
+
+    // Failed lines are acked with why, and a blank line is skipped.
+    let acks = produce(
+        TcpStream::connect(&tcp).await.unwrap(),
+        &[
+            "withdrawal,0,100000,1000.0".to_string(),
+            String::new(),
+            "deposit,0,100001,abc".to_string(),
+            "{\"type\":".to_string(),
+            "deposit,0,100002,2.0".to_string(),
+        ],
+    )
+    .await;
+    assert_eq!(
+        acks.iter()
+            .map(|ack| (ack.line, ack.status, ack.code.as_deref()))
+            .collect::<Vec<_>>(),
+        [
+            (1, AckStatus::Rejected, Some("insufficient_funds")),
+            (3, AckStatus::Malformed, Some("invalid_amount")),
+            (4, AckStatus::Malformed, Some("invalid_transaction")),
+            (5, AckStatus::Accepted, None),
+        ]
+    );
+    assert!(acks[0].seq.is_some() && acks[0].seq < acks[3].seq);
+
+    let summary = client_summary_csv(&state).await.unwrap();
+    let mut expected = String::from("client, available, held, total, locked\n");
+    for producer in 0..producers {
+        let total = rows as f64 + if producer == 0 { 2.0 } else { 0.0 };
+        expected.push_str(&format!(
+            "{producer}, {total:.4}, 0.0000, {total:.4}, false\n"
+        ));
+    }
+    assert_eq!(summary, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}